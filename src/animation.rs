@@ -0,0 +1,123 @@
+use std::ops::{Add, Mul, Sub};
+
+use serde::{Deserialize, Serialize};
+
+use crate::vec3::Point;
+
+/// The frames to render, from `start` up to and including `end`
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy)]
+pub struct FrameRange {
+    pub start: i32,
+    pub end: i32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Clone, Copy)]
+pub enum Interpolation {
+    /// Straight lines between keyframes, changing direction at each one
+    #[default]
+    Linear,
+    /// A smooth curve through the keyframes
+    CatmullRom,
+}
+
+/// The camera settings at a frame. Settings left out are interpolated from the keyframes that
+/// have them, and settings no keyframe has keep their value from the scene.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy)]
+pub struct CameraKeyframe {
+    pub frame: f32,
+    pub look_from: Option<Point>,
+    pub look_to: Option<Point>,
+    pub viewport_fov: Option<f32>,
+    pub focal_length: Option<f32>,
+    pub aperture: Option<f32>,
+}
+
+/// Interpolate the keys, given as (frame, value) sorted by frame, at the frame. Before the
+/// first and after the last key the value holds still.
+pub fn interpolate<T>(keys: &[(f32, T)], frame: f32, interpolation: Interpolation) -> Option<T>
+where
+    T: Copy + Add<Output = T> + Sub<Output = T> + Mul<f32, Output = T>,
+{
+    let last = keys.len().checked_sub(1)?;
+    let next = keys.partition_point(|&(key_frame, _)| key_frame <= frame);
+    if next == 0 {
+        return Some(keys[0].1);
+    }
+    if next > last {
+        return Some(keys[last].1);
+    }
+
+    let (i, j) = (next - 1, next);
+    let ((f0, p0), (f1, p1)) = (keys[i], keys[j]);
+    let span = f1 - f0;
+    let t = (frame - f0) / span;
+    match interpolation {
+        Interpolation::Linear => Some(p0 + (p1 - p0) * t),
+        Interpolation::CatmullRom => {
+            // A cubic Hermite curve, with tangents from the neighbouring keys so unevenly
+            // spaced keys still give a smooth speed
+            let tangent = |k: usize| {
+                let (before, after) = (k.saturating_sub(1), (k + 1).min(last));
+                let (fa, pa) = keys[before];
+                let (fb, pb) = keys[after];
+                (pb - pa) * (span / (fb - fa))
+            };
+            let (m0, m1) = (tangent(i), tangent(j));
+
+            let (t2, t3) = (t * t, t * t * t);
+            Some(
+                p0 * (2. * t3 - 3. * t2 + 1.)
+                    + m0 * (t3 - 2. * t2 + t)
+                    + p1 * (-2. * t3 + 3. * t2)
+                    + m1 * (t3 - t2),
+            )
+        }
+    }
+}
+
+/// Fill in the frame number for `%d` or a zero padded `%04d` in the pattern
+pub fn frame_path(pattern: &str, frame: i32) -> Option<String> {
+    let start = pattern.find('%')?;
+    let rest = &pattern[start + 1..];
+    let end = rest.find('d')?;
+    let width = match &rest[..end] {
+        "" => 0,
+        digits if digits.starts_with('0') => digits.parse().ok()?,
+        _ => return None,
+    };
+    Some(format!(
+        "{}{:0width$}{}",
+        &pattern[..start],
+        frame,
+        &rest[end + 1..],
+        width = width
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_interpolate() {
+        let keys = [(0., 0.), (10., 10.), (20., 0.)];
+        assert_eq!(interpolate(&keys, -5., Interpolation::Linear), Some(0.));
+        assert_eq!(interpolate(&keys, 5., Interpolation::Linear), Some(5.));
+        assert_eq!(interpolate(&keys, 15., Interpolation::Linear), Some(5.));
+        assert_eq!(interpolate(&keys, 25., Interpolation::Linear), Some(0.));
+
+        // Passes through the keys, but overshoots the straight line towards the peak
+        let smooth = |frame| interpolate(&keys, frame, Interpolation::CatmullRom).unwrap();
+        assert!((smooth(10.) - 10.).abs() < 1e-5);
+        assert!(smooth(8.) > 8.);
+        assert_eq!(interpolate::<f32>(&[], 1., Interpolation::Linear), None);
+    }
+
+    #[test]
+    fn test_frame_path() {
+        assert_eq!(frame_path("out_%04d.png", 7).unwrap(), "out_0007.png");
+        assert_eq!(frame_path("%d.ppm", 12).unwrap(), "12.ppm");
+        assert_eq!(frame_path("out.png", 1), None);
+        assert_eq!(frame_path("out_%4d.png", 1), None);
+    }
+}
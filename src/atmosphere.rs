@@ -0,0 +1,265 @@
+use serde::{Deserialize, Serialize};
+
+use crate::material::HenyeyGreenstein;
+use crate::primitive::random;
+use crate::ray::Ray;
+use crate::validate::{join, Problems, Validate};
+use crate::vec3::Color;
+
+/// A medium filling the whole scene that every ray travels through, for aerial perspective
+/// and fog. The coefficients are the chance per unit of distance of light being scattered or
+/// absorbed, per color channel, at the base height.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct Atmosphere {
+    pub scattering: Color,
+    #[serde(default = "default_absorption")]
+    pub absorption: Color,
+    /// Thin the atmosphere out with height, by a factor of e every 1 / falloff units
+    /// above the base height, for fog that settles low
+    pub height_falloff: Option<f32>,
+    #[serde(default)]
+    pub base_height: f32,
+    /// The anisotropy of scattering, from -1 for backward to 1 for forward
+    #[serde(default)]
+    pub g: f32,
+    /// How far rays travel through the atmosphere before they leave it for the background
+    #[serde(default = "default_max_distance")]
+    pub max_distance: f32,
+}
+
+fn default_absorption() -> Color {
+    Color::new(0., 0., 0.)
+}
+
+fn default_max_distance() -> f32 {
+    1000.
+}
+
+/// What happens to a ray on its way through the atmosphere to the next hit
+pub enum Interaction {
+    /// It scattered before reaching the hit, continuing as the new ray
+    Scatter { ray: Ray, weight: Color },
+    /// It reached the hit, or the background
+    Pass { weight: Color },
+}
+
+impl Atmosphere {
+    fn extinction(&self) -> Color {
+        self.scattering + self.absorption
+    }
+
+    /// The extinction averaged over the color channels, which distances are sampled with
+    fn mean_extinction(&self) -> f32 {
+        let extinction = self.extinction();
+        (extinction.x() + extinction.y() + extinction.z()) / 3.
+    }
+
+    /// The density relative to the base height, integrated along a unit direction going
+    /// `rise` upwards per unit of distance, from the height over the distance
+    fn optical_depth(&self, height: f32, rise: f32, distance: f32) -> f32 {
+        let falloff = match self.height_falloff {
+            Some(falloff) => falloff,
+            None => return distance,
+        };
+        let density = (-falloff * (height - self.base_height)).exp();
+        let rate = falloff * rise;
+        if rate.abs() < 1e-6 {
+            density * distance
+        } else {
+            density * (1. - (-rate * distance).exp()) / rate
+        }
+    }
+
+    /// The distance at which the optical depth reaches the depth, or None if it never does
+    /// because the atmosphere thins out too quickly upwards
+    fn distance_to_depth(&self, height: f32, rise: f32, depth: f32) -> Option<f32> {
+        let falloff = match self.height_falloff {
+            Some(falloff) => falloff,
+            None => return Some(depth),
+        };
+        let density = (-falloff * (height - self.base_height)).exp();
+        let rate = falloff * rise;
+        if rate.abs() < 1e-6 {
+            return Some(depth / density);
+        }
+        let remaining = 1. - depth * rate / density;
+        (remaining > 0.).then(|| -remaining.ln() / rate)
+    }
+
+    /// The fraction of each color channel left after traveling along the ray up to `t_end`,
+    /// which may be infinite
+    pub fn transmittance(&self, ray: Ray, t_end: f32) -> Color {
+        let speed = ray.direction().length();
+        let rise = ray.direction().y() / speed;
+        let distance = (t_end * speed).min(self.max_distance);
+        let depth = self.optical_depth(ray.origin().y(), rise, distance);
+        let extinction = self.extinction();
+        let channel = |extinction: f32| match extinction * depth {
+            // Avoid zero times infinity for clear channels
+            optical if extinction == 0. || optical.is_nan() => 1.,
+            optical => (-optical).exp(),
+        };
+        Color::new(
+            channel(extinction.x()),
+            channel(extinction.y()),
+            channel(extinction.z()),
+        )
+    }
+
+    /// Sample whether the ray scatters in the atmosphere before `t_end`, where it hits
+    /// something or infinity for the background, and before it leaves the atmosphere.
+    /// Distances are sampled with the mean extinction, and the weight corrects for each
+    /// channel's own extinction.
+    pub fn interact(&self, ray: Ray, t_end: f32) -> Interaction {
+        let mean = self.mean_extinction();
+        if mean <= 0. {
+            return Interaction::Pass {
+                weight: Color::new(1., 1., 1.),
+            };
+        }
+        let speed = ray.direction().length();
+        let direction = ray.direction() / speed;
+        let (height, rise) = (ray.origin().y(), direction.y());
+        let end = (t_end * speed).min(self.max_distance);
+
+        let extinction = self.extinction();
+        let reweight = |depth: f32| {
+            let channel = |extinction: f32| (-(extinction - mean) * depth).exp();
+            Color::new(
+                channel(extinction.x()),
+                channel(extinction.y()),
+                channel(extinction.z()),
+            )
+        };
+
+        let depth = -(1. - random::gen::<f32>()).ln() / mean;
+        match self.distance_to_depth(height, rise, depth) {
+            Some(distance) if distance < end => {
+                let phase = HenyeyGreenstein {
+                    albedo: Color::new(1., 1., 1.),
+                    g: self.g,
+                };
+                let point = ray.at(distance / speed);
+                let scattered = Ray::new(point, phase.scatter_direction(direction));
+                Interaction::Scatter {
+                    ray: scattered.with_time(ray.time()),
+                    weight: self.scattering * reweight(depth) / mean,
+                }
+            }
+            _ => {
+                let depth = self.optical_depth(height, rise, end);
+                Interaction::Pass {
+                    weight: reweight(depth),
+                }
+            }
+        }
+    }
+}
+
+impl Validate for Atmosphere {
+    fn validate(&self, path: &str, problems: &mut Problems) {
+        let negative = |color: Color| color.x() < 0. || color.y() < 0. || color.z() < 0.;
+        if negative(self.scattering) {
+            problems.error(
+                &join(path, "scattering"),
+                "The coefficients must not be negative",
+            );
+        }
+        if negative(self.absorption) {
+            problems.error(
+                &join(path, "absorption"),
+                "The coefficients must not be negative",
+            );
+        }
+        if self.height_falloff.is_some_and(|falloff| falloff <= 0.) {
+            problems.error(
+                &join(path, "height_falloff"),
+                "The falloff must be positive",
+            );
+        }
+        if self.g <= -1. || self.g >= 1. {
+            problems.error(&join(path, "g"), "g must be strictly between -1 and 1");
+        }
+        if self.max_distance.is_nan() || self.max_distance <= 0. {
+            problems.error(&join(path, "max_distance"), "The distance must be positive");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vec3::Vec3;
+
+    fn fog(height_falloff: Option<f32>) -> Atmosphere {
+        Atmosphere {
+            scattering: Color::new(0.1, 0.2, 0.3),
+            absorption: Color::new(0.1, 0., 0.),
+            height_falloff,
+            base_height: 0.,
+            g: 0.,
+            max_distance: default_max_distance(),
+        }
+    }
+
+    #[test]
+    fn test_transmittance_per_channel() {
+        random::seed(11);
+        let atmosphere = fog(None);
+        let ray = Ray::new(Vec3::new(0., 0., 0.), Vec3::new(0., 0., -2.));
+        // 2 units to the hit at t = 1, through extinctions of 0.2, 0.2 and 0.3
+        let samples = 20000;
+        let mut total = Color::new(0., 0., 0.);
+        for _ in 0..samples {
+            if let Interaction::Pass { weight } = atmosphere.interact(ray, 1.) {
+                total += weight;
+            }
+        }
+        let average = total / samples as f32;
+        let expected = [(-0.4f32).exp(), (-0.4f32).exp(), (-0.6f32).exp()];
+        for (channel, expected) in [average.x(), average.y(), average.z()].iter().zip(expected) {
+            assert!(
+                (channel - expected).abs() < 0.02,
+                "{} != {}",
+                channel,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn test_rays_leave_at_max_distance() {
+        random::seed(12);
+        let atmosphere = Atmosphere {
+            max_distance: 5.,
+            ..fog(None)
+        };
+        // Towards the background, through 5 units of extinctions 0.2, 0.2 and 0.3
+        let ray = Ray::new(Vec3::new(0., 0., 0.), Vec3::new(0., 1., 0.));
+        let expected = Color::new((-1f32).exp(), (-1f32).exp(), (-1.5f32).exp());
+        let transmittance = atmosphere.transmittance(ray, f32::INFINITY);
+        assert!((transmittance - expected).length() < 1e-5);
+        let passed = (0..1000)
+            .filter(|_| {
+                matches!(
+                    atmosphere.interact(ray, f32::INFINITY),
+                    Interaction::Pass { .. }
+                )
+            })
+            .count();
+        let chance = (-5. * atmosphere.mean_extinction()).exp();
+        assert!((passed as f32 / 1000. - chance).abs() < 0.05);
+    }
+
+    #[test]
+    fn test_height_falloff() {
+        let atmosphere = fog(Some(0.5));
+        // Upwards, the optical depth to infinity is finite
+        let to_space = atmosphere.optical_depth(0., 1., f32::INFINITY);
+        assert!((to_space - 2.).abs() < 1e-5);
+        assert!(atmosphere.distance_to_depth(0., 1., 2.5).is_none());
+
+        let distance = atmosphere.distance_to_depth(1., -0.5, 0.7).unwrap();
+        assert!((atmosphere.optical_depth(1., -0.5, distance) - 0.7).abs() < 1e-4);
+    }
+}
@@ -1,7 +1,633 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::animation::{self, CameraKeyframe, Interpolation};
+use crate::image::Image;
+use crate::primitive::random;
 use crate::primitive::ray::Ray;
 use crate::primitive::vec3::{Point, Vec3};
+use crate::validate::{join, Problems, Validate};
+
+/// An alternative to `look_to` for orienting the camera
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy)]
+pub enum Orientation {
+    /// Angles in degrees in the world frame, where y is up. With all angles at zero the camera
+    /// looks down -z, positive yaw turns it left and positive pitch tilts it up. The roll is
+    /// applied on top of the camera's `roll`.
+    YawPitchRoll {
+        yaw: f32,
+        pitch: f32,
+        #[serde(default)]
+        roll: f32,
+    },
+    /// The rotation part of a look-at (view) matrix, whose rows are the camera's right, up and
+    /// backward axes in world space. The rows are re-orthonormalized.
+    LookAtMatrix([[f32; 3]; 3]),
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Clone, Copy)]
+pub enum FisheyeMapping {
+    /// The distance from the image center is proportional to the angle from the view direction
+    #[default]
+    Equidistant,
+    /// The distance from the image center preserves solid angles, like most real fisheye lenses
+    Equisolid,
+}
+
+/// How the camera maps image coordinates to rays
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Clone, Copy)]
+pub enum Projection {
+    /// A thin lens camera with the scene's field of view and aperture
+    #[default]
+    Perspective,
+    /// Parallel rays through a viewport `height` scene units tall
+    Orthographic { height: f32 },
+    /// An image circle covering `fov` degrees, inscribed in the shorter side of the image. The
+    /// image is black outside of it.
+    Fisheye {
+        #[serde(default = "default_fisheye_fov")]
+        fov: f32,
+        #[serde(default)]
+        mapping: FisheyeMapping,
+    },
+    /// A full 360x180 degree panorama, with the view direction in the center of the image
+    Equirectangular,
+}
+
+fn default_fisheye_fov() -> f32 {
+    180.
+}
+
+/// The number of tries to find a point on the aperture that the lens barrel does not block
+const MAX_LENS_SAMPLES: usize = 64;
+
+/// A real lens, whose settings replace `viewport_fov` and `aperture`
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy)]
+pub struct Lens {
+    /// The focal length in millimeters
+    pub focal_length: f32,
+    /// The focal length divided by the diameter of the aperture
+    pub f_stop: f32,
+    /// The height of the sensor in millimeters, 24 for full frame
+    #[serde(default = "default_sensor_height")]
+    pub sensor_height: f32,
+    /// How many scene units make up a meter
+    #[serde(default = "default_units_per_meter")]
+    pub units_per_meter: f32,
+}
+
+fn default_sensor_height() -> f32 {
+    24.
+}
+
+fn default_units_per_meter() -> f32 {
+    1.
+}
+
+/// The shape of the aperture, which is the shape of out of focus highlights
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Clone)]
+pub enum ApertureShape {
+    #[default]
+    Circle,
+    /// A regular polygon formed by the aperture blades, rotated counterclockwise in degrees
+    Polygon {
+        blades: u32,
+        #[serde(default)]
+        rotation: f32,
+    },
+    /// An image whose brightness is how much light passes through each part of the aperture.
+    /// The path is relative to the scene file.
+    Mask {
+        path: PathBuf,
+        #[serde(skip)]
+        mask: Option<Arc<ApertureMask>>,
+    },
+}
+
+impl ApertureShape {
+    /// A random point on the aperture, which fits in the square from (-1, -1) to (1, 1)
+    fn sample(&self) -> (f32, f32) {
+        match self {
+            ApertureShape::Circle => {
+                let point = Vec3::new_random_in_unit_disk();
+                (point.x(), point.y())
+            }
+            ApertureShape::Polygon { blades, rotation } => {
+                // Pick one of the triangles between the center and two neighbouring corners
+                let blade = random::gen_range(0..*blades) as f32;
+                let step = 2. * std::f32::consts::PI / *blades as f32;
+                let angle = rotation.to_radians() + blade * step;
+                let (a, b) = (angle.sin_cos(), (angle + step).sin_cos());
+
+                let (mut s, mut t) = (random::gen::<f32>(), random::gen::<f32>());
+                if s + t > 1. {
+                    s = 1. - s;
+                    t = 1. - t;
+                }
+                (s * a.1 + t * b.1, s * a.0 + t * b.0)
+            }
+            ApertureShape::Mask { path, mask } => mask
+                .as_ref()
+                .unwrap_or_else(|| {
+                    panic!(
+                        "Aperture mask {} is loaded when the scene is prepared",
+                        path.display()
+                    )
+                })
+                .sample(),
+        }
+    }
+}
+
+/// An aperture mask, sampled in proportion to how much light each pixel lets through
+#[derive(Debug, PartialEq)]
+pub struct ApertureMask {
+    width: usize,
+    height: usize,
+    /// The running total of the brightness of the pixels, row by row from the top left
+    cumulative: Vec<f32>,
+}
+
+impl ApertureMask {
+    pub fn new(image: &Image) -> Result<Self> {
+        let mut total = 0.;
+        let cumulative = image
+            .pixels()
+            .iter()
+            .map(|pixel| {
+                total += (pixel.x() + pixel.y() + pixel.z()).max(0.) / 3.;
+                total
+            })
+            .collect();
+        if total <= 0. {
+            return Err(anyhow!("The aperture mask is completely black"));
+        }
+        Ok(Self {
+            width: image.width() as usize,
+            height: image.height() as usize,
+            cumulative,
+        })
+    }
+
+    fn sample(&self) -> (f32, f32) {
+        let total = self.cumulative[self.cumulative.len() - 1];
+        let target = random::gen::<f32>() * total;
+        let index = self
+            .cumulative
+            .partition_point(|&sum| sum <= target)
+            .min(self.cumulative.len() - 1);
+        let x = (index % self.width) as f32 + random::gen::<f32>();
+        let y = (index / self.width) as f32 + random::gen::<f32>();
+        // Stretch the mask over the square, with the top row at the top
+        (
+            2. * x / self.width as f32 - 1.,
+            1. - 2. * y / self.height as f32,
+        )
+    }
+}
+
+/// What the camera focuses on, replacing `focal_length`
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub enum FocusTarget {
+    /// Whatever is seen through the pixel, counted from the top left of the image
+    Pixel { x: i32, y: i32 },
+    /// The surface of the named object facing the camera
+    Object(String),
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy)]
+pub enum Eye {
+    Left,
+    Right,
+}
+
+impl Eye {
+    /// Which way along the camera's right axis the eye is offset
+    fn sign(self) -> f32 {
+        match self {
+            Eye::Left => -1.,
+            Eye::Right => 1.,
+        }
+    }
+}
+
+/// How the images of both eyes are saved
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Clone, Copy)]
+pub enum StereoLayout {
+    /// Two files, suffixed with `_left` and `_right`
+    #[default]
+    Separate,
+    /// One file with the left eye above the right eye
+    TopBottom,
+    /// One file with the left eye left of the right eye
+    SideBySide,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy)]
+pub struct StereoConfig {
+    /// Distance between the eyes in scene units
+    #[serde(default = "default_interocular_distance")]
+    pub interocular_distance: f32,
+    /// Distance at which both eyes see the same image. Perspective cameras default to the
+    /// focus distance, panoramas default to parallel eyes.
+    pub convergence_distance: Option<f32>,
+    #[serde(default)]
+    pub layout: StereoLayout,
+}
+
+fn default_interocular_distance() -> f32 {
+    0.064
+}
+
+/// The camera settings of a scene
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct CameraConfig {
+    #[serde(default = "default_viewport_fov")]
+    pub viewport_fov: f32,
+    #[serde(default = "default_aperture")]
+    pub aperture: f32,
+    pub focal_length: Option<f32>,
+    /// Set `focal_length` by tracing a ray when the scene is loaded
+    pub focus_on: Option<FocusTarget>,
+    pub look_from: Point,
+    /// The point the camera looks at, unless an orientation is given
+    pub look_to: Option<Point>,
+    /// The direction that is up in the image, before rolling
+    #[serde(default = "default_vup")]
+    pub vup: Vec3,
+    /// Counterclockwise rotation of the camera around its view direction, in degrees
+    #[serde(default)]
+    pub roll: f32,
+    pub orientation: Option<Orientation>,
+    #[serde(default)]
+    pub projection: Projection,
+    /// Render an image for each eye instead of a single image
+    pub stereo: Option<StereoConfig>,
+    pub lens: Option<Lens>,
+    #[serde(default)]
+    pub aperture_shape: ApertureShape,
+    /// How much the lens barrel clips the aperture towards the edges of the image, giving out
+    /// of focus highlights a cat's eye shape. From 0 for none up to 1.
+    #[serde(default)]
+    pub cat_eye: f32,
+    /// The time the shutter opens, rays are sent at random times until it closes
+    #[serde(default)]
+    pub shutter_open: f32,
+    #[serde(default)]
+    pub shutter_close: f32,
+    /// Camera settings changing over the frames of an animation
+    #[serde(default)]
+    pub keyframes: Vec<CameraKeyframe>,
+    #[serde(default)]
+    pub interpolation: Interpolation,
+}
+
+fn default_viewport_fov() -> f32 {
+    90.0
+}
+
+fn default_aperture() -> f32 {
+    0.0
+}
+
+fn default_vup() -> Vec3 {
+    Vec3::new(0., 1., 0.)
+}
+
+impl CameraConfig {
+    /// The right, up and backward axes of the camera
+    pub fn basis(&self) -> (Vec3, Vec3, Vec3) {
+        match self.orientation {
+            Some(Orientation::YawPitchRoll { yaw, pitch, roll }) => {
+                let (yaw, pitch) = (yaw.to_radians(), pitch.to_radians());
+                let forward = Vec3::new(
+                    -yaw.sin() * pitch.cos(),
+                    pitch.sin(),
+                    -yaw.cos() * pitch.cos(),
+                );
+                // The right axis stays level, so looking straight up or down is well defined
+                let u = Vec3::new(yaw.cos(), 0., -yaw.sin());
+                let w = -forward;
+                rolled(u, w.cross(u), w, self.roll + roll)
+            }
+            Some(Orientation::LookAtMatrix([right, _, backward])) => {
+                let w = Vec3::new(backward[0], backward[1], backward[2]).unit_vector();
+                let right = Vec3::new(right[0], right[1], right[2]);
+                let u = (right - right.dot(w) * w).unit_vector();
+                rolled(u, w.cross(u), w, self.roll)
+            }
+            None => {
+                let look_to = self
+                    .look_to
+                    .unwrap_or(self.look_from - Vec3::new(0., 0., 1.));
+                let (u, v, w) = orthonormal_basis(self.look_from - look_to, self.vup);
+                rolled(u, v, w, self.roll)
+            }
+        }
+    }
+
+    /// The vertical field of view in degrees, as given or from the lens and sensor
+    pub fn fov(&self) -> f32 {
+        match self.lens {
+            Some(lens) => {
+                2. * (lens.sensor_height / (2. * lens.focal_length))
+                    .atan()
+                    .to_degrees()
+            }
+            None => self.viewport_fov,
+        }
+    }
+
+    /// The diameter of the aperture in scene units, as given or from the lens
+    pub fn aperture_diameter(&self) -> f32 {
+        match self.lens {
+            Some(lens) => lens.focal_length / lens.f_stop / 1000. * lens.units_per_meter,
+            None => self.aperture,
+        }
+    }
+
+    /// The settings at the frame, interpolated from the keyframes
+    pub fn at_frame(&self, frame: f32) -> CameraConfig {
+        let mut keyframes = self.keyframes.clone();
+        keyframes.sort_by(|a, b| a.frame.total_cmp(&b.frame));
+        let interpolation = self.interpolation;
+        let animate = |field: fn(&CameraKeyframe) -> Option<f32>| {
+            animation::interpolate(&keys(&keyframes, field), frame, interpolation)
+        };
+        let animate_point = |field: fn(&CameraKeyframe) -> Option<Point>| {
+            animation::interpolate(&keys(&keyframes, field), frame, interpolation)
+        };
+
+        CameraConfig {
+            look_from: animate_point(|key| key.look_from).unwrap_or(self.look_from),
+            look_to: animate_point(|key| key.look_to).or(self.look_to),
+            viewport_fov: animate(|key| key.viewport_fov).unwrap_or(self.viewport_fov),
+            focal_length: animate(|key| key.focal_length).or(self.focal_length),
+            aperture: animate(|key| key.aperture).unwrap_or(self.aperture),
+            ..self.clone()
+        }
+    }
+
+    /// Load the aperture mask, relative to the directory of the scene
+    pub fn load_files(&mut self, directory: &Path) -> Result<()> {
+        if let ApertureShape::Mask { path, mask } = &mut self.aperture_shape {
+            let image = Image::load_raw(&directory.join(path))?;
+            *mask = Some(Arc::new(ApertureMask::new(&image)?));
+        }
+        Ok(())
+    }
+
+    /// The distance to the plane in focus, defaulting to the distance to `look_to`
+    pub fn focus_distance(&self) -> f32 {
+        self.focal_length.unwrap_or_else(|| match self.look_to {
+            Some(look_to) if self.orientation.is_none() => (self.look_from - look_to).length(),
+            _ => 1.,
+        })
+    }
+}
+
+/// The (frame, value) keys of the keyframes that set the field
+fn keys<T>(keyframes: &[CameraKeyframe], field: fn(&CameraKeyframe) -> Option<T>) -> Vec<(f32, T)> {
+    keyframes
+        .iter()
+        .filter_map(|key| Some((key.frame, field(key)?)))
+        .collect()
+}
+
+/// Build the right, up and backward axes from the backward direction and an up vector.
+/// When the up vector is parallel to the backward direction, the world axis least aligned
+/// with the view is used instead, so the basis never degenerates into NaNs.
+pub fn orthonormal_basis(backward: Vec3, vup: Vec3) -> (Vec3, Vec3, Vec3) {
+    let w = backward.unit_vector();
+    let mut u = vup.cross(w);
+    if u.length_squared() < 1e-12 {
+        let fallback = if w.x().abs() <= w.y().abs() && w.x().abs() <= w.z().abs() {
+            Vec3::new(1., 0., 0.)
+        } else if w.y().abs() <= w.z().abs() {
+            Vec3::new(0., 1., 0.)
+        } else {
+            Vec3::new(0., 0., 1.)
+        };
+        u = fallback.cross(w);
+    }
+    let u = u.unit_vector();
+    (u, w.cross(u), w)
+}
+
+fn rolled(u: Vec3, v: Vec3, w: Vec3, roll: f32) -> (Vec3, Vec3, Vec3) {
+    let (sin, cos) = roll.to_radians().sin_cos();
+    (cos * u + sin * v, cos * v - sin * u, w)
+}
+
+impl Validate for CameraConfig {
+    fn validate(&self, path: &str, problems: &mut Problems) {
+        let field = |name: &str| join(path, name);
+
+        if !(self.viewport_fov > 0. && self.viewport_fov < 180.) {
+            problems.error(
+                &field("viewport_fov"),
+                "The field of view must be between 0 and 180 degrees",
+            );
+        }
+        if self.aperture < 0. {
+            problems.error(&field("aperture"), "The aperture cannot be negative");
+        }
+        if let Some(focal_length) = self.focal_length {
+            if focal_length <= 0. {
+                problems.error(&field("focal_length"), "The focal length must be positive");
+            }
+        }
+        if self.vup.length_squared() == 0. {
+            problems.error(&field("vup"), "The up vector must not be zero");
+        }
+        if self.focus_on.is_some() && self.focal_length.is_some() {
+            problems.warning(
+                &field("focal_length"),
+                "The camera focuses on a target, so the focal length is ignored",
+            );
+        }
 
-#[derive(Debug, Copy, Clone, PartialEq)]
+        if let Some(lens) = self.lens {
+            for (name, ignored) in [
+                ("viewport_fov", self.viewport_fov != default_viewport_fov()),
+                ("aperture", self.aperture != default_aperture()),
+            ] {
+                if ignored {
+                    problems.warning(
+                        &field(name),
+                        format!("The camera has a lens, so the {} is ignored", name),
+                    );
+                }
+            }
+            let path = field("lens");
+            for (name, value) in [
+                ("focal_length", lens.focal_length),
+                ("f_stop", lens.f_stop),
+                ("sensor_height", lens.sensor_height),
+                ("units_per_meter", lens.units_per_meter),
+            ] {
+                if value <= 0. {
+                    problems.error(&join(&path, name), format!("The {} must be positive", name));
+                }
+            }
+        }
+        if let ApertureShape::Polygon { blades, .. } = self.aperture_shape {
+            if blades < 3 {
+                problems.error(
+                    &join(&field("aperture_shape"), "Polygon.blades"),
+                    "The aperture needs at least 3 blades",
+                );
+            }
+        }
+        if self.aperture_shape != ApertureShape::Circle && self.aperture_diameter() == 0. {
+            problems.warning(
+                &field("aperture_shape"),
+                "The aperture is closed, so its shape has no effect",
+            );
+        }
+        for (index, key) in self.keyframes.iter().enumerate() {
+            let path = join(&field("keyframes"), &index.to_string());
+            if self.keyframes[..index]
+                .iter()
+                .any(|other| other.frame == key.frame)
+            {
+                problems.error(
+                    &join(&path, "frame"),
+                    "Another keyframe is at the same frame",
+                );
+            }
+            if key
+                .viewport_fov
+                .is_some_and(|fov| !(fov > 0. && fov < 180.))
+            {
+                problems.error(
+                    &join(&path, "viewport_fov"),
+                    "The field of view must be between 0 and 180 degrees",
+                );
+            }
+            if key.focal_length.is_some_and(|length| length <= 0.) {
+                problems.error(
+                    &join(&path, "focal_length"),
+                    "The focal length must be positive",
+                );
+            }
+            if key.aperture.is_some_and(|aperture| aperture < 0.) {
+                problems.error(&join(&path, "aperture"), "The aperture cannot be negative");
+            }
+            if self.lens.is_some() && (key.viewport_fov.is_some() || key.aperture.is_some()) {
+                problems.warning(
+                    &path,
+                    "The camera has a lens, so the field of view and aperture are ignored",
+                );
+            }
+            if key.look_to.is_some() && self.orientation.is_some() {
+                problems.warning(
+                    &join(&path, "look_to"),
+                    "The camera has an orientation, so look_to is ignored",
+                );
+            }
+        }
+
+        if !(0. ..=1.).contains(&self.cat_eye) {
+            problems.error(&field("cat_eye"), "The cat's eye must be between 0 and 1");
+        }
+
+        if let Some(Orientation::LookAtMatrix([right, _, backward])) = self.orientation {
+            let right = Vec3::new(right[0], right[1], right[2]);
+            let backward = Vec3::new(backward[0], backward[1], backward[2]);
+            if right.cross(backward).length_squared() < 1e-12 {
+                problems.error(
+                    &field("orientation"),
+                    "The right and backward rows of the matrix must be non-zero and not parallel",
+                );
+            }
+        }
+
+        match self.projection {
+            Projection::Orthographic { height } if height <= 0. => {
+                problems.error(
+                    &join(&field("projection"), "Orthographic.height"),
+                    "The viewport height must be positive",
+                );
+            }
+            Projection::Fisheye { fov, .. } if !(fov > 0. && fov <= 360.) => {
+                problems.error(
+                    &join(&field("projection"), "Fisheye.fov"),
+                    "The fisheye field of view must be between 0 and 360 degrees",
+                );
+            }
+            _ => {}
+        }
+
+        if let Some(stereo) = self.stereo {
+            let path = field("stereo");
+            if stereo.interocular_distance < 0. {
+                problems.error(
+                    &join(&path, "interocular_distance"),
+                    "The interocular distance cannot be negative",
+                );
+            }
+            if let Some(convergence) = stereo.convergence_distance {
+                if convergence <= 0. {
+                    problems.error(
+                        &join(&path, "convergence_distance"),
+                        "The convergence distance must be positive",
+                    );
+                }
+            }
+            if let Projection::Orthographic { .. } = self.projection {
+                problems.warning(
+                    &path,
+                    "Orthographic eyes see the same image, so there is no sense of depth",
+                );
+            }
+        }
+
+        if self.shutter_close < self.shutter_open {
+            problems.error(
+                &field("shutter_close"),
+                "The shutter cannot close before it opens",
+            );
+        }
+
+        match (self.orientation, self.look_to) {
+            (Some(_), Some(_)) => {
+                problems.warning(
+                    &field("look_to"),
+                    "The camera has an orientation, so look_to is ignored",
+                );
+            }
+            (Some(_), None) => {}
+            (None, None) => {
+                problems.error(
+                    &field("look_to"),
+                    "The camera needs either look_to or an orientation",
+                );
+            }
+            (None, Some(look_to)) => {
+                let view = self.look_from - look_to;
+                if view.length_squared() == 0. {
+                    problems.error(
+                        &field("look_to"),
+                        "The camera looks at its own position, look_from and look_to must differ",
+                    );
+                } else if self.vup.length_squared() > 0.
+                    && view.unit_vector().cross(self.vup.unit_vector()).length() < 1e-4
+                {
+                    problems.warning(
+                        &field("vup"),
+                        "The camera looks straight along the up vector, a world axis is used as up instead",
+                    );
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct Camera {
     origin: Point,
     lower_left: Point,
@@ -11,6 +637,15 @@ pub struct Camera {
     v: Vec3,
     w: Vec3,
     lens_radius: f32,
+    aperture_shape: ApertureShape,
+    cat_eye: f32,
+    projection: Projection,
+    aspect_ratio: f32,
+    /// Signed offset of the eye along the right axis, for omnidirectional stereo panoramas
+    eye_offset: f32,
+    convergence: Option<f32>,
+    shutter_open: f32,
+    shutter_close: f32,
 }
 
 impl Camera {
@@ -21,17 +656,107 @@ impl Camera {
         aspect_ratio: f32,
         aperture: f32,
         focus_dist: f32,
+    ) -> Self {
+        let (u, v, w) = orthonormal_basis(look_from - look_at, Vec3::new(0., 1., 0.));
+        Self::from_basis(
+            look_from,
+            (u, v, w),
+            viewport_fov,
+            aspect_ratio,
+            aperture,
+            focus_dist,
+        )
+    }
+
+    pub fn from_config(config: &CameraConfig, aspect_ratio: f32) -> Self {
+        let basis = config.basis();
+        let camera = Self {
+            aperture_shape: config.aperture_shape.clone(),
+            cat_eye: config.cat_eye,
+            shutter_open: config.shutter_open,
+            shutter_close: config.shutter_close,
+            ..Self::from_basis(
+                config.look_from,
+                basis,
+                config.fov(),
+                aspect_ratio,
+                config.aperture_diameter(),
+                config.focus_distance(),
+            )
+        };
+
+        match config.projection {
+            Projection::Perspective => camera,
+            Projection::Orthographic { height } => {
+                let (u, v, _) = basis;
+                let horizontal = height * aspect_ratio * u;
+                let vertical = height * v;
+                Self {
+                    horizontal,
+                    vertical,
+                    lower_left: config.look_from - horizontal / 2. - vertical / 2.,
+                    lens_radius: 0.,
+                    projection: config.projection,
+                    ..camera
+                }
+            }
+            projection => Self {
+                lens_radius: 0.,
+                projection,
+                ..camera
+            },
+        }
+    }
+
+    /// The camera of one eye, offset along the right axis by half the interocular distance.
+    /// Perspective eyes use off-axis frustums that converge at the convergence distance, and
+    /// equirectangular eyes use omnidirectional stereo, moving the eye around a circle so it
+    /// stays perpendicular to every ray.
+    pub fn for_eye(config: &CameraConfig, aspect_ratio: f32, eye: Eye) -> Self {
+        let camera = Self::from_config(config, aspect_ratio);
+        let stereo = match config.stereo {
+            Some(stereo) => stereo,
+            None => return camera,
+        };
+
+        let offset = eye.sign() * stereo.interocular_distance / 2.;
+        let shift = offset * camera.u;
+        match camera.projection {
+            Projection::Perspective => {
+                let focus_distance = config.focus_distance();
+                let convergence = stereo.convergence_distance.unwrap_or(focus_distance);
+                Self {
+                    origin: camera.origin + shift,
+                    lower_left: camera.lower_left + shift - (focus_distance / convergence) * shift,
+                    ..camera
+                }
+            }
+            Projection::Equirectangular => Self {
+                eye_offset: offset,
+                convergence: stereo.convergence_distance,
+                ..camera
+            },
+            _ => Self {
+                origin: camera.origin + shift,
+                lower_left: camera.lower_left + shift,
+                ..camera
+            },
+        }
+    }
+
+    /// Create a camera at the origin with the given right, up and backward axes
+    pub fn from_basis(
+        origin: Point,
+        (u, v, w): (Vec3, Vec3, Vec3),
+        viewport_fov: f32,
+        aspect_ratio: f32,
+        aperture: f32,
+        focus_dist: f32,
     ) -> Self {
         let h = (viewport_fov.to_radians() / 2.).tan();
         let viewport_height = 2. * h;
         let viewport_width = aspect_ratio * viewport_height;
 
-        let vup = Vec3::new(0., 1., 0.);
-        let w = (look_from - look_at).unit_vector();
-        let u = vup.cross(w).unit_vector();
-        let v = w.cross(u);
-
-        let origin = look_from;
         let horizontal = focus_dist * viewport_width * u;
         let vertical = focus_dist * viewport_height * v;
 
@@ -47,12 +772,94 @@ impl Camera {
             v,
             w,
             lens_radius,
+            aperture_shape: ApertureShape::Circle,
+            cat_eye: 0.,
+            projection: Projection::Perspective,
+            aspect_ratio,
+            eye_offset: 0.,
+            convergence: None,
+            shutter_open: 0.,
+            shutter_close: 0.,
         }
     }
 
-    pub fn get_ray(self, u: f32, v: f32) -> Ray {
-        let rd = self.lens_radius * Vec3::new_random_in_unit_disk();
-        let offset = self.u * rd.x() + self.v * rd.y();
+    /// The ray through the image at (u, v), with (0, 0) at the bottom left and (1, 1) at the
+    /// top right, sent at a random time while the shutter is open
+    pub fn get_ray(&self, u: f32, v: f32) -> Ray {
+        let time = if self.shutter_close > self.shutter_open {
+            random::gen_range(self.shutter_open..self.shutter_close)
+        } else {
+            self.shutter_open
+        };
+        self.project(u, v).with_time(time)
+    }
+
+    fn project(&self, u: f32, v: f32) -> Ray {
+        match self.projection {
+            Projection::Perspective => self.get_perspective_ray(u, v),
+            Projection::Orthographic { .. } => Ray::new(
+                self.lower_left + u * self.horizontal + v * self.vertical,
+                -self.w,
+            ),
+            Projection::Fisheye { fov, mapping } => {
+                // Coordinates where the inscribed image circle has radius 1
+                let shorter = self.aspect_ratio.min(1.);
+                let x = (2. * u - 1.) * self.aspect_ratio / shorter;
+                let y = (2. * v - 1.) / shorter;
+                let r = (x * x + y * y).sqrt();
+                if r > 1. {
+                    // Outside the image circle, where no light reaches the sensor
+                    return Ray::new(self.origin, Vec3::new(0., 0., 0.));
+                }
+
+                let max_theta = fov.to_radians() / 2.;
+                let theta = match mapping {
+                    FisheyeMapping::Equidistant => r * max_theta,
+                    FisheyeMapping::Equisolid => 2. * (r * (max_theta / 2.).sin()).min(1.).asin(),
+                };
+                let phi = y.atan2(x);
+                self.ray_towards(
+                    theta.sin() * phi.cos(),
+                    theta.sin() * phi.sin(),
+                    theta.cos(),
+                )
+            }
+            Projection::Equirectangular => {
+                let longitude = (u - 0.5) * 2. * std::f32::consts::PI;
+                let latitude = (v - 0.5) * std::f32::consts::PI;
+                let ray = self.ray_towards(
+                    latitude.cos() * longitude.sin(),
+                    latitude.sin(),
+                    latitude.cos() * longitude.cos(),
+                );
+                if self.eye_offset == 0. {
+                    return ray;
+                }
+
+                // Move the eye around the viewing circle, to the side of the ray's heading
+                let offset =
+                    self.eye_offset * (longitude.cos() * self.u + longitude.sin() * self.w);
+                let direction = match self.convergence {
+                    Some(convergence) => convergence * ray.direction() - offset,
+                    None => ray.direction(),
+                };
+                Ray::new(ray.origin() + offset, direction)
+            }
+        }
+    }
+
+    /// A ray from the origin, given its components along the right, up and forward axes
+    fn ray_towards(&self, right: f32, up: f32, forward: f32) -> Ray {
+        Ray::new(self.origin, right * self.u + up * self.v - forward * self.w)
+    }
+
+    fn get_perspective_ray(&self, u: f32, v: f32) -> Ray {
+        let (x, y) = if self.lens_radius > 0. {
+            self.lens_sample(u, v)
+        } else {
+            (0., 0.)
+        };
+        let offset = self.lens_radius * (self.u * x + self.v * y);
 
         let horizontal_offset = u * self.horizontal;
         let vertical_offset = v * self.vertical;
@@ -61,4 +868,217 @@ impl Camera {
             self.lower_left + horizontal_offset + vertical_offset - self.origin - offset,
         )
     }
+
+    /// The ray through (u, v) from the center of the lens when the shutter opens, as used to
+    /// find what the camera sees there
+    pub fn pinhole_ray(&self, u: f32, v: f32) -> Ray {
+        let pinhole = Self {
+            lens_radius: 0.,
+            ..self.clone()
+        };
+        pinhole.project(u, v).with_time(self.shutter_open)
+    }
+
+    /// The distance from the camera to the plane through the point facing the camera
+    pub fn depth_of(&self, point: Point) -> f32 {
+        (point - self.origin).dot(-self.w)
+    }
+
+    /// A random point on the aperture for a ray through (u, v). Off-axis, the lens barrel is
+    /// a circle shifted towards the edge of the image, and only the overlap lets light through.
+    fn lens_sample(&self, u: f32, v: f32) -> (f32, f32) {
+        let barrel = (self.cat_eye * (2. * u - 1.), self.cat_eye * (2. * v - 1.));
+        for _ in 0..MAX_LENS_SAMPLES {
+            let (x, y) = self.aperture_shape.sample();
+            if self.cat_eye == 0. || (x - barrel.0).powi(2) + (y - barrel.1).powi(2) <= 1. {
+                return (x, y);
+            }
+        }
+        // Halfway to the barrel's center is inside both circles
+        (barrel.0 / 2., barrel.1 / 2.)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_near(a: Vec3, b: Vec3) {
+        assert!((a - b).length() < 1e-5, "{:?} != {:?}", a, b);
+    }
+
+    fn testing_config() -> CameraConfig {
+        serde_json::from_value(serde_json::json!({
+            "look_from": {"x": 0, "y": 0, "z": 0},
+            "look_to": {"x": 0, "y": 0, "z": -1},
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_basis_looking_straight_down() {
+        let mut config = testing_config();
+        config.look_to = Some(Vec3::new(0., -5., 0.));
+        let (u, v, w) = config.basis();
+        for axis in [u, v, w] {
+            assert!((axis.length() - 1.).abs() < 1e-5);
+        }
+        assert_near(w, Vec3::new(0., 1., 0.));
+        assert!(u.dot(v).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_roll_rotates_up_towards_left() {
+        let mut config = testing_config();
+        config.roll = 90.;
+        let (u, v, _) = config.basis();
+        assert_near(u, Vec3::new(0., 1., 0.));
+        assert_near(v, Vec3::new(-1., 0., 0.));
+    }
+
+    #[test]
+    fn test_projections_look_forward_at_center() {
+        let mut config = testing_config();
+        config.look_to = Some(Vec3::new(1., 0., 0.));
+        for projection in [
+            Projection::Perspective,
+            Projection::Orthographic { height: 2. },
+            Projection::Fisheye {
+                fov: 180.,
+                mapping: FisheyeMapping::Equisolid,
+            },
+            Projection::Equirectangular,
+        ] {
+            config.projection = projection;
+            let ray = Camera::from_config(&config, 2.).get_ray(0.5, 0.5);
+            assert_near(ray.origin(), Vec3::new(0., 0., 0.));
+            assert_near(ray.direction().unit_vector(), Vec3::new(1., 0., 0.));
+        }
+    }
+
+    #[test]
+    fn test_equirectangular_covers_full_sphere() {
+        let mut config = testing_config();
+        config.projection = Projection::Equirectangular;
+        let camera = Camera::from_config(&config, 2.);
+        assert_near(camera.get_ray(0., 0.5).direction(), Vec3::new(0., 0., 1.));
+        assert_near(camera.get_ray(0.75, 0.5).direction(), Vec3::new(1., 0., 0.));
+        assert_near(camera.get_ray(0.5, 1.).direction(), Vec3::new(0., 1., 0.));
+    }
+
+    #[test]
+    fn test_fisheye_edge_of_image_circle() {
+        let mut config = testing_config();
+        config.projection = Projection::Fisheye {
+            fov: 180.,
+            mapping: FisheyeMapping::Equidistant,
+        };
+        let camera = Camera::from_config(&config, 2.);
+        // The circle touches the top of the image, 90 degrees from the view direction
+        assert_near(camera.get_ray(0.5, 1.).direction(), Vec3::new(0., 1., 0.));
+    }
+
+    #[test]
+    fn test_eyes_converge_at_convergence_distance() {
+        let mut config = testing_config();
+        config.stereo = Some(StereoConfig {
+            interocular_distance: 0.1,
+            convergence_distance: Some(4.),
+            layout: StereoLayout::Separate,
+        });
+        let left = Camera::for_eye(&config, 1., Eye::Left).get_ray(0.5, 0.5);
+        let right = Camera::for_eye(&config, 1., Eye::Right).get_ray(0.5, 0.5);
+        assert_near(left.origin(), Vec3::new(-0.05, 0., 0.));
+        assert_near(right.origin(), Vec3::new(0.05, 0., 0.));
+
+        let converged = Vec3::new(0., 0., -4.);
+        for ray in [left, right] {
+            let t = (converged - ray.origin()).length() / ray.direction().length();
+            assert_near(ray.at(t), converged);
+        }
+    }
+
+    #[test]
+    fn test_omnidirectional_stereo_eye_is_perpendicular() {
+        let mut config = testing_config();
+        config.projection = Projection::Equirectangular;
+        config.stereo = Some(StereoConfig {
+            interocular_distance: 0.1,
+            convergence_distance: None,
+            layout: StereoLayout::TopBottom,
+        });
+        let camera = Camera::for_eye(&config, 2., Eye::Left);
+        for u in [0., 0.2, 0.5, 0.9] {
+            let ray = camera.get_ray(u, 0.5);
+            assert!((ray.origin().length() - 0.05).abs() < 1e-5);
+            assert!(ray.origin().dot(ray.direction()).abs() < 1e-5);
+        }
+        // Looking forward, the left eye is on the left
+        assert_near(camera.get_ray(0.5, 0.5).origin(), Vec3::new(-0.05, 0., 0.));
+    }
+
+    #[test]
+    fn test_orientations_match_look_to() {
+        let mut config = testing_config();
+        config.look_to = Some(Vec3::new(-1., 0., 0.));
+        let expected = config.basis();
+
+        config.orientation = Some(Orientation::YawPitchRoll {
+            yaw: 90.,
+            pitch: 0.,
+            roll: 0.,
+        });
+        let (u, v, w) = config.basis();
+        assert_near(u, expected.0);
+        assert_near(v, expected.1);
+        assert_near(w, expected.2);
+
+        config.orientation = Some(Orientation::LookAtMatrix([
+            [0., 0., -1.],
+            [0., 1., 0.],
+            [1., 0., 0.],
+        ]));
+        let (u, v, w) = config.basis();
+        assert_near(u, expected.0);
+        assert_near(v, expected.1);
+        assert_near(w, expected.2);
+    }
+
+    #[test]
+    fn test_lens_sets_fov_and_aperture() {
+        let mut config = testing_config();
+        config.lens = Some(Lens {
+            focal_length: 50.,
+            f_stop: 2.,
+            sensor_height: 24.,
+            units_per_meter: 1.,
+        });
+        assert!((config.fov() - 26.99).abs() < 0.01);
+        assert!((config.aperture_diameter() - 0.025).abs() < 1e-6);
+
+        // The settings the lens replaces are reported when they are given
+        config.viewport_fov = 40.;
+        let mut problems = Problems::new(Vec::new());
+        config.validate("", &mut problems);
+        let paths: Vec<_> = problems
+            .into_vec()
+            .into_iter()
+            .map(|problem| problem.path)
+            .collect();
+        assert_eq!(paths, ["viewport_fov"]);
+    }
+
+    #[test]
+    fn test_polygon_aperture_stays_inside_blades() {
+        let shape = ApertureShape::Polygon {
+            blades: 4,
+            rotation: 45.,
+        };
+        // A square with its corners on the unit circle, rotated to align with the axes
+        for _ in 0..1000 {
+            let (x, y) = shape.sample();
+            let half_side = std::f32::consts::FRAC_1_SQRT_2 + 1e-5;
+            assert!(x.abs() <= half_side && y.abs() <= half_side);
+        }
+    }
 }
@@ -1,6 +1,19 @@
+use std::path::Path;
+
+use anyhow::{anyhow, Result};
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 
-use crate::object::Object;
+use crate::animation::FrameRange;
+use crate::atmosphere::Atmosphere;
+use crate::camera::{Camera, CameraConfig, FocusTarget};
+use crate::gltf::GltfImport;
+use crate::light::Light;
+use crate::material::MaterialLibrary;
+use crate::object::{Hittable, LoadFiles, Object, ResolveMaterials};
+use crate::ray::Ray;
+use crate::tile::{Region, TileOrder};
+use crate::validate::{join, Problem, Problems};
 use crate::vec3::Point;
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -13,14 +26,237 @@ pub struct RaytracerConfig {
     pub samples_per_pixel: i32,
     #[serde(default = "default_max_depth")]
     pub max_depth: i32,
-    #[serde(default = "default_viewport_fov")]
-    pub viewport_fov: f32,
-    #[serde(default = "default_aperture")]
-    pub aperture: f32,
-    pub focal_length: Option<f32>,
-    pub look_from: Point,
-    pub look_to: Point,
+    #[serde(flatten)]
+    pub camera: CameraConfig,
+    /// Materials that objects can refer to by name
+    #[serde(default)]
+    pub materials: MaterialLibrary,
     pub world: Vec<Object>,
+    /// glTF scenes added to the world when the scene is loaded
+    #[serde(default)]
+    pub gltf: Vec<GltfImport>,
+    /// A medium every ray travels through, e.g. haze or fog
+    pub atmosphere: Option<Atmosphere>,
+    /// Point-like lights that light surfaces directly
+    #[serde(default)]
+    pub lights: Vec<Light>,
+    #[serde(default = "default_tile_size")]
+    pub tile_size: i32,
+    #[serde(default)]
+    pub tile_order: TileOrder,
+    pub region: Option<Region>,
+    /// Number of render threads, defaults to one per core
+    pub threads: Option<usize>,
+    /// Seed for reproducible renders, defaults to a random seed per tile
+    pub seed: Option<u64>,
+    /// The frames of an animation, defaulting to the span of the camera keyframes
+    pub frames: Option<FrameRange>,
+}
+
+impl RaytracerConfig {
+    /// Parse a scene, applying `path.to.field=value` overrides to it afterwards
+    pub fn from_source<S: AsRef<str>>(source: &str, overrides: &[S]) -> Result<Self> {
+        // Deserialize straight from the source so errors point at a line
+        let config = serde_json::from_str(source)?;
+        if overrides.is_empty() {
+            return Ok(config);
+        }
+
+        // Overrides walk every field the scene has, defaults included, so misspelled
+        // fields are caught rather than ignored
+        let mut scene = serde_json::to_value(&config)?;
+        for assignment in overrides {
+            apply_override(&mut scene, assignment.as_ref())?;
+        }
+        Ok(serde_json::from_value(scene)?)
+    }
+
+    /// The frames to render, if the scene is animated
+    pub fn frame_range(&self) -> Option<FrameRange> {
+        self.frames.or_else(|| {
+            let frames = self.camera.keyframes.iter().map(|key| key.frame);
+            let start = frames.clone().reduce(f32::min)?;
+            let end = frames.reduce(f32::max)?;
+            Some(FrameRange {
+                start: start.floor() as i32,
+                end: end.ceil() as i32,
+            })
+        })
+    }
+
+    /// Make the scene ready to render, by loading the files it refers to relative to the
+    /// directory of the scene, resolving named materials and focusing the camera
+    pub fn prepare(mut self, directory: &Path) -> Result<PreparedConfig> {
+        let warnings = self.load_files(directory)?;
+        self.resolve_materials()?;
+        self.autofocus()?;
+        Ok(PreparedConfig {
+            config: self,
+            warnings,
+        })
+    }
+
+    /// Load the files the scene refers to, relative to the directory of the scene, with
+    /// warnings about what they had that could not be loaded
+    fn load_files(&mut self, directory: &Path) -> Result<Vec<Problem>> {
+        self.camera.load_files(directory)?;
+        self.materials
+            .values_mut()
+            .try_for_each(|material| material.load_files(directory))?;
+        self.world
+            .iter_mut()
+            .try_for_each(|object| object.load_files(directory))?;
+
+        let mut problems = Problems::default();
+        for (index, import) in self.gltf.iter().enumerate() {
+            let scene = import.load(directory)?;
+            let path = join("gltf", &index.to_string());
+            for warning in scene.warnings {
+                problems.warning(&path, warning);
+            }
+            if import.use_camera {
+                let camera = scene.cameras.first().ok_or_else(|| {
+                    anyhow!("{} has no camera to look through", import.path.display())
+                })?;
+                camera.apply(&mut self.camera);
+            }
+            self.world.extend(scene.objects);
+            self.lights.extend(scene.lights);
+        }
+        Ok(problems.into_vec())
+    }
+
+    /// Set the camera's focal length to the distance of its focus target, if it has one
+    pub(crate) fn autofocus(&mut self) -> Result<()> {
+        let target = match &self.camera.focus_on {
+            Some(target) => target,
+            None => return Ok(()),
+        };
+        let aspect_ratio = self.image_width as f32 / self.image_height as f32;
+        let camera = Camera::from_config(&self.camera, aspect_ratio);
+
+        let point = match target {
+            FocusTarget::Pixel { x, y } => {
+                // The center of the pixel, as sampled by the tracer
+                let u = (*x as f32 + 0.5) / (self.image_width - 1) as f32;
+                let v = ((self.image_height - 1 - y) as f32 + 0.5) / (self.image_height - 1) as f32;
+                let ray = camera.pinhole_ray(u, v);
+                self.world
+                    .hit(ray, 0.001, f32::MAX)
+                    .ok_or_else(|| anyhow!("There is nothing to focus on at pixel ({}, {})", x, y))?
+                    .point()
+            }
+            FocusTarget::Object(name) => {
+                let time = self.camera.shutter_open;
+                let (object, transforms) = self
+                    .world
+                    .iter()
+                    .find_map(|object| object.find(name, time))
+                    .ok_or_else(|| anyhow!("There is no object named '{}' to focus on", name))?;
+                let aabb = object.bounding_box().ok_or_else(|| {
+                    anyhow!("'{}' is unbounded, so it cannot be focused on", name)
+                })?;
+                let center = transforms
+                    .iter()
+                    .fold(aabb, |aabb, transform| transform.bounding_box(aabb))
+                    .center();
+                // The surface facing the camera, or the center if the ray slips past it. The
+                // ray is moved into the object's space, where it keeps the same t.
+                let origin = self.camera.look_from;
+                let to_local = |point: Point| {
+                    transforms
+                        .iter()
+                        .rev()
+                        .fold(point, |point, transform| transform.inverse_point(point))
+                };
+                let local = Ray::new(to_local(origin), to_local(center) - to_local(origin));
+                object
+                    .hit(local.with_time(time), 0.001, f32::MAX)
+                    .map_or(center, |record| {
+                        Ray::new(origin, center - origin).at(record.t())
+                    })
+            }
+        };
+
+        let distance = camera.depth_of(point);
+        if distance <= 0. {
+            return Err(anyhow!("The focus target is behind the camera"));
+        }
+        self.camera.focal_length = Some(distance);
+        Ok(())
+    }
+
+    /// Replace references to named materials in the world with the materials themselves
+    fn resolve_materials(&mut self) -> Result<()> {
+        for object in &mut self.world {
+            object.resolve_materials(&self.materials)?;
+        }
+        Ok(())
+    }
+}
+
+/// A scene that was prepared to render, which is what the tracer takes so it never meets
+/// files that were not loaded or materials that were not resolved
+#[derive(Debug)]
+pub struct PreparedConfig {
+    config: RaytracerConfig,
+    warnings: Vec<Problem>,
+}
+
+impl PreparedConfig {
+    /// Problems found while loading files, which leave parts of them out
+    pub fn warnings(&self) -> &[Problem] {
+        &self.warnings
+    }
+
+    pub fn into_inner(self) -> RaytracerConfig {
+        self.config
+    }
+}
+
+/// Set the field at a dot-separated path, e.g. `world.0.Sphere.radius=2`. Every field along the
+/// path must already exist, while unset fields can only be set as a whole. The value is parsed
+/// as JSON, falling back to a plain string.
+pub fn apply_override(scene: &mut Value, assignment: &str) -> Result<()> {
+    let (path, raw_value) = assignment
+        .split_once('=')
+        .ok_or_else(|| anyhow!("Expected path.to.field=value, got '{}'", assignment))?;
+    let value = serde_json::from_str(raw_value.trim())
+        .unwrap_or_else(|_| Value::String(raw_value.trim().to_string()));
+
+    let mut target = scene;
+    for key in path.trim().split('.') {
+        target = match target {
+            Value::Object(fields) => fields
+                .get_mut(key)
+                .ok_or_else(|| anyhow!("'{}' in '{}' is not a field", key, path))?,
+            Value::Array(items) => {
+                let index: usize = key
+                    .parse()
+                    .map_err(|_| anyhow!("'{}' in '{}' is not an array index", key, path))?;
+                let length = items.len();
+                items.get_mut(index).ok_or_else(|| {
+                    anyhow!(
+                        "Index {} in '{}' is out of bounds ({})",
+                        index,
+                        path,
+                        length
+                    )
+                })?
+            }
+            Value::Null => {
+                return Err(anyhow!(
+                    "'{}' in '{}' is inside a field that is not set, set it as a whole",
+                    key,
+                    path
+                ))
+            }
+            _ => return Err(anyhow!("'{}' in '{}' is not an object or array", key, path)),
+        };
+    }
+    *target = value;
+
+    Ok(())
 }
 
 fn default_image_width() -> i32 {
@@ -39,10 +275,135 @@ fn default_max_depth() -> i32 {
     50
 }
 
-fn default_viewport_fov() -> f32 {
-    90.0
+fn default_tile_size() -> i32 {
+    32
 }
 
-fn default_aperture() -> f32 {
-    0.0
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_override_nested_field() {
+        let mut scene = json!({"world": [{"Sphere": {"radius": 1}}]});
+        apply_override(&mut scene, "world.0.Sphere.radius=2.5").unwrap();
+        assert_eq!(scene, json!({"world": [{"Sphere": {"radius": 2.5}}]}));
+    }
+
+    #[test]
+    fn test_override_rejects_unknown_fields() {
+        let scene = json!({
+            "look_from": {"x": 0, "y": 0, "z": 0},
+            "look_to": {"x": 0, "y": 0, "z": -1},
+            "world": [{"Sphere": {
+                "center": {"x": 0, "y": 0, "z": -1},
+                "radius": 0.5,
+                "material": {"Lambertian": {"albedo": {"x": 0.5, "y": 0.5, "z": 0.5}}},
+            }}],
+        })
+        .to_string();
+        // Fields left at their defaults can be overridden, misspelled ones cannot
+        let config = RaytracerConfig::from_source(&scene, &["tile_order=Spiral", "seed=3"]);
+        assert_eq!(config.unwrap().seed, Some(3));
+        assert!(RaytracerConfig::from_source(&scene, &["sampels_per_pixel=5"]).is_err());
+        assert!(RaytracerConfig::from_source(&scene, &["world.0.Sphere.radus=2"]).is_err());
+        assert!(RaytracerConfig::from_source(&scene, &["region.crop=true"]).is_err());
+    }
+
+    #[test]
+    fn test_resolve_named_materials() {
+        let scene = json!({
+            "look_from": {"x": 0, "y": 0, "z": 0},
+            "look_to": {"x": 0, "y": 0, "z": -1},
+            "materials": {"glass": {"Dielectric": {"refractive_index": 1.5}}},
+            "world": [
+                {"Sphere": {"center": {"x": 0, "y": 0, "z": -1}, "radius": 0.5, "material": "glass"}},
+            ],
+        });
+        let config =
+            RaytracerConfig::from_source(&scene.to_string(), &["world.0.Sphere.radius=1"]).unwrap();
+        let config = config.prepare(Path::new("")).unwrap().into_inner();
+        let resolved = serde_json::to_value(&config.world).unwrap();
+        assert_eq!(
+            resolved[0]["Sphere"]["material"]["Dielectric"]["refractive_index"],
+            json!(1.5)
+        );
+
+        let overrides = ["world.0.Sphere.material=steel"];
+        let config = RaytracerConfig::from_source(&scene.to_string(), &overrides).unwrap();
+        assert!(config.prepare(Path::new("")).is_err());
+    }
+
+    #[test]
+    fn test_load_maps_of_inline_materials() {
+        let scene = json!({
+            "look_from": {"x": 0, "y": 0, "z": 0},
+            "look_to": {"x": 0, "y": 0, "z": -1},
+            "world": [{"Sphere": {
+                "center": {"x": 0, "y": 0, "z": -1},
+                "radius": 0.5,
+                "material": {"Lambertian": {
+                    "albedo": {"x": 0.5, "y": 0.5, "z": 0.5},
+                    "bump_map": "missing.pgm",
+                }},
+            }}],
+        });
+        let source = scene.to_string();
+        let config = RaytracerConfig::from_source::<&str>(&source, &[]).unwrap();
+        assert!(crate::validate::validate(&config, &source).is_empty());
+        // The map is read with the object, so the missing file is reported
+        assert!(config.prepare(Path::new("")).is_err());
+    }
+
+    #[test]
+    fn test_override_invalid_path() {
+        let mut scene = json!({"world": [], "image_width": 400});
+        assert!(apply_override(&mut scene, "world.3.radius=1").is_err());
+        assert!(apply_override(&mut scene, "image_width.x=1").is_err());
+        assert!(apply_override(&mut scene, "image_width").is_err());
+    }
+
+    #[test]
+    fn test_autofocus() {
+        let sphere = |z: f32| {
+            json!({"Sphere": {
+                "center": {"x": 0, "y": 0, "z": z},
+                "radius": 1,
+                "material": {"Dielectric": {"refractive_index": 1.5}},
+            }})
+        };
+        let scene = json!({
+            "image_width": 101,
+            "image_height": 101,
+            "look_from": {"x": 0, "y": 0, "z": 0},
+            "look_to": {"x": 0, "y": 0, "z": -1},
+            "focus_on": {"Pixel": {"x": 50, "y": 50}},
+            "world": [
+                sphere(-4.),
+                {"Named": {"name": "far", "object": sphere(-10.)}},
+                {"Union": {"objects": [
+                    sphere(-4.),
+                    {"Instance": {
+                        "object": {"Named": {"name": "moved", "object": sphere(0.)}},
+                        "transform": {"translate": {"x": 0, "y": 0, "z": -7}},
+                    }},
+                ]}},
+            ],
+        });
+        let mut config = RaytracerConfig::from_source(&scene.to_string(), &[""; 0]).unwrap();
+        config.autofocus().unwrap();
+        assert!((config.camera.focal_length.unwrap() - 3.).abs() < 1e-2);
+
+        let overrides = ["focus_on={\"Object\": \"far\"}"];
+        let mut config = RaytracerConfig::from_source(&scene.to_string(), &overrides).unwrap();
+        config.autofocus().unwrap();
+        assert!((config.camera.focal_length.unwrap() - 9.).abs() < 1e-3);
+
+        // Named objects inside others are focused on themselves, where they were moved to
+        let overrides = ["focus_on={\"Object\": \"moved\"}"];
+        let mut config = RaytracerConfig::from_source(&scene.to_string(), &overrides).unwrap();
+        config.autofocus().unwrap();
+        assert!((config.camera.focal_length.unwrap() - 6.).abs() < 1e-3);
+    }
 }
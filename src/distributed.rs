@@ -0,0 +1,351 @@
+use std::collections::VecDeque;
+use std::io::{BufRead, BufReader, BufWriter, ErrorKind, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::config::RaytracerConfig;
+use crate::image::Image;
+use crate::tile::Tile;
+use crate::tracer::Tracer;
+use crate::vec3::Color;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// Messages exchanged between the coordinator and its workers, one JSON object per line.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+enum Message {
+    /// Sent by a worker when it connects
+    Hello { fingerprint: u64 },
+    /// Sent by the coordinator when the worker loaded a different scene
+    Rejected { reason: String },
+    /// Sent by the coordinator to hand out a tile
+    Render { tile: Tile },
+    /// Sent by a worker with the averaged, linear pixels of a tile
+    Rendered { tile: Tile, pixels: Vec<Color> },
+    /// Sent by the coordinator when there are no more tiles
+    Done,
+}
+
+struct Connection {
+    reader: BufReader<TcpStream>,
+    writer: BufWriter<TcpStream>,
+}
+
+impl Connection {
+    fn new(stream: TcpStream) -> Result<Self> {
+        stream.set_nodelay(true)?;
+        Ok(Self {
+            reader: BufReader::new(stream.try_clone()?),
+            writer: BufWriter::new(stream),
+        })
+    }
+
+    fn send(&mut self, message: &Message) -> Result<()> {
+        serde_json::to_writer(&mut self.writer, message)?;
+        self.writer.write_all(b"\n")?;
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    fn receive(&mut self) -> Result<Message> {
+        let mut line = String::new();
+        match self.reader.read_line(&mut line) {
+            Ok(0) => return Err(anyhow!("Connection closed")),
+            Ok(_) => {}
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                return Err(anyhow!("No reply within the timeout"));
+            }
+            Err(e) => return Err(e.into()),
+        }
+        Ok(serde_json::from_str(&line)?)
+    }
+}
+
+/// A fingerprint of everything that affects the rendered pixels, so workers with a different
+/// scene are turned away. Tiling and thread settings only matter locally and are left out.
+pub fn fingerprint(config: &RaytracerConfig) -> Result<u64> {
+    let mut value = serde_json::to_value(config)?;
+    if let Some(settings) = value.as_object_mut() {
+        for key in ["tile_size", "tile_order", "region", "threads"] {
+            settings.remove(key);
+        }
+    }
+
+    // 64-bit FNV-1a, stable across builds unlike the std hasher
+    let hash = value
+        .to_string()
+        .bytes()
+        .fold(0xcbf29ce484222325_u64, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+        });
+    Ok(hash)
+}
+
+/// Hand out the tiles of the render to workers connecting to the listener, and merge the
+/// returned tiles into the final image. Tiles held by a worker that disconnects, or does not
+/// reply within the timeout, are handed out again to the remaining workers.
+pub fn coordinate(tracer: &Tracer, listener: TcpListener, timeout: Duration) -> Result<Image> {
+    let fingerprint = fingerprint(tracer.config())?;
+    let tiles = tracer.tiles();
+    let total_tiles = tiles.len();
+    let queue = Mutex::new(tiles.into_iter().collect::<VecDeque<_>>());
+    let finished = AtomicBool::new(false);
+    let (sender, receiver) = mpsc::channel();
+
+    listener.set_nonblocking(true)?;
+    eprintln!("Waiting for workers on {}", listener.local_addr()?);
+
+    let mut rendered = Vec::with_capacity(total_tiles);
+    thread::scope(|scope| {
+        let (queue, finished) = (&queue, &finished);
+        scope.spawn(move || {
+            while !finished.load(Ordering::Relaxed) {
+                match listener.accept() {
+                    Ok((stream, address)) => {
+                        let sender = sender.clone();
+                        scope.spawn(move || {
+                            let served =
+                                serve_worker(stream, timeout, fingerprint, queue, finished, sender);
+                            if let Err(e) = served {
+                                eprintln!("\nWorker {} disconnected: {}", address, e);
+                            }
+                        });
+                    }
+                    Err(e) if e.kind() == ErrorKind::WouldBlock => thread::sleep(POLL_INTERVAL),
+                    Err(e) => eprintln!("\nFailed to accept worker: {}", e),
+                }
+            }
+        });
+
+        while rendered.len() < total_tiles {
+            match receiver.recv() {
+                Ok(result) => {
+                    rendered.push(result);
+                    eprint!("\rTiles remaining: {}", total_tiles - rendered.len());
+                }
+                Err(_) => break,
+            }
+        }
+        finished.store(true, Ordering::Relaxed);
+    });
+    eprintln!();
+
+    Ok(tracer.assemble(rendered))
+}
+
+fn serve_worker(
+    stream: TcpStream,
+    timeout: Duration,
+    fingerprint: u64,
+    queue: &Mutex<VecDeque<Tile>>,
+    finished: &AtomicBool,
+    results: mpsc::Sender<(Tile, Vec<Color>)>,
+) -> Result<()> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(timeout))?;
+    let mut connection = Connection::new(stream)?;
+
+    match connection.receive()? {
+        Message::Hello { fingerprint: f } if f == fingerprint => {}
+        Message::Hello { .. } => {
+            let reason = String::from("The worker loaded a different scene");
+            connection.send(&Message::Rejected {
+                reason: reason.clone(),
+            })?;
+            return Err(anyhow!(reason));
+        }
+        message => return Err(anyhow!("Expected a greeting, got {:?}", message)),
+    }
+
+    loop {
+        let tile = queue.lock().unwrap().pop_front();
+        let tile = match tile {
+            Some(tile) => tile,
+            None if finished.load(Ordering::Relaxed) => break,
+            None => {
+                // Other workers may still drop their tiles back into the queue
+                thread::sleep(POLL_INTERVAL);
+                continue;
+            }
+        };
+
+        match render_remotely(&mut connection, tile) {
+            Ok(pixels) => {
+                if results.send((tile, pixels)).is_err() {
+                    break;
+                }
+            }
+            Err(e) => {
+                queue.lock().unwrap().push_front(tile);
+                return Err(e);
+            }
+        }
+    }
+
+    connection.send(&Message::Done)
+}
+
+fn render_remotely(connection: &mut Connection, tile: Tile) -> Result<Vec<Color>> {
+    connection.send(&Message::Render { tile })?;
+    match connection.receive()? {
+        Message::Rendered { tile: t, pixels } if t == tile && pixels.len() == tile.area() => {
+            Ok(pixels)
+        }
+        message => Err(anyhow!("Expected tile {:?}, got {:?}", tile, message)),
+    }
+}
+
+/// Connect to a coordinator with `connections` parallel connections and render the tiles
+/// it hands out until it is done.
+pub fn work<A: ToSocketAddrs>(tracer: &Tracer, address: A, connections: usize) -> Result<()> {
+    let fingerprint = fingerprint(tracer.config())?;
+    let addresses: Vec<_> = address.to_socket_addrs()?.collect();
+
+    thread::scope(|scope| {
+        let handles: Vec<_> = (0..connections.max(1))
+            .map(|_| {
+                let addresses = &addresses[..];
+                scope.spawn(move || -> Result<usize> {
+                    let stream = TcpStream::connect(addresses)?;
+                    render_for(tracer, Connection::new(stream)?, fingerprint)
+                })
+            })
+            .collect();
+
+        let mut rendered = 0;
+        for handle in handles {
+            rendered += handle
+                .join()
+                .map_err(|_| anyhow!("Worker thread panicked"))??;
+        }
+        eprintln!("Rendered {} tiles", rendered);
+        Ok(())
+    })
+}
+
+fn render_for(tracer: &Tracer, mut connection: Connection, fingerprint: u64) -> Result<usize> {
+    let mut rendered = 0;
+
+    connection.send(&Message::Hello { fingerprint })?;
+    loop {
+        match connection.receive()? {
+            Message::Render { tile } => {
+                let pixels = tracer.render_tile(tile);
+                connection.send(&Message::Rendered { tile, pixels })?;
+                rendered += 1;
+            }
+            Message::Done => return Ok(rendered),
+            Message::Rejected { reason } => return Err(anyhow!(reason)),
+            message => return Err(anyhow!("Unexpected message {:?}", message)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    const TIMEOUT: Duration = Duration::from_secs(60);
+
+    fn testing_config(samples_per_pixel: i32) -> RaytracerConfig {
+        serde_json::from_value(serde_json::json!({
+            "image_width": 24,
+            "image_height": 16,
+            "samples_per_pixel": samples_per_pixel,
+            "tile_size": 4,
+            "look_from": {"x": 0, "y": 0, "z": 0},
+            "look_to": {"x": 0, "y": 0, "z": -1},
+            "world": [],
+        }))
+        .unwrap()
+    }
+
+    fn testing_tracer(samples_per_pixel: i32) -> Tracer {
+        let config = testing_config(samples_per_pixel);
+        Tracer::new(config.prepare(Path::new("")).unwrap())
+    }
+
+    #[test]
+    fn test_fingerprint_ignores_tiling() {
+        let mut config = testing_config(1);
+        let expected = fingerprint(&config).unwrap();
+        config.tile_size = 64;
+        assert_eq!(fingerprint(&config).unwrap(), expected);
+        config.samples_per_pixel = 2;
+        assert_ne!(fingerprint(&config).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_render_survives_disconnected_worker() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let tracer = testing_tracer(1);
+
+        thread::scope(|scope| {
+            let coordinator = scope.spawn(|| coordinate(&tracer, listener, TIMEOUT).unwrap());
+
+            // Take a tile and hang up without rendering it
+            let mut connection = Connection::new(TcpStream::connect(address).unwrap()).unwrap();
+            let fingerprint = fingerprint(tracer.config()).unwrap();
+            connection.send(&Message::Hello { fingerprint }).unwrap();
+            assert!(matches!(
+                connection.receive().unwrap(),
+                Message::Render { .. }
+            ));
+            drop(connection);
+
+            work(&tracer, address, 2).unwrap();
+            let image = coordinator.join().unwrap();
+            assert_eq!((image.width(), image.height()), (24, 16));
+            assert!(image.pixels().iter().all(|pixel| pixel.z() > 0.));
+        });
+    }
+
+    #[test]
+    fn test_render_survives_hanging_worker() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let tracer = testing_tracer(1);
+
+        thread::scope(|scope| {
+            let coordinator =
+                scope.spawn(|| coordinate(&tracer, listener, Duration::from_millis(200)));
+
+            // Take a tile and keep the connection open without ever rendering it
+            let mut connection = Connection::new(TcpStream::connect(address).unwrap()).unwrap();
+            let fingerprint = fingerprint(tracer.config()).unwrap();
+            connection.send(&Message::Hello { fingerprint }).unwrap();
+            assert!(matches!(
+                connection.receive().unwrap(),
+                Message::Render { .. }
+            ));
+
+            work(&tracer, address, 1).unwrap();
+            let image = coordinator.join().unwrap().unwrap();
+            assert!(image.pixels().iter().all(|pixel| pixel.z() > 0.));
+            drop(connection);
+        });
+    }
+
+    #[test]
+    fn test_worker_with_different_scene_is_rejected() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let tracer = testing_tracer(1);
+        let other = testing_tracer(2);
+
+        thread::scope(|scope| {
+            scope.spawn(|| coordinate(&tracer, listener, TIMEOUT).unwrap());
+            assert!(work(&other, address, 1).is_err());
+            work(&tracer, address, 1).unwrap();
+        });
+    }
+}
@@ -0,0 +1,342 @@
+// GIF Format taken from: https://www.w3.org/Graphics/GIF/spec-gif89a.txt
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Result};
+
+use crate::image::{AnimationOptions, Image};
+
+/// Colors in the palette of every frame, the most a GIF allows
+const PALETTE_SIZE: usize = 256;
+/// The largest code the LZW compression may use
+const MAX_CODE: u16 = 4095;
+
+/// Encode the frames as a looping GIF89a, each with its own median cut palette
+pub fn encode(frames: &[Image], options: AnimationOptions) -> Result<Vec<u8>> {
+    let first = frames
+        .first()
+        .ok_or_else(|| anyhow!("A gif needs at least one frame"))?;
+    let (width, height) = (first.width(), first.height());
+    if width > u16::MAX as i32 || height > u16::MAX as i32 {
+        return Err(anyhow!("A gif can be at most 65535 pixels wide and tall"));
+    }
+    let (width, height) = (width as u16, height as u16);
+
+    let mut bytes = Vec::new();
+    bytes.extend_from_slice(b"GIF89a");
+
+    // Logical screen descriptor, without a global color table
+    bytes.extend_from_slice(&width.to_le_bytes());
+    bytes.extend_from_slice(&height.to_le_bytes());
+    bytes.extend_from_slice(&[
+        0x00, // No global color table
+        0x00, // Background color index
+        0x00, // No pixel aspect ratio
+    ]);
+
+    // Netscape application extension for looping, where the count is the number of repeats
+    if options.loops != 1 {
+        bytes.extend_from_slice(&[0x21, 0xFF, 0x0B]);
+        bytes.extend_from_slice(b"NETSCAPE2.0");
+        bytes.extend_from_slice(&[0x03, 0x01]);
+        bytes.extend_from_slice(&options.loops.saturating_sub(1).to_le_bytes());
+        bytes.push(0x00);
+    }
+
+    let delay = (100. / options.frames_per_second).round() as u16;
+    for frame in frames {
+        // Graphic control extension with the delay in hundredths of a second
+        bytes.extend_from_slice(&[0x21, 0xF9, 0x04, 0x00]);
+        bytes.extend_from_slice(&delay.to_le_bytes());
+        bytes.extend_from_slice(&[0x00, 0x00]);
+
+        // Image descriptor with a local color table of 256 colors
+        bytes.push(0x2C);
+        bytes.extend_from_slice(&[0, 0, 0, 0]);
+        bytes.extend_from_slice(&width.to_le_bytes());
+        bytes.extend_from_slice(&height.to_le_bytes());
+        bytes.push(0x87);
+
+        let colors = frame.to_rgb8();
+        let mut palette = median_cut(&colors, PALETTE_SIZE);
+        let indices = map_to_palette(&colors, width as usize, &palette, options.dither);
+        palette.resize(PALETTE_SIZE, [0, 0, 0]);
+        bytes.extend(palette.iter().flatten());
+
+        bytes.push(8); // LZW minimum code size
+        for block in lzw_encode(&indices, 8).chunks(255) {
+            bytes.push(block.len() as u8);
+            bytes.extend_from_slice(block);
+        }
+        bytes.push(0x00);
+    }
+
+    bytes.push(0x3B); // Trailer
+    Ok(bytes)
+}
+
+/// Reduce the colors to a palette of at most `size` colors, by repeatedly splitting the box
+/// of colors with the widest range at the median of that channel
+fn median_cut(colors: &[[u8; 3]], size: usize) -> Vec<[u8; 3]> {
+    let mut boxes = vec![colors.to_vec()];
+    while boxes.len() < size {
+        let (index, channel, range) = boxes
+            .iter()
+            .enumerate()
+            .map(|(index, colors)| {
+                let (channel, range) = widest_channel(colors);
+                (index, channel, range)
+            })
+            .max_by_key(|&(_, _, range)| range)
+            .unwrap();
+        if range == 0 {
+            break;
+        }
+
+        let mut lower = boxes.swap_remove(index);
+        lower.sort_unstable_by_key(|color| color[channel]);
+        let upper = lower.split_off(lower.len() / 2);
+        boxes.push(lower);
+        boxes.push(upper);
+    }
+
+    boxes
+        .iter()
+        .filter(|colors| !colors.is_empty())
+        .map(|colors| {
+            let mut sum = [0_u64; 3];
+            for color in colors {
+                for channel in 0..3 {
+                    sum[channel] += color[channel] as u64;
+                }
+            }
+            let count = colors.len() as u64;
+            sum.map(|total| ((total + count / 2) / count) as u8)
+        })
+        .collect()
+}
+
+/// The channel whose values are spread the widest, and how wide
+fn widest_channel(colors: &[[u8; 3]]) -> (usize, u8) {
+    (0..3)
+        .map(|channel| {
+            let values = colors.iter().map(|color| color[channel]);
+            let min = values.clone().min().unwrap_or(0);
+            let max = values.max().unwrap_or(0);
+            (channel, max - min)
+        })
+        .max_by_key(|&(_, range)| range)
+        .unwrap()
+}
+
+/// The index of the nearest palette color of every pixel, optionally spreading the error of
+/// each pixel over its unvisited neighbours with Floyd-Steinberg dithering
+fn map_to_palette(colors: &[[u8; 3]], width: usize, palette: &[[u8; 3]], dither: bool) -> Vec<u8> {
+    let mut nearest_cache = HashMap::new();
+    let mut nearest = |color: [u8; 3]| {
+        *nearest_cache.entry(color).or_insert_with(|| {
+            let distance = |entry: &[u8; 3]| {
+                (0..3)
+                    .map(|channel| (entry[channel] as i32 - color[channel] as i32).pow(2))
+                    .sum::<i32>()
+            };
+            (0..palette.len())
+                .min_by_key(|&index| distance(&palette[index]))
+                .unwrap() as u8
+        })
+    };
+
+    if !dither {
+        return colors.iter().map(|&color| nearest(color)).collect();
+    }
+
+    let mut errors = vec![[0_f32; 3]; colors.len()];
+    let mut indices = Vec::with_capacity(colors.len());
+    for (position, color) in colors.iter().enumerate() {
+        let wanted = [0, 1, 2].map(|c| (color[c] as f32 + errors[position][c]).clamp(0., 255.));
+        let index = nearest(wanted.map(|value| value.round() as u8));
+        indices.push(index);
+
+        let chosen = palette[index as usize];
+        let error = [0, 1, 2].map(|c| wanted[c] - chosen[c] as f32);
+        let (x, y) = (position % width, position / width);
+        let height = colors.len() / width;
+        for (dx, dy, weight) in [(1, 0, 7.), (-1, 1, 3.), (0, 1, 5.), (1, 1, 1.)] {
+            let (nx, ny) = (x as i64 + dx, y as i64 + dy);
+            if (0..width as i64).contains(&nx) && (ny as usize) < height {
+                let neighbour = &mut errors[ny as usize * width + nx as usize];
+                for c in 0..3 {
+                    neighbour[c] += error[c] * weight / 16.;
+                }
+            }
+        }
+    }
+    indices
+}
+
+/// Compress the indices with variable length LZW codes, packed from the least significant bit
+fn lzw_encode(indices: &[u8], min_code_size: u8) -> Vec<u8> {
+    let clear_code: u16 = 1 << min_code_size;
+    let end_code = clear_code + 1;
+
+    let mut writer = BitWriter::default();
+    let mut table: HashMap<(u16, u8), u16> = HashMap::new();
+    let mut next_code = end_code + 1;
+    let mut code_size = min_code_size + 1;
+    writer.write(clear_code, code_size);
+
+    let mut prefix: Option<u16> = None;
+    for &index in indices {
+        let current = match prefix {
+            Some(current) => current,
+            None => {
+                prefix = Some(index as u16);
+                continue;
+            }
+        };
+        if let Some(&code) = table.get(&(current, index)) {
+            prefix = Some(code);
+            continue;
+        }
+
+        writer.write(current, code_size);
+        if next_code <= MAX_CODE {
+            table.insert((current, index), next_code);
+            next_code += 1;
+            if next_code > 1 << code_size && code_size < 12 {
+                code_size += 1;
+            }
+        } else {
+            // The table is full, so start over
+            writer.write(clear_code, code_size);
+            table.clear();
+            next_code = end_code + 1;
+            code_size = min_code_size + 1;
+        }
+        prefix = Some(index as u16);
+    }
+
+    if let Some(current) = prefix {
+        writer.write(current, code_size);
+    }
+    writer.write(end_code, code_size);
+    writer.finish()
+}
+
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    buffer: u32,
+    bits: u8,
+}
+
+impl BitWriter {
+    fn write(&mut self, code: u16, size: u8) {
+        self.buffer |= (code as u32) << self.bits;
+        self.bits += size;
+        while self.bits >= 8 {
+            self.bytes.push(self.buffer as u8);
+            self.buffer >>= 8;
+            self.bits -= 8;
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.bits > 0 {
+            self.bytes.push(self.buffer as u8);
+        }
+        self.bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::primitive::random;
+    use crate::vec3::Color;
+
+    /// A straightforward LZW decoder to check the encoder against
+    fn lzw_decode(bytes: &[u8], min_code_size: u8) -> Vec<u8> {
+        let clear_code = 1_usize << min_code_size;
+        let end_code = clear_code + 1;
+        let mut table: Vec<Vec<u8>> = Vec::new();
+        let mut code_size = min_code_size + 1;
+        let mut previous: Option<usize> = None;
+        let mut output = Vec::new();
+
+        let (mut buffer, mut bits, mut position) = (0_u32, 0, 0);
+        loop {
+            while bits < code_size {
+                buffer |= (bytes[position] as u32) << bits;
+                position += 1;
+                bits += 8;
+            }
+            let code = (buffer & ((1 << code_size) - 1)) as usize;
+            buffer >>= code_size;
+            bits -= code_size;
+
+            if code == clear_code {
+                table = (0..clear_code).map(|i| vec![i as u8]).collect();
+                table.push(Vec::new());
+                table.push(Vec::new());
+                code_size = min_code_size + 1;
+                previous = None;
+                continue;
+            }
+            if code == end_code {
+                return output;
+            }
+            let entry = match (table.get(code), previous) {
+                (Some(entry), _) => entry.clone(),
+                (None, Some(previous)) => {
+                    let mut entry = table[previous].clone();
+                    entry.push(table[previous][0]);
+                    entry
+                }
+                (None, None) => panic!("Invalid code {}", code),
+            };
+            output.extend_from_slice(&entry);
+            if let Some(previous) = previous {
+                let mut new_entry = table[previous].clone();
+                new_entry.push(entry[0]);
+                table.push(new_entry);
+                if table.len() == 1 << code_size && code_size < 12 {
+                    code_size += 1;
+                }
+            }
+            previous = Some(code);
+        }
+    }
+
+    #[test]
+    fn test_lzw_round_trip() {
+        random::seed(7);
+        // Enough varied data to fill the code table several times
+        let mut indices: Vec<u8> = (0..50_000).map(|_| random::gen_range(0..16)).collect();
+        indices.extend(std::iter::repeat_n(3, 10_000));
+        assert_eq!(lzw_decode(&lzw_encode(&indices, 8), 8), indices);
+        assert_eq!(lzw_decode(&lzw_encode(&[], 8), 8), Vec::<u8>::new());
+    }
+
+    #[test]
+    fn test_median_cut_keeps_few_colors_exact() {
+        let colors = [[255, 0, 0], [0, 0, 255], [255, 0, 0], [10, 200, 10]];
+        let mut palette = median_cut(&colors, 256);
+        palette.sort_unstable();
+        assert_eq!(palette, [[0, 0, 255], [10, 200, 10], [255, 0, 0]]);
+    }
+
+    #[test]
+    fn test_encode_structure() {
+        let mut image = Image::new(3, 2);
+        image.set(1, 1, Color::new(1., 0.5, 0.));
+        let options = AnimationOptions {
+            dither: true,
+            ..AnimationOptions::default()
+        };
+        let bytes = encode(&[image.clone(), image], options).unwrap();
+        assert!(bytes.starts_with(b"GIF89a"));
+        assert_eq!(bytes.last(), Some(&0x3B));
+        let frames = bytes.windows(2).filter(|w| w == &[0x21, 0xF9]).count();
+        assert_eq!(frames, 2);
+    }
+}
@@ -0,0 +1,1042 @@
+use std::collections::HashMap;
+use std::f32::consts::PI;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::camera::{CameraConfig, Projection};
+use crate::image::Image;
+use crate::light::Light;
+use crate::material::{Dielectric, Lambertian, Material, Metal, SurfaceMaps};
+use crate::object::{Mesh, Object, Transform, TriangleMesh};
+use crate::validate::{join, Problems, Validate};
+use crate::vec3::{Color, Point, Vec3};
+
+/// A glTF 2.0 scene added to the world when the scene is loaded, with its meshes, materials
+/// and lights. Paths are relative to the scene file.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct GltfImport {
+    pub path: PathBuf,
+    /// Places the whole glTF scene in the world
+    #[serde(default)]
+    pub transform: Transform,
+    /// Look through the first camera of the glTF scene instead of the scene's own camera
+    #[serde(default)]
+    pub use_camera: bool,
+}
+
+impl Validate for GltfImport {
+    fn validate(&self, path: &str, problems: &mut Problems) {
+        let extension = self.path.extension().and_then(|s| s.to_str());
+        if extension != Some("gltf") {
+            problems.error(&join(path, "path"), "Only .gltf files can be imported");
+        }
+        self.transform.validate(&join(path, "transform"), problems);
+    }
+}
+
+/// A scene that renders a glTF file on its own, through its first camera
+pub fn scene_source(file_name: &str) -> String {
+    serde_json::json!({
+        "look_from": {"x": 0, "y": 0, "z": 0},
+        "look_to": {"x": 0, "y": 0, "z": -1},
+        "world": [],
+        "gltf": [{"path": file_name, "use_camera": true}],
+    })
+    .to_string()
+}
+
+/// What a glTF file adds to the scene, in world space
+pub struct GltfScene {
+    pub objects: Vec<Object>,
+    pub lights: Vec<Light>,
+    pub cameras: Vec<GltfCamera>,
+    /// What was left out because it cannot be read, e.g. textures in unsupported formats
+    pub warnings: Vec<String>,
+}
+
+/// A camera of a glTF scene
+#[derive(Debug, PartialEq)]
+pub struct GltfCamera {
+    position: Point,
+    forward: Vec3,
+    up: Vec3,
+    /// The vertical field of view in degrees, or None for orthographic cameras
+    fov: Option<f32>,
+    /// The height of the view of orthographic cameras
+    height: f32,
+}
+
+impl GltfCamera {
+    /// Point the scene's camera like this one
+    pub fn apply(&self, camera: &mut CameraConfig) {
+        camera.look_from = self.position;
+        camera.look_to = Some(self.position + self.forward);
+        camera.vup = self.up;
+        camera.roll = 0.;
+        camera.orientation = None;
+        match self.fov {
+            Some(fov) => {
+                camera.viewport_fov = fov;
+                camera.projection = Projection::Perspective;
+            }
+            None => {
+                camera.projection = Projection::Orthographic {
+                    height: self.height,
+                }
+            }
+        }
+    }
+}
+
+impl GltfImport {
+    pub fn load(&self, directory: &Path) -> Result<GltfScene> {
+        let path = directory.join(&self.path);
+        let source =
+            fs::read(&path).map_err(|e| anyhow!("Cannot read {}: {}", path.display(), e))?;
+        let document: Document = serde_json::from_slice(&source)
+            .map_err(|e| anyhow!("Cannot load {}: {}", path.display(), e))?;
+        let base = path.parent().unwrap_or_else(|| Path::new(""));
+        let mut loader = Loader {
+            buffers: document
+                .buffers
+                .iter()
+                .map(|buffer| read_uri(buffer.uri.as_deref(), base))
+                .collect::<Result<_>>()?,
+            document,
+            base,
+            path: &path,
+            transform: self.transform,
+            textures: HashMap::new(),
+            scene: GltfScene {
+                objects: Vec::new(),
+                lights: Vec::new(),
+                cameras: Vec::new(),
+                warnings: Vec::new(),
+            },
+        };
+        loader
+            .load()
+            .map_err(|e| anyhow!("Cannot load {}: {}", path.display(), e))?;
+        Ok(loader.scene)
+    }
+}
+
+// The parts of the glTF format that are read, see https://registry.khronos.org/glTF/
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase", default)]
+struct Document {
+    scene: Option<usize>,
+    scenes: Vec<SceneNodes>,
+    nodes: Vec<Node>,
+    meshes: Vec<GltfMesh>,
+    accessors: Vec<Accessor>,
+    buffer_views: Vec<BufferView>,
+    buffers: Vec<Buffer>,
+    materials: Vec<GltfMaterial>,
+    textures: Vec<Texture>,
+    images: Vec<GltfImage>,
+    cameras: Vec<Camera>,
+    extensions: Option<DocumentExtensions>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct SceneNodes {
+    nodes: Vec<usize>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct Node {
+    children: Vec<usize>,
+    mesh: Option<usize>,
+    camera: Option<usize>,
+    matrix: Option<[f32; 16]>,
+    translation: Option<[f32; 3]>,
+    rotation: Option<[f32; 4]>,
+    scale: Option<[f32; 3]>,
+    extensions: Option<NodeExtensions>,
+}
+
+#[derive(Deserialize)]
+struct GltfMesh {
+    primitives: Vec<Primitive>,
+}
+
+#[derive(Deserialize)]
+struct Primitive {
+    attributes: HashMap<String, usize>,
+    indices: Option<usize>,
+    material: Option<usize>,
+    mode: Option<u32>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Accessor {
+    buffer_view: Option<usize>,
+    #[serde(default)]
+    byte_offset: usize,
+    component_type: u32,
+    #[serde(default)]
+    normalized: bool,
+    count: usize,
+    #[serde(rename = "type")]
+    kind: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BufferView {
+    buffer: usize,
+    #[serde(default)]
+    byte_offset: usize,
+    byte_length: usize,
+    byte_stride: Option<usize>,
+}
+
+#[derive(Deserialize)]
+struct Buffer {
+    uri: Option<String>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase", default)]
+struct GltfMaterial {
+    pbr_metallic_roughness: MetallicRoughness,
+    normal_texture: Option<NormalTextureInfo>,
+    extensions: MaterialExtensions,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+struct MetallicRoughness {
+    base_color_factor: [f32; 4],
+    base_color_texture: Option<TextureInfo>,
+    metallic_factor: f32,
+    roughness_factor: f32,
+}
+
+impl Default for MetallicRoughness {
+    fn default() -> Self {
+        Self {
+            base_color_factor: [1., 1., 1., 1.],
+            base_color_texture: None,
+            metallic_factor: 1.,
+            roughness_factor: 1.,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TextureInfo {
+    index: usize,
+    #[serde(default)]
+    tex_coord: usize,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct NormalTextureInfo {
+    index: usize,
+    #[serde(default)]
+    tex_coord: usize,
+    #[serde(default = "default_normal_scale")]
+    scale: f32,
+}
+
+fn default_normal_scale() -> f32 {
+    1.
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct MaterialExtensions {
+    #[serde(rename = "KHR_materials_transmission")]
+    transmission: Option<Transmission>,
+    #[serde(rename = "KHR_materials_ior")]
+    ior: Option<Ior>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Transmission {
+    #[serde(default)]
+    transmission_factor: f32,
+}
+
+#[derive(Deserialize)]
+struct Ior {
+    ior: f32,
+}
+
+#[derive(Deserialize)]
+struct Texture {
+    source: Option<usize>,
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+struct GltfImage {
+    uri: Option<String>,
+    buffer_view: Option<usize>,
+    mime_type: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum Camera {
+    Perspective { perspective: Perspective },
+    Orthographic { orthographic: Orthographic },
+}
+
+#[derive(Deserialize)]
+struct Perspective {
+    yfov: f32,
+}
+
+#[derive(Deserialize)]
+struct Orthographic {
+    ymag: f32,
+}
+
+#[derive(Deserialize)]
+struct DocumentExtensions {
+    #[serde(rename = "KHR_lights_punctual")]
+    lights: Option<PunctualLights>,
+}
+
+#[derive(Deserialize)]
+struct PunctualLights {
+    lights: Vec<PunctualLight>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PunctualLight {
+    #[serde(rename = "type")]
+    kind: String,
+    color: Option<[f32; 3]>,
+    intensity: Option<f32>,
+    spot: Option<Spot>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Spot {
+    #[serde(default)]
+    inner_cone_angle: f32,
+    outer_cone_angle: Option<f32>,
+}
+
+#[derive(Deserialize)]
+struct NodeExtensions {
+    #[serde(rename = "KHR_lights_punctual")]
+    light: Option<LightIndex>,
+}
+
+#[derive(Deserialize)]
+struct LightIndex {
+    light: usize,
+}
+
+/// A 4x4 matrix for the transforms of nodes, stored by rows
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct Matrix([[f32; 4]; 4]);
+
+impl Matrix {
+    const IDENTITY: Matrix = Matrix([
+        [1., 0., 0., 0.],
+        [0., 1., 0., 0.],
+        [0., 0., 1., 0.],
+        [0., 0., 0., 1.],
+    ]);
+
+    /// The matrix from glTF's column by column order
+    fn from_columns(values: [f32; 16]) -> Matrix {
+        let mut rows = [[0.; 4]; 4];
+        for (i, value) in values.iter().enumerate() {
+            rows[i % 4][i / 4] = *value;
+        }
+        Matrix(rows)
+    }
+
+    /// A translation of a rotation, given as a quaternion x, y, z, w, of a scale
+    fn from_parts(translation: [f32; 3], rotation: [f32; 4], scale: [f32; 3]) -> Matrix {
+        let [x, y, z, w] = rotation;
+        let rotation = [
+            [
+                1. - 2. * (y * y + z * z),
+                2. * (x * y - z * w),
+                2. * (x * z + y * w),
+            ],
+            [
+                2. * (x * y + z * w),
+                1. - 2. * (x * x + z * z),
+                2. * (y * z - x * w),
+            ],
+            [
+                2. * (x * z - y * w),
+                2. * (y * z + x * w),
+                1. - 2. * (x * x + y * y),
+            ],
+        ];
+        let mut rows = Matrix::IDENTITY.0;
+        for row in 0..3 {
+            for column in 0..3 {
+                rows[row][column] = rotation[row][column] * scale[column];
+            }
+            rows[row][3] = translation[row];
+        }
+        Matrix(rows)
+    }
+
+    fn then(self, child: Matrix) -> Matrix {
+        let mut rows = [[0.; 4]; 4];
+        for (row, values) in rows.iter_mut().enumerate() {
+            for (column, value) in values.iter_mut().enumerate() {
+                *value = (0..4).map(|k| self.0[row][k] * child.0[k][column]).sum();
+            }
+        }
+        Matrix(rows)
+    }
+
+    fn vector(self, v: Vec3) -> Vec3 {
+        let row = |r: [f32; 4]| r[0] * v.x() + r[1] * v.y() + r[2] * v.z();
+        Vec3::new(row(self.0[0]), row(self.0[1]), row(self.0[2]))
+    }
+
+    fn point(self, p: Point) -> Point {
+        self.vector(p) + Vec3::new(self.0[0][3], self.0[1][3], self.0[2][3])
+    }
+
+    /// The columns of the inverse transpose scaled by the determinant, which keep normals
+    /// perpendicular to the surface, and the determinant
+    fn normal_columns(self) -> ([Vec3; 3], f32) {
+        let [a, b, c] = [
+            Vec3::new(1., 0., 0.),
+            Vec3::new(0., 1., 0.),
+            Vec3::new(0., 0., 1.),
+        ]
+        .map(|axis| self.vector(axis));
+        ([b.cross(c), c.cross(a), a.cross(b)], a.dot(b.cross(c)))
+    }
+}
+
+/// A texture multiplying the base color, and the set of surface coordinates placing it and
+/// the normal map
+struct BaseColor {
+    image: Option<Arc<Image>>,
+    tex_coord: usize,
+}
+
+struct Loader<'a> {
+    document: Document,
+    buffers: Vec<Vec<u8>>,
+    /// The directory of the glTF file
+    base: &'a Path,
+    path: &'a Path,
+    transform: Transform,
+    /// Images by texture, and whether they are colors rather than data
+    textures: HashMap<(usize, bool), Option<Arc<Image>>>,
+    scene: GltfScene,
+}
+
+impl Loader<'_> {
+    fn load(&mut self) -> Result<()> {
+        let roots = match self.document.scenes.get(self.document.scene.unwrap_or(0)) {
+            Some(scene) => scene.nodes.clone(),
+            // Without scenes, every node that is not a child is a root
+            None => {
+                let children: Vec<usize> = self
+                    .document
+                    .nodes
+                    .iter()
+                    .flat_map(|node| node.children.iter().copied())
+                    .collect();
+                (0..self.document.nodes.len())
+                    .filter(|node| !children.contains(node))
+                    .collect()
+            }
+        };
+        for root in roots {
+            self.load_node(root, Matrix::IDENTITY, 0)?;
+        }
+        Ok(())
+    }
+
+    fn load_node(&mut self, index: usize, parent: Matrix, depth: usize) -> Result<()> {
+        let node = self
+            .document
+            .nodes
+            .get(index)
+            .ok_or_else(|| anyhow!("There is no node {}", index))?;
+        if depth > self.document.nodes.len() {
+            return Err(anyhow!("Node {} is its own ancestor", index));
+        }
+        let local = match node.matrix {
+            Some(matrix) => Matrix::from_columns(matrix),
+            None => Matrix::from_parts(
+                node.translation.unwrap_or([0., 0., 0.]),
+                node.rotation.unwrap_or([0., 0., 0., 1.]),
+                node.scale.unwrap_or([1., 1., 1.]),
+            ),
+        };
+        let matrix = parent.then(local);
+        let (mesh, camera, children) = (node.mesh, node.camera, node.children.clone());
+        let light = node
+            .extensions
+            .as_ref()
+            .and_then(|extensions| extensions.light.as_ref())
+            .map(|light| light.light);
+
+        if let Some(mesh) = mesh {
+            self.load_mesh(mesh, matrix)?;
+        }
+        if let Some(camera) = camera {
+            self.load_camera(camera, matrix)?;
+        }
+        if let Some(light) = light {
+            self.load_light(light, matrix)?;
+        }
+        for child in children {
+            self.load_node(child, matrix, depth + 1)?;
+        }
+        Ok(())
+    }
+
+    /// The position of a node, and the directions of its -z and y axes, in the world
+    fn placement(&self, matrix: Matrix) -> (Point, Vec3, Vec3) {
+        let transform = self.transform;
+        let position = transform.point(matrix.point(Vec3::new(0., 0., 0.)));
+        let forward = transform.vector(matrix.vector(Vec3::new(0., 0., -1.)));
+        let up = transform.vector(matrix.vector(Vec3::new(0., 1., 0.)));
+        (position, forward.unit_vector(), up.unit_vector())
+    }
+
+    fn load_camera(&mut self, index: usize, matrix: Matrix) -> Result<()> {
+        let camera = self
+            .document
+            .cameras
+            .get(index)
+            .ok_or_else(|| anyhow!("There is no camera {}", index))?;
+        let (position, forward, up) = self.placement(matrix);
+        let (fov, height) = match camera {
+            Camera::Perspective { perspective } => (Some(perspective.yfov.to_degrees()), 0.),
+            Camera::Orthographic { orthographic } => {
+                let scale =
+                    self.transform.scale.y().abs() * matrix.vector(Vec3::new(0., 1., 0.)).length();
+                (None, 2. * orthographic.ymag * scale)
+            }
+        };
+        self.scene.cameras.push(GltfCamera {
+            position,
+            forward,
+            up,
+            fov,
+            height,
+        });
+        Ok(())
+    }
+
+    fn load_light(&mut self, index: usize, matrix: Matrix) -> Result<()> {
+        let light = self
+            .document
+            .extensions
+            .as_ref()
+            .and_then(|extensions| extensions.lights.as_ref())
+            .and_then(|lights| lights.lights.get(index))
+            .ok_or_else(|| anyhow!("There is no light {}", index))?;
+        let (position, direction, _) = self.placement(matrix);
+        let [r, g, b] = light.color.unwrap_or([1., 1., 1.]);
+        let color = Color::new(r, g, b);
+        let intensity = light.intensity.unwrap_or(1.);
+        let light = match light.kind.as_str() {
+            "point" => Light::Point {
+                position,
+                color,
+                intensity,
+            },
+            "spot" => {
+                let spot = light.spot.as_ref();
+                Light::Spot {
+                    position,
+                    direction,
+                    color,
+                    intensity,
+                    inner_angle: spot.map_or(0., |spot| spot.inner_cone_angle.to_degrees()),
+                    outer_angle: spot
+                        .and_then(|spot| spot.outer_cone_angle)
+                        .unwrap_or(PI / 4.)
+                        .to_degrees(),
+                }
+            }
+            "directional" => Light::Directional {
+                direction,
+                color,
+                intensity,
+            },
+            other => return Err(anyhow!("Unknown light type '{}'", other)),
+        };
+        self.scene.lights.push(light);
+        Ok(())
+    }
+
+    fn load_mesh(&mut self, index: usize, matrix: Matrix) -> Result<()> {
+        let primitives = self
+            .document
+            .meshes
+            .get(index)
+            .ok_or_else(|| anyhow!("There is no mesh {}", index))?
+            .primitives
+            .len();
+        for primitive in 0..primitives {
+            let material = self.document.meshes[index].primitives[primitive].material;
+            let (material, texture) = self.material(material)?;
+            let primitive = &self.document.meshes[index].primitives[primitive];
+            let mesh =
+                match self.triangles(primitive, texture.as_ref().map(|t| t.tex_coord), matrix)? {
+                    Some(mesh) => mesh.transformed(self.transform),
+                    None => continue,
+                };
+            self.scene.objects.push(Object::Mesh(Mesh::from_triangles(
+                self.path.to_path_buf(),
+                mesh,
+                material,
+                texture.and_then(|t| t.image),
+            )));
+        }
+        Ok(())
+    }
+
+    /// The triangles of a primitive moved by the matrix, or None for points and lines
+    fn triangles(
+        &self,
+        primitive: &Primitive,
+        tex_coord: Option<usize>,
+        matrix: Matrix,
+    ) -> Result<Option<TriangleMesh>> {
+        let mode = primitive.mode.unwrap_or(4);
+        if !(4..=6).contains(&mode) {
+            return Ok(None);
+        }
+        // Each element of an attribute with at least the given number of components
+        let attribute = |name: &str, components: usize| -> Result<Option<Vec<Vec<f32>>>> {
+            primitive
+                .attributes
+                .get(name)
+                .map(|&accessor| {
+                    let elements = self.read(accessor)?;
+                    if elements.first().is_some_and(|v| v.len() < components) {
+                        return Err(anyhow!(
+                            "{} needs at least {} components, but accessor {} has {}",
+                            name,
+                            components,
+                            accessor,
+                            elements[0].len()
+                        ));
+                    }
+                    Ok(elements
+                        .into_iter()
+                        .map(|v| v.into_iter().map(|x| x as f32).collect())
+                        .collect())
+                })
+                .transpose()
+        };
+        let vec3 = |v: &Vec<f32>| Vec3::new(v[0], v[1], v[2]);
+
+        let positions =
+            attribute("POSITION", 3)?.ok_or_else(|| anyhow!("A primitive has no positions"))?;
+        let (normal_columns, determinant) = matrix.normal_columns();
+        let mut mesh = TriangleMesh {
+            positions: positions.iter().map(|v| matrix.point(vec3(v))).collect(),
+            ..TriangleMesh::default()
+        };
+        if let Some(normals) = attribute("NORMAL", 3)? {
+            mesh.normals = normals
+                .iter()
+                .map(|v| {
+                    let [a, b, c] = normal_columns;
+                    (v[0] * a + v[1] * b + v[2] * c).unit_vector() * determinant.signum()
+                })
+                .collect();
+        }
+        let tex_coord = format!("TEXCOORD_{}", tex_coord.unwrap_or(0));
+        if let Some(uvs) = attribute(&tex_coord, 2)? {
+            // glTF puts v = 0 at the top of images, the renderer at the bottom
+            mesh.uvs = uvs.iter().map(|v| (v[0], 1. - v[1])).collect();
+        }
+        if let Some(colors) = attribute("COLOR_0", 3)? {
+            mesh.colors = colors.iter().map(vec3).collect();
+        }
+
+        let indices: Vec<usize> = match primitive.indices {
+            Some(accessor) => self
+                .read(accessor)?
+                .into_iter()
+                .map(|v| v[0] as usize)
+                .collect(),
+            None => (0..mesh.positions.len()).collect(),
+        };
+        let triangle = |a: usize, b: usize, c: usize| [indices[a], indices[b], indices[c]];
+        let count = indices.len();
+        mesh.triangles = match mode {
+            4 => (0..count / 3)
+                .map(|i| triangle(3 * i, 3 * i + 1, 3 * i + 2))
+                .collect(),
+            // Strips alternate their winding
+            5 => (0..count.saturating_sub(2))
+                .map(|i| match i % 2 {
+                    0 => triangle(i, i + 1, i + 2),
+                    _ => triangle(i + 1, i, i + 2),
+                })
+                .collect(),
+            _ => (1..count.saturating_sub(1))
+                .map(|i| triangle(0, i, i + 1))
+                .collect(),
+        };
+        // Mirroring turns the triangles inside out
+        if determinant < 0. {
+            for triangle in &mut mesh.triangles {
+                triangle.swap(1, 2);
+            }
+        }
+        mesh.check()?;
+        Ok(Some(mesh))
+    }
+
+    /// The renderer's closest material with its normal map, and its base color texture
+    fn material(&mut self, index: Option<usize>) -> Result<(Material, Option<BaseColor>)> {
+        let default = GltfMaterial::default();
+        let material = match index {
+            Some(index) => self
+                .document
+                .materials
+                .get(index)
+                .ok_or_else(|| anyhow!("There is no material {}", index))?,
+            None => &default,
+        };
+        let pbr = &material.pbr_metallic_roughness;
+        let [r, g, b, _] = pbr.base_color_factor;
+        let albedo = Color::new(r, g, b);
+        let transmission = material
+            .extensions
+            .transmission
+            .as_ref()
+            .map_or(0., |t| t.transmission_factor);
+        let ior = material.extensions.ior.as_ref().map_or(1.5, |ior| ior.ior);
+        let (metallic, fuzz) = (pbr.metallic_factor, pbr.roughness_factor.clamp(0., 1.));
+
+        let texture = pbr
+            .base_color_texture
+            .as_ref()
+            .map(|info| (info.index, info.tex_coord));
+        let normal = material
+            .normal_texture
+            .as_ref()
+            .map(|info| (info.index, info.tex_coord, info.scale));
+        let mut texture = match texture {
+            Some((index, tex_coord)) => Some(BaseColor {
+                image: self.texture(index, true)?,
+                tex_coord,
+            }),
+            None => None,
+        };
+        let mut maps = SurfaceMaps::default();
+        if let Some((index, tex_coord, scale)) = normal {
+            if let Some(image) = self.texture(index, false)? {
+                maps = SurfaceMaps::with_normal_image(image, scale);
+            }
+            // Meshes only keep one set of surface coordinates, so the base color's wins
+            texture.get_or_insert(BaseColor {
+                image: None,
+                tex_coord,
+            });
+        }
+
+        let result = if transmission >= 0.5 {
+            Material::Dielectric(Dielectric {
+                refractive_index: ior,
+                maps,
+            })
+        } else if metallic >= 0.5 {
+            Material::Metal(Metal { albedo, fuzz, maps })
+        } else {
+            Material::Lambertian(Lambertian { albedo, maps })
+        };
+        Ok((result, texture))
+    }
+
+    /// The image of a texture, in linear color if it holds colors, or None if it cannot be read
+    fn texture(&mut self, index: usize, color: bool) -> Result<Option<Arc<Image>>> {
+        if let Some(image) = self.textures.get(&(index, color)) {
+            return Ok(image.clone());
+        }
+        let source = self
+            .document
+            .textures
+            .get(index)
+            .ok_or_else(|| anyhow!("There is no texture {}", index))?
+            .source;
+        let image = match source.and_then(|source| self.document.images.get(source)) {
+            Some(image) => self.image(&image.clone(), color)?,
+            None => None,
+        };
+        self.textures.insert((index, color), image.clone());
+        Ok(image)
+    }
+
+    fn image(&mut self, image: &GltfImage, color: bool) -> Result<Option<Arc<Image>>> {
+        let (bytes, kind) = match (&image.uri, image.buffer_view) {
+            (Some(uri), _) => {
+                let kind = match uri.strip_prefix("data:") {
+                    Some(data) => data.split(';').next().unwrap_or("").to_string(),
+                    None => uri.rsplit('.').next().unwrap_or("").to_lowercase(),
+                };
+                (read_uri(Some(uri), self.base)?, kind)
+            }
+            (None, Some(view)) => (
+                self.view(view)?.to_vec(),
+                image.mime_type.clone().unwrap_or_default(),
+            ),
+            (None, None) => return Ok(None),
+        };
+        let extension = match kind.as_str() {
+            "image/png" | "png" => "png",
+            "image/bmp" | "bmp" => "bmp",
+            _ => {
+                self.scene.warnings.push(format!(
+                    "Leaving out a {} texture, only PNG and BMP textures can be read",
+                    kind
+                ));
+                return Ok(None);
+            }
+        };
+        let image = Image::decode_raw(&bytes, extension)?;
+        Ok(Some(Arc::new(if color {
+            image.linearized()
+        } else {
+            image
+        })))
+    }
+
+    fn view(&self, index: usize) -> Result<&[u8]> {
+        let view = self
+            .document
+            .buffer_views
+            .get(index)
+            .ok_or_else(|| anyhow!("There is no buffer view {}", index))?;
+        self.buffers
+            .get(view.buffer)
+            .and_then(|buffer| buffer.get(view.byte_offset..view.byte_offset + view.byte_length))
+            .ok_or_else(|| anyhow!("Buffer view {} lies outside of its buffer", index))
+    }
+
+    /// The elements of an accessor, each a list of its components
+    fn read(&self, index: usize) -> Result<Vec<Vec<f64>>> {
+        let accessor = self
+            .document
+            .accessors
+            .get(index)
+            .ok_or_else(|| anyhow!("There is no accessor {}", index))?;
+        let components = match accessor.kind.as_str() {
+            "SCALAR" => 1,
+            "VEC2" => 2,
+            "VEC3" => 3,
+            "VEC4" => 4,
+            other => return Err(anyhow!("Accessors of {} cannot be read", other)),
+        };
+        let size = match accessor.component_type {
+            5120 | 5121 => 1,
+            5122 | 5123 => 2,
+            5125 | 5126 => 4,
+            other => return Err(anyhow!("Unknown component type {}", other)),
+        };
+        let view_index = match accessor.buffer_view {
+            Some(view) => view,
+            None => return Ok(vec![vec![0.; components]; accessor.count]),
+        };
+        let bytes = self.view(view_index)?;
+        let stride = self.document.buffer_views[view_index]
+            .byte_stride
+            .unwrap_or(size * components);
+
+        let mut elements = Vec::with_capacity(accessor.count);
+        for element in 0..accessor.count {
+            let start = accessor.byte_offset + element * stride;
+            let values = (0..components)
+                .map(|component| {
+                    let offset = start + component * size;
+                    let bytes = bytes
+                        .get(offset..offset + size)
+                        .ok_or_else(|| anyhow!("Accessor {} lies outside of its view", index))?;
+                    Ok(component_value(
+                        accessor.component_type,
+                        accessor.normalized,
+                        bytes,
+                    ))
+                })
+                .collect::<Result<Vec<_>>>()?;
+            elements.push(values);
+        }
+        Ok(elements)
+    }
+}
+
+fn component_value(component_type: u32, normalized: bool, bytes: &[u8]) -> f64 {
+    let (value, max) = match component_type {
+        5120 => (bytes[0] as i8 as f64, 127.),
+        5121 => (bytes[0] as f64, 255.),
+        5122 => (i16::from_le_bytes([bytes[0], bytes[1]]) as f64, 32767.),
+        5123 => (u16::from_le_bytes([bytes[0], bytes[1]]) as f64, 65535.),
+        5125 => (
+            u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64,
+            1.,
+        ),
+        _ => (
+            f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64,
+            1.,
+        ),
+    };
+    if normalized {
+        (value / max).max(-1.)
+    } else {
+        value
+    }
+}
+
+/// The bytes of a file next to the glTF file or of a base64 data URI
+fn read_uri(uri: Option<&str>, base: &Path) -> Result<Vec<u8>> {
+    let uri = uri.ok_or_else(|| anyhow!("Binary .glb buffers are not supported"))?;
+    match uri.strip_prefix("data:") {
+        Some(data) => {
+            let (_, encoded) = data
+                .split_once(";base64,")
+                .ok_or_else(|| anyhow!("Only base64 data URIs are supported"))?;
+            decode_base64(encoded)
+        }
+        None => {
+            let path = base.join(uri.replace("%20", " "));
+            fs::read(&path).map_err(|e| anyhow!("Cannot read {}: {}", path.display(), e))
+        }
+    }
+}
+
+fn decode_base64(text: &str) -> Result<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(text.len() * 3 / 4);
+    let (mut buffer, mut bits) = (0u32, 0);
+    for c in text.bytes().filter(|c| !c.is_ascii_whitespace()) {
+        let value = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' | b'-' => 62,
+            b'/' | b'_' => 63,
+            b'=' => break,
+            _ => return Err(anyhow!("'{}' is not base64", c as char)),
+        };
+        buffer = (buffer << 6) | value as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((buffer >> bits) as u8);
+            buffer &= (1 << bits) - 1;
+        }
+    }
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object::Hittable;
+    use crate::ray::Ray;
+
+    #[test]
+    fn test_decode_base64() {
+        assert_eq!(decode_base64("aGVsbG8gd29ybGQ=").unwrap(), b"hello world");
+        assert_eq!(decode_base64("AAEC").unwrap(), vec![0, 1, 2]);
+    }
+
+    /// A triangle in a child node moved up by its parent, with a point light and a camera
+    const TRIANGLE: &str = r#"{
+        "asset": {"version": "2.0"},
+        "scene": 0,
+        "scenes": [{"nodes": [0]}],
+        "nodes": [
+            {"translation": [0, 2, 0], "children": [1, 2, 3]},
+            {"mesh": 0, "scale": [2, 2, 2]},
+            {"camera": 0, "translation": [0, 0, 5]},
+            {"extensions": {"KHR_lights_punctual": {"light": 0}}, "translation": [0, 0, 1]}
+        ],
+        "meshes": [{"primitives": [{"attributes": {"POSITION": 0}, "material": 0}]}],
+        "materials": [{"pbrMetallicRoughness": {
+            "baseColorFactor": [0.8, 0.2, 0.2, 1], "metallicFactor": 0
+        }}],
+        "cameras": [{"type": "perspective", "perspective": {"yfov": 0.5, "znear": 0.1}}],
+        "extensions": {"KHR_lights_punctual": {"lights": [{"type": "point", "intensity": 3}]}},
+        "accessors": [{
+            "bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3"
+        }],
+        "bufferViews": [{"buffer": 0, "byteLength": 36}],
+        "buffers": [{"byteLength": 36, "uri": "data:application/octet-stream;base64,AAAAAAAAAAAAAAAAAACAPwAAAAAAAAAAAAAAAAAAgD8AAAAA"}]
+    }"#;
+
+    #[test]
+    fn test_load_nodes() {
+        let directory = std::env::temp_dir().join("raytracer_gltf_test");
+        fs::create_dir_all(&directory).unwrap();
+        fs::write(directory.join("triangle.gltf"), TRIANGLE).unwrap();
+        let import = GltfImport {
+            path: "triangle.gltf".into(),
+            transform: Transform::default(),
+            use_camera: true,
+        };
+        let scene = import.load(&directory).unwrap();
+
+        // The triangle spans (0, 2, 0), (2, 2, 0) and (0, 4, 0)
+        let ray = Ray::new(Vec3::new(0.5, 2.5, 3.), Vec3::new(0., 0., -1.));
+        let record = scene.objects[0].hit(ray, 0., f32::MAX).unwrap();
+        assert!((record.point() - Vec3::new(0.5, 2.5, 0.)).length() < 1e-5);
+        assert_eq!(
+            *record.material(),
+            Material::Lambertian(Lambertian {
+                albedo: Color::new(0.8, 0.2, 0.2),
+                maps: SurfaceMaps::default(),
+            })
+        );
+        let miss = Ray::new(Vec3::new(1.5, 3.5, 3.), Vec3::new(0., 0., -1.));
+        assert!(scene.objects[0].hit(miss, 0., f32::MAX).is_none());
+
+        assert_eq!(
+            scene.lights,
+            vec![Light::Point {
+                position: Vec3::new(0., 2., 1.),
+                color: Color::new(1., 1., 1.),
+                intensity: 3.,
+            }]
+        );
+        let camera = &scene.cameras[0];
+        assert_eq!(camera.position, Vec3::new(0., 2., 5.));
+        assert_eq!(camera.forward, Vec3::new(0., 0., -1.));
+        assert!((camera.fov.unwrap() - 0.5f32.to_degrees()).abs() < 1e-4);
+
+        // Positions read as pairs cannot place the triangle
+        let flat = TRIANGLE.replace(
+            "\"count\": 3, \"type\": \"VEC3\"",
+            "\"count\": 4, \"type\": \"VEC2\"",
+        );
+        fs::write(directory.join("flat.gltf"), flat).unwrap();
+        let import = GltfImport {
+            path: "flat.gltf".into(),
+            ..import
+        };
+        assert!(import.load(&directory).is_err());
+    }
+}
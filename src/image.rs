@@ -0,0 +1,603 @@
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+use anyhow::{anyhow, Result};
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+
+use crate::gif;
+use crate::png::{self, Chunk, ChunkType};
+use crate::tile::Tile;
+use crate::vec3::Color;
+
+/// A rendered image, stored row by row from the top left in linear color.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Image {
+    width: i32,
+    height: i32,
+    pixels: Vec<Color>,
+}
+
+impl Image {
+    /// Create a new black image
+    pub fn new(width: i32, height: i32) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![Color::new(0., 0., 0.); (width.max(0) * height.max(0)) as usize],
+        }
+    }
+
+    pub fn width(&self) -> i32 {
+        self.width
+    }
+
+    pub fn height(&self) -> i32 {
+        self.height
+    }
+
+    pub fn pixels(&self) -> &[Color] {
+        &self.pixels
+    }
+
+    pub fn get(&self, x: i32, y: i32) -> Color {
+        self.pixels[(y * self.width + x) as usize]
+    }
+
+    pub fn set(&mut self, x: i32, y: i32, color: Color) {
+        self.pixels[(y * self.width + x) as usize] = color;
+    }
+
+    /// Copy the pixels of a rendered tile into the image, offset by (-dx, -dy)
+    pub fn write_tile(&mut self, tile: Tile, pixels: &[Color], dx: i32, dy: i32) {
+        for row in 0..tile.height {
+            for column in 0..tile.width {
+                let x = tile.x + column - dx;
+                let y = tile.y + row - dy;
+                if (0..self.width).contains(&x) && (0..self.height).contains(&y) {
+                    self.set(x, y, pixels[(row * tile.width + column) as usize]);
+                }
+            }
+        }
+    }
+
+    /// A new image with the other image placed to the right of this one
+    #[must_use]
+    pub fn beside(&self, other: &Image) -> Image {
+        let mut image = Image::new(self.width + other.width, self.height.max(other.height));
+        image.paste(self, 0, 0);
+        image.paste(other, self.width, 0);
+        image
+    }
+
+    /// A new image with the other image placed below this one
+    #[must_use]
+    pub fn above(&self, other: &Image) -> Image {
+        let mut image = Image::new(self.width.max(other.width), self.height + other.height);
+        image.paste(self, 0, 0);
+        image.paste(other, 0, self.height);
+        image
+    }
+
+    fn paste(&mut self, other: &Image, x: i32, y: i32) {
+        let tile = Tile::new(x, y, other.width, other.height);
+        self.write_tile(tile, &other.pixels, 0, 0);
+    }
+
+    /// Gamma corrected 8-bit RGB triplets, row by row from the top left
+    pub fn to_rgb8(&self) -> Vec<[u8; 3]> {
+        self.pixels
+            .iter()
+            .map(|&pixel| {
+                let mut pixel = pixel;
+                pixel.correct_color(1.);
+                [pixel.r(), pixel.g(), pixel.b()]
+            })
+            .collect()
+    }
+
+    /// Load an image saved as ppm, bmp or png, undoing the gamma correction of `save` so the
+    /// colors are linear again
+    pub fn load(filepath: &Path) -> Result<Image> {
+        Ok(Image::load_raw(filepath)?.linearized())
+    }
+
+    /// Load an image with its stored values in [0, 1], for data like masks and height maps
+    pub fn load_raw(filepath: &Path) -> Result<Image> {
+        let bytes = fs::read(filepath)
+            .map_err(|e| anyhow!("Could not read {}: {}", filepath.display(), e))?;
+        let extension = filepath.extension().and_then(|s| s.to_str()).unwrap_or("");
+        Image::decode_raw(&bytes, extension)
+    }
+
+    /// Read an image file already in memory, with the extension its format would have, and
+    /// its stored values in [0, 1]
+    pub fn decode_raw(bytes: &[u8], extension: &str) -> Result<Image> {
+        let (width, height, pixels) = match extension {
+            "png" => png::decode(bytes)?,
+            "bmp" => decode_bmp(bytes)?,
+            "ppm" => decode_ppm(bytes)?,
+            _ => return Err(anyhow!("Unsupported filetype!")),
+        };
+        Ok(Image {
+            width: width as i32,
+            height: height as i32,
+            pixels,
+        })
+    }
+
+    /// Undo the gamma correction of `save`, so the colors are linear again
+    #[must_use]
+    pub fn linearized(mut self) -> Image {
+        for pixel in &mut self.pixels {
+            *pixel = *pixel * *pixel;
+        }
+        self
+    }
+
+    /// The color at (u, v) in [0, 1], with (0, 0) at the bottom left, of the nearest pixel
+    pub fn sample(&self, u: f32, v: f32) -> Color {
+        let x = ((u * self.width as f32) as i32).clamp(0, self.width - 1);
+        let y = (((1. - v) * self.height as f32) as i32).clamp(0, self.height - 1);
+        self.get(x, y)
+    }
+
+    /// The color at (u, v) like `sample`, blended between the four nearest pixel centers so
+    /// it changes smoothly, e.g. for the slopes of bump maps
+    pub fn sample_bilinear(&self, u: f32, v: f32) -> Color {
+        let x = u * self.width as f32 - 0.5;
+        let y = (1. - v) * self.height as f32 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let pixel = |dx: i32, dy: i32| {
+            self.get(
+                (x0 as i32 + dx).clamp(0, self.width - 1),
+                (y0 as i32 + dy).clamp(0, self.height - 1),
+            )
+        };
+        let top = (1. - fx) * pixel(0, 0) + fx * pixel(1, 0);
+        let bottom = (1. - fx) * pixel(0, 1) + fx * pixel(1, 1);
+        (1. - fy) * top + fy * bottom
+    }
+
+    pub fn save(&self, filepath: &Path) -> Result<()> {
+        let file = File::create(filepath)?;
+
+        if let Some(ext) = filepath.extension().and_then(|s| s.to_str()) {
+            match ext {
+                "ppm" => self.save_as_ppm(file)?,
+                "bmp" => self.save_as_bmp(file)?,
+                "png" => self.save_as_png(file)?,
+                _ => return Err(anyhow!("Unsupported filetype!")),
+            }
+        } else {
+            println!("No filetype given, defaulting to ppm...");
+            self.save_as_ppm(file)?;
+        }
+
+        Ok(())
+    }
+
+    fn save_as_ppm<W: Write>(&self, writable: W) -> Result<()> {
+        let mut file = BufWriter::new(writable);
+
+        // Write header
+        writeln!(file, "P3")?;
+        writeln!(file, "{} {}", self.width, self.height)?;
+        writeln!(file, "{}", 255)?; // Maximum color
+
+        // Write pixels
+        for [r, g, b] in self.to_rgb8() {
+            writeln!(file, "{} {} {}", r, g, b)?;
+        }
+
+        Ok(())
+    }
+
+    fn save_as_bmp<W: Write>(&self, writable: W) -> Result<()> {
+        let mut file = BufWriter::new(writable);
+
+        let mut bitmap_file_header: [u8; 14] = [
+            0x42, 0x4D, // BM marker
+            0xFF, 0xFF, 0xFF, 0xFF, // BMP file size in bytes
+            0x00, 0x00, 0x00, 0x00, // Reserved
+            0x36, 0x00, 0x00, 0x00, // Byte offset of the pixel array
+        ];
+        let mut dib_header: [u8; 40] = [
+            0x28, 0x00, 0x00, 0x00, // DIB header size in bytes
+            0xFF, 0xFF, 0xFF, 0xFF, // Bitmap pixel width
+            0xFF, 0xFF, 0xFF, 0xFF, // Bitmap pixel height
+            0x01, 0x00, // Number of color planes
+            0xFF, 0xFF, // Number of bits per pixel
+            0x00, 0x00, 0x00, 0x00, // BI_RGB, no pixel array compression
+            0xFF, 0xFF, 0xFF, 0xFF, // Size of raw bitmap data including padding
+            0x00, 0x00, 0x00, 0x00, // Print resolution (vertical)
+            0x00, 0x00, 0x00, 0x00, // Print resolution (horizontal)
+            0x00, 0x00, 0x00, 0x00, // Number of colors in the palette
+            0x00, 0x00, 0x00, 0x00, // Important colors (0 means all important)
+        ];
+
+        let bits_per_pixel: i32 = 24;
+        let row_size = (((bits_per_pixel * self.width) as f32 / 32.).ceil() * 4.) as usize;
+        let data_size = row_size * self.height as usize;
+
+        dib_header[4..=7].copy_from_slice(&(self.width as u32).to_le_bytes());
+        dib_header[8..=11].copy_from_slice(&(self.height as u32).to_le_bytes());
+        dib_header[14..=15].copy_from_slice(&(bits_per_pixel as u16).to_le_bytes());
+        dib_header[20..=23].copy_from_slice(&(data_size as u32).to_le_bytes());
+
+        let total_size = data_size + dib_header.len() + bitmap_file_header.len();
+        bitmap_file_header[2..=5].copy_from_slice(&(total_size as u32).to_le_bytes());
+
+        file.write_all(&bitmap_file_header)?;
+        file.write_all(&dib_header)?;
+
+        // BMP rows are stored bottom-up, each padded to a multiple of 4 bytes
+        let padding_required = (4 - (3 * self.width) % 4) % 4;
+        let width = self.width.max(1) as usize;
+        for row in self.to_rgb8().chunks(width).rev() {
+            for [r, g, b] in row {
+                file.write_all(&[*b, *g, *r])?;
+            }
+            for _ in 0..padding_required {
+                file.write_all(&[0_u8])?;
+            }
+        }
+
+        Ok(())
+    }
+
+    // PNG Format taken from: http://www.libpng.org/pub/png/spec/1.2/PNG-Contents.html
+    // PNG Chunk/ChunkType scaffold + tests taken from https://picklenerd.github.io/pngme_book/
+    fn save_as_png<W: Write>(&self, writable: W) -> Result<()> {
+        let mut file = BufWriter::new(writable);
+
+        // Write PNG Header
+        file.write_all(&PNG_SIGNATURE)?;
+
+        // Write IHDR chunk
+        file.write_all(&self.png_header().as_bytes())?;
+
+        let compressed = self.png_image_data()?;
+        file.write_all(&Chunk::new(ChunkType::from_str("IDAT").unwrap(), compressed).as_bytes())?;
+
+        // Write IEND chunk
+        file.write_all(&Chunk::new(ChunkType::from_str("IEND").unwrap(), [].to_vec()).as_bytes())?;
+
+        Ok(())
+    }
+
+    fn png_header(&self) -> Chunk {
+        let mut ihdr_data: [u8; 13] = [
+            0xFF, 0xFF, 0xFF, 0xFF, // Pixel width
+            0xFF, 0xFF, 0xFF, 0xFF, // Pixel height
+            0x8,  // Bit depth (number of bits per sample, NOT per pixel)
+            0x2,  // Color type
+            0x0,  // Compression method
+            0x0,  // Filter method
+            0x0,  // Interlace method
+        ];
+        ihdr_data[0..=3].copy_from_slice(&(self.width as u32).to_be_bytes());
+        ihdr_data[4..=7].copy_from_slice(&(self.height as u32).to_be_bytes());
+
+        Chunk::new(ChunkType::from_str("IHDR").unwrap(), ihdr_data.to_vec())
+    }
+
+    /// The compressed scanlines of the image, as stored in IDAT chunks
+    fn png_image_data(&self) -> Result<Vec<u8>> {
+        let mut e = ZlibEncoder::new(Vec::new(), Compression::default());
+
+        let width = self.width.max(1) as usize;
+        for row in self.to_rgb8().chunks(width) {
+            // Write filter-type byte every row
+            e.write_all(&[0])?;
+            for pixel in row {
+                e.write_all(pixel)?;
+            }
+        }
+
+        Ok(e.finish()?)
+    }
+}
+
+const PNG_SIGNATURE: [u8; 8] = [137, 80, 78, 71, 13, 10, 26, 10];
+
+/// How a sequence of images is played back
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AnimationOptions {
+    pub frames_per_second: f32,
+    /// How many times the animation plays, or 0 to loop forever
+    pub loops: u16,
+    /// Dither GIF frames, trading banding for noise
+    pub dither: bool,
+}
+
+impl Default for AnimationOptions {
+    fn default() -> Self {
+        Self {
+            frames_per_second: 24.,
+            loops: 0,
+            dither: false,
+        }
+    }
+}
+
+impl AnimationOptions {
+    /// Whether the frames can be timed, which needs a positive frame rate
+    pub fn check(&self) -> Result<()> {
+        if !self.frames_per_second.is_finite() || self.frames_per_second <= 0. {
+            return Err(anyhow!(
+                "The frames per second must be positive, got {}",
+                self.frames_per_second
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Save a sequence of images of the same size as an animated png or gif
+pub fn save_animation(frames: &[Image], filepath: &Path, options: AnimationOptions) -> Result<()> {
+    options.check()?;
+    let first = frames
+        .first()
+        .ok_or_else(|| anyhow!("An animation needs at least one frame"))?;
+    if frames
+        .iter()
+        .any(|frame| (frame.width, frame.height) != (first.width, first.height))
+    {
+        return Err(anyhow!(
+            "All frames of an animation must have the same size"
+        ));
+    }
+
+    let file = File::create(filepath)?;
+    match filepath.extension().and_then(|s| s.to_str()) {
+        Some("png") => save_as_apng(frames, file, options),
+        Some("gif") => {
+            let mut file = BufWriter::new(file);
+            file.write_all(&gif::encode(frames, options)?)?;
+            Ok(())
+        }
+        _ => Err(anyhow!("Animations can only be saved as png or gif")),
+    }
+}
+
+// APNG extension taken from: https://wiki.mozilla.org/APNG_Specification
+fn save_as_apng<W: Write>(frames: &[Image], writable: W, options: AnimationOptions) -> Result<()> {
+    let mut file = BufWriter::new(writable);
+    let first = &frames[0];
+
+    file.write_all(&PNG_SIGNATURE)?;
+    file.write_all(&first.png_header().as_bytes())?;
+
+    // Write acTL chunk
+    let mut actl_data = Vec::with_capacity(8);
+    actl_data.extend_from_slice(&(frames.len() as u32).to_be_bytes()); // Number of frames
+    actl_data.extend_from_slice(&(options.loops as u32).to_be_bytes()); // Number of plays
+    file.write_all(&Chunk::new(ChunkType::from_str("acTL").unwrap(), actl_data).as_bytes())?;
+
+    // The delay as a fraction of a second, in milliseconds
+    let delay = (1000. / options.frames_per_second).round() as u16;
+
+    // fcTL and fdAT chunks share one sequence
+    let mut sequence: u32 = 0;
+    for (index, frame) in frames.iter().enumerate() {
+        let mut fctl_data: [u8; 26] = [
+            0xFF, 0xFF, 0xFF, 0xFF, // Sequence number
+            0xFF, 0xFF, 0xFF, 0xFF, // Width
+            0xFF, 0xFF, 0xFF, 0xFF, // Height
+            0x00, 0x00, 0x00, 0x00, // X offset
+            0x00, 0x00, 0x00, 0x00, // Y offset
+            0xFF, 0xFF, // Delay numerator
+            0x03, 0xE8, // Delay denominator, 1000
+            0x0,  // Dispose op, none
+            0x0,  // Blend op, source
+        ];
+        fctl_data[0..=3].copy_from_slice(&sequence.to_be_bytes());
+        fctl_data[4..=7].copy_from_slice(&(frame.width as u32).to_be_bytes());
+        fctl_data[8..=11].copy_from_slice(&(frame.height as u32).to_be_bytes());
+        fctl_data[20..=21].copy_from_slice(&delay.to_be_bytes());
+        file.write_all(
+            &Chunk::new(ChunkType::from_str("fcTL").unwrap(), fctl_data.to_vec()).as_bytes(),
+        )?;
+        sequence += 1;
+
+        // The first frame is the default image, seen by decoders without APNG support
+        let compressed = frame.png_image_data()?;
+        if index == 0 {
+            file.write_all(
+                &Chunk::new(ChunkType::from_str("IDAT").unwrap(), compressed).as_bytes(),
+            )?;
+        } else {
+            let fdat_data = [&sequence.to_be_bytes()[..], &compressed].concat();
+            file.write_all(
+                &Chunk::new(ChunkType::from_str("fdAT").unwrap(), fdat_data).as_bytes(),
+            )?;
+            sequence += 1;
+        }
+    }
+
+    file.write_all(&Chunk::new(ChunkType::from_str("IEND").unwrap(), [].to_vec()).as_bytes())?;
+
+    Ok(())
+}
+
+/// Decode a plain (P3) or binary (P6) ppm with 8-bit samples
+fn decode_ppm(bytes: &[u8]) -> Result<(usize, usize, Vec<Color>)> {
+    // The header is whitespace separated, with comments running to the end of the line
+    let mut position = 0;
+    let next_token = |position: &mut usize| -> Option<String> {
+        loop {
+            while bytes.get(*position)?.is_ascii_whitespace() {
+                *position += 1;
+            }
+            if bytes[*position] != b'#' {
+                break;
+            }
+            while *bytes.get(*position)? != b'\n' {
+                *position += 1;
+            }
+        }
+        let start = *position;
+        while bytes
+            .get(*position)
+            .is_some_and(|b| !b.is_ascii_whitespace())
+        {
+            *position += 1;
+        }
+        Some(String::from_utf8_lossy(&bytes[start..*position]).into_owned())
+    };
+
+    let truncated = || anyhow!("Truncated ppm header");
+    let magic = next_token(&mut position).ok_or_else(truncated)?;
+    let width: usize = next_token(&mut position).ok_or_else(truncated)?.parse()?;
+    let height: usize = next_token(&mut position).ok_or_else(truncated)?.parse()?;
+    let max: f32 = next_token(&mut position).ok_or_else(truncated)?.parse()?;
+    if max > 255. {
+        return Err(anyhow!("Only 8-bit ppm files are supported"));
+    }
+
+    let samples: Vec<f32> = match magic.as_str() {
+        "P3" => std::iter::from_fn(|| next_token(&mut position))
+            .map(|token| token.parse::<f32>())
+            .collect::<Result<_, _>>()?,
+        // A single whitespace byte separates the header from the samples
+        "P6" => bytes
+            .get(position + 1..)
+            .unwrap_or_default()
+            .iter()
+            .map(|&byte| byte as f32)
+            .collect(),
+        _ => return Err(anyhow!("Not a ppm file")),
+    };
+    if samples.len() < width * height * 3 {
+        return Err(anyhow!("Truncated ppm pixels"));
+    }
+    let pixels = samples
+        .chunks(3)
+        .take(width * height)
+        .map(|rgb| Color::new(rgb[0] / max, rgb[1] / max, rgb[2] / max))
+        .collect();
+    Ok((width, height, pixels))
+}
+
+/// Decode an uncompressed 24 or 32-bit bmp
+fn decode_bmp(bytes: &[u8]) -> Result<(usize, usize, Vec<Color>)> {
+    let read_u32 = |at: usize| -> Result<u32> {
+        let word = bytes
+            .get(at..at + 4)
+            .ok_or_else(|| anyhow!("Truncated bmp header"))?;
+        Ok(u32::from_le_bytes([word[0], word[1], word[2], word[3]]))
+    };
+    if !bytes.starts_with(b"BM") {
+        return Err(anyhow!("Not a bmp file"));
+    }
+
+    let offset = read_u32(10)? as usize;
+    let width = read_u32(18)? as i32;
+    // A negative height means the rows are stored top-down
+    let height = read_u32(22)? as i32;
+    let bits_per_pixel = read_u32(28)? & 0xFFFF;
+    let compression = read_u32(30)?;
+    if !matches!(bits_per_pixel, 24 | 32) || !matches!(compression, 0 | 3) {
+        return Err(anyhow!(
+            "Only uncompressed 24 and 32-bit bmp files are supported"
+        ));
+    }
+
+    let (width, rows) = (
+        width.unsigned_abs() as usize,
+        height.unsigned_abs() as usize,
+    );
+    let pixel_bytes = bits_per_pixel as usize / 8;
+    let row_size = (pixel_bytes * width).div_ceil(4) * 4;
+    let mut pixels = Vec::with_capacity(width * rows);
+    for row in 0..rows {
+        let stored_row = if height > 0 { rows - 1 - row } else { row };
+        let start = offset + stored_row * row_size;
+        let data = bytes
+            .get(start..start + pixel_bytes * width)
+            .ok_or_else(|| anyhow!("Truncated bmp pixels"))?;
+        pixels.extend(data.chunks(pixel_bytes).map(|bgr| {
+            Color::new(
+                bgr[2] as f32 / 255.,
+                bgr[1] as f32 / 255.,
+                bgr[0] as f32 / 255.,
+            )
+        }));
+    }
+    Ok((width, rows, pixels))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_save_and_load_round_trip() {
+        let mut image = Image::new(5, 3);
+        for y in 0..3 {
+            for x in 0..5 {
+                image.set(x, y, Color::new(x as f32 / 4., y as f32 / 2., 0.25));
+            }
+        }
+
+        let directory = std::env::temp_dir();
+        for extension in ["png", "bmp", "ppm"] {
+            let path = directory.join(format!("raytracer_round_trip.{}", extension));
+            image.save(&path).unwrap();
+            let loaded = Image::load(&path).unwrap();
+            fs::remove_file(&path).unwrap();
+
+            assert_eq!((loaded.width(), loaded.height()), (5, 3));
+            for (a, b) in image.pixels().iter().zip(loaded.pixels()) {
+                // Quantized to 8 bits after gamma correction
+                assert!(
+                    (*a - *b).length() < 0.02,
+                    "{}: {:?} != {:?}",
+                    extension,
+                    a,
+                    b
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_apng_shows_first_frame_without_apng_support() {
+        let frames: Vec<Image> = (0..3)
+            .map(|frame| {
+                let mut image = Image::new(2, 2);
+                image.set(0, 0, Color::new(frame as f32 / 2., 0., 0.));
+                image
+            })
+            .collect();
+
+        let path = std::env::temp_dir().join("raytracer_animation.png");
+        save_animation(&frames, &path, AnimationOptions::default()).unwrap();
+        let bytes = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let count = |name: &[u8]| bytes.windows(4).filter(|window| window == &name).count();
+        assert_eq!((count(b"acTL"), count(b"fcTL"), count(b"fdAT")), (1, 3, 2));
+        let (width, height, pixels) = png::decode(&bytes).unwrap();
+        assert_eq!((width, height), (2, 2));
+        assert_eq!(pixels[0], Color::new(0., 0., 0.));
+    }
+
+    #[test]
+    fn test_animation_needs_positive_frame_rate() {
+        let frames = [Image::new(2, 2)];
+        let path = std::env::temp_dir().join("raytracer_frame_rate.gif");
+        for frames_per_second in [0., -24., f32::NAN] {
+            let options = AnimationOptions {
+                frames_per_second,
+                ..AnimationOptions::default()
+            };
+            assert!(save_animation(&frames, &path, options).is_err());
+        }
+        assert!(!path.exists());
+    }
+}
@@ -1,9 +1,18 @@
+pub mod animation;
+pub mod atmosphere;
 pub mod camera;
 pub mod config;
+pub mod distributed;
+mod gif;
+pub mod gltf;
+pub mod image;
+pub mod light;
 pub mod material;
 pub mod object;
 mod png;
+pub mod tile;
 pub mod tracer;
+pub mod validate;
 
 mod primitive;
-pub use primitive::{ray, vec3};
+pub use primitive::{aabb, ray, vec3};
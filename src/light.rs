@@ -0,0 +1,178 @@
+use serde::{Deserialize, Serialize};
+
+use crate::validate::{join, Problems, Validate};
+use crate::vec3::{Color, Point, Vec3};
+
+/// A light too small to be hit by rays, lighting the surfaces that can see it directly
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy)]
+pub enum Light {
+    /// Shines equally in every direction, fading with the square of the distance
+    Point {
+        position: Point,
+        #[serde(default = "default_color")]
+        color: Color,
+        intensity: f32,
+    },
+    /// A point light limited to a cone, fading out between the inner and outer angles
+    Spot {
+        position: Point,
+        direction: Vec3,
+        #[serde(default = "default_color")]
+        color: Color,
+        intensity: f32,
+        /// Angles from the direction in degrees
+        #[serde(default)]
+        inner_angle: f32,
+        #[serde(default = "default_outer_angle")]
+        outer_angle: f32,
+    },
+    /// Parallel light from far away, like the sun, given by the direction it travels in
+    Directional {
+        direction: Vec3,
+        #[serde(default = "default_color")]
+        color: Color,
+        intensity: f32,
+    },
+}
+
+fn default_color() -> Color {
+    Color::new(1., 1., 1.)
+}
+
+fn default_outer_angle() -> f32 {
+    45.
+}
+
+/// Light arriving at a point
+pub struct Illumination {
+    /// The unit direction towards the light
+    pub direction: Vec3,
+    /// How far the light is, infinite for directional lights
+    pub distance: f32,
+    pub color: Color,
+}
+
+impl Light {
+    /// The light reaching the point if nothing is in the way, or None if it is not lit
+    pub fn illuminate(&self, point: Point) -> Option<Illumination> {
+        match *self {
+            Light::Point {
+                position,
+                color,
+                intensity,
+            } => towards(point, position, color * intensity),
+            Light::Spot {
+                position,
+                direction,
+                color,
+                intensity,
+                inner_angle,
+                outer_angle,
+            } => {
+                let illumination = towards(point, position, color * intensity)?;
+                let cos_angle = -illumination.direction.dot(direction.unit_vector());
+                let (cos_inner, cos_outer) = (
+                    inner_angle.to_radians().cos(),
+                    outer_angle.to_radians().cos(),
+                );
+                let falloff = if cos_inner - cos_outer > 1e-6 {
+                    ((cos_angle - cos_outer) / (cos_inner - cos_outer)).clamp(0., 1.)
+                } else {
+                    (cos_angle >= cos_outer) as i32 as f32
+                };
+                (falloff > 0.).then(|| Illumination {
+                    color: illumination.color * falloff * falloff,
+                    ..illumination
+                })
+            }
+            Light::Directional {
+                direction,
+                color,
+                intensity,
+            } => Some(Illumination {
+                direction: -direction.unit_vector(),
+                distance: f32::INFINITY,
+                color: color * intensity,
+            }),
+        }
+    }
+}
+
+fn towards(point: Point, position: Point, color: Color) -> Option<Illumination> {
+    let offset = position - point;
+    let distance = offset.length();
+    (distance > 0.).then(|| Illumination {
+        direction: offset / distance,
+        distance,
+        color: color / (distance * distance),
+    })
+}
+
+impl Validate for Light {
+    fn validate(&self, path: &str, problems: &mut Problems) {
+        let (name, color, intensity) = match *self {
+            Light::Point {
+                color, intensity, ..
+            } => ("Point", color, intensity),
+            Light::Spot {
+                color, intensity, ..
+            } => ("Spot", color, intensity),
+            Light::Directional {
+                color, intensity, ..
+            } => ("Directional", color, intensity),
+        };
+        let path = join(path, name);
+        if color.x() < 0. || color.y() < 0. || color.z() < 0. || intensity < 0. {
+            problems.error(&path, "Lights cannot be negative");
+        }
+        match *self {
+            Light::Spot {
+                direction,
+                inner_angle,
+                outer_angle,
+                ..
+            } => {
+                if direction.is_near_zero() {
+                    problems.error(&join(&path, "direction"), "The direction cannot be zero");
+                }
+                if !(0. ..=180.).contains(&outer_angle)
+                    || !(0. ..=outer_angle).contains(&inner_angle)
+                {
+                    problems.error(
+                        &join(&path, "inner_angle"),
+                        "The angles must be in [0, 180] with the inner angle within the outer one",
+                    );
+                }
+            }
+            Light::Directional { direction, .. } if direction.is_near_zero() => {
+                problems.error(&join(&path, "direction"), "The direction cannot be zero");
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_spot_fades_between_angles() {
+        let spot = Light::Spot {
+            position: Vec3::new(0., 1., 0.),
+            direction: Vec3::new(0., -1., 0.),
+            color: default_color(),
+            intensity: 4.,
+            inner_angle: 20.,
+            outer_angle: 40.,
+        };
+        let below = spot.illuminate(Vec3::new(0., -1., 0.)).unwrap();
+        assert_eq!(below.direction, Vec3::new(0., 1., 0.));
+        assert!((below.color.x() - 1.).abs() < 1e-5);
+
+        let edge = 2. * 30f32.to_radians().tan();
+        let between = spot.illuminate(Vec3::new(edge, -1., 0.)).unwrap();
+        assert!(between.color.x() > 0. && between.color.x() < 1. / (4. + edge * edge) * 4.);
+        assert!(spot.illuminate(Vec3::new(3., -1., 0.)).is_none());
+    }
+}
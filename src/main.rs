@@ -1,43 +1,318 @@
-use std::fs::File;
-use std::io::BufReader;
+use std::fs;
+use std::net::TcpListener;
 use std::path::{Path, PathBuf};
+use std::time::Duration;
 
-use anyhow::Result;
-use clap::{arg, Command};
+use anyhow::{anyhow, Result};
+use clap::{arg, Arg, ArgMatches, Command};
 
-use raytracer::camera::Camera;
+use raytracer::animation;
 use raytracer::config::RaytracerConfig;
+use raytracer::distributed;
+use raytracer::gltf;
+use raytracer::image::{self, AnimationOptions, Image};
 use raytracer::tracer::Tracer;
+use raytracer::validate::{self, Problem, Severity};
+
+/// Flags that override a field of the scene, and the field they set
+const OVERRIDE_FLAGS: [(&str, &str); 9] = [
+    ("width", "image_width"),
+    ("height", "image_height"),
+    ("spp", "samples_per_pixel"),
+    ("max-depth", "max_depth"),
+    ("fov", "viewport_fov"),
+    ("aperture", "aperture"),
+    ("threads", "threads"),
+    ("seed", "seed"),
+    ("tile-size", "tile_size"),
+];
 
 fn main() -> Result<()> {
     let matches = Command::new("raytracer")
-        .arg(arg!(<scene> "The scene to render"))
-        .arg(arg!(-s --save <save> "The path to save the render").required(false))
+        .args_conflicts_with_subcommands(true)
+        .subcommand_negates_reqs(true)
+        .args(scene_args())
+        .arg(save_arg())
+        .subcommand(
+            Command::new("check")
+                .about("Validate a scene and report every problem found")
+                .args(scene_args()),
+        )
+        .subcommand(
+            Command::new("frames")
+                .about("Render the frames of an animated scene, skipping frames already saved")
+                .args(scene_args())
+                .arg(
+                    arg!(-s --save <pattern> "The path of each frame, with %04d for the number")
+                        .required(false)
+                        .default_value("frame_%04d.png"),
+                )
+                .arg(arg!(--overwrite "Render frames again even if they were saved before"))
+                .arg(
+                    arg!(--animation <path> "Also combine the frames into an animated png or gif")
+                        .required(false),
+                )
+                .arg(
+                    arg!(--fps <fps> "The frames per second of the animation")
+                        .required(false)
+                        .default_value("24"),
+                )
+                .arg(arg!(--dither "Dither the colors of gif animations")),
+        )
+        .subcommand(
+            Command::new("coordinator")
+                .about("Hand out tiles to workers over TCP and save the merged render")
+                .args(scene_args())
+                .arg(save_arg())
+                .arg(
+                    arg!(-l --listen <address> "The address to listen for workers on")
+                        .default_value("0.0.0.0:7878"),
+                )
+                .arg(
+                    arg!(--timeout <seconds> "Seconds to wait for a tile before handing it out again")
+                        .required(false)
+                        .default_value("600"),
+                ),
+        )
+        .subcommand(
+            Command::new("worker")
+                .about("Render tiles for a coordinator that loaded the same scene")
+                .args(scene_args())
+                .arg(arg!(-c --connect <address> "The address of the coordinator"))
+                .arg(
+                    arg!(--connections <count> "The number of tiles to render in parallel")
+                        .required(false),
+                ),
+        )
         .get_matches();
 
-    let scene = matches.value_of("scene").map(PathBuf::from).unwrap();
-    let reader = BufReader::new(File::open(scene)?);
-    let config: RaytracerConfig = serde_json::from_reader(reader)?;
-
-    let aspect_ratio: f32 = config.image_width as f32 / config.image_height as f32;
-    let camera = Camera::new(
-        config.look_from,
-        config.look_to,
-        config.viewport_fov,
-        aspect_ratio,
-        config.aperture,
-        config
-            .focal_length
-            .unwrap_or_else(|| (config.look_from - config.look_to).length()),
-    );
-
-    let tracer = Tracer::new(camera, config);
-
-    let save_path = matches
+    let (mode, matches) = match matches.subcommand() {
+        Some((mode, matches)) => (Some(mode), matches),
+        None => (None, &matches),
+    };
+    let (config, problems) = load_config(matches)?;
+    let errors = problems
+        .iter()
+        .filter(|problem| problem.severity == Severity::Error)
+        .count();
+    if mode == Some("check") {
+        for problem in &problems {
+            println!("{}", problem);
+        }
+        return match errors {
+            0 => {
+                println!("{} is valid", matches.value_of("scene").unwrap());
+                Ok(())
+            }
+            _ => Err(anyhow!("Found {} error(s)", errors)),
+        };
+    }
+    for problem in &problems {
+        eprintln!("{}", problem);
+    }
+    if errors > 0 {
+        return Err(anyhow!(
+            "Found {} error(s), run `raytracer check` for details",
+            errors
+        ));
+    }
+
+    if matches.is_present("dry-run") {
+        println!("{}", serde_json::to_string_pretty(&config)?);
+        return Ok(());
+    }
+    let scene = Path::new(matches.value_of("scene").unwrap());
+    let mut tracer = build_tracer(config, scene.parent().unwrap_or_else(|| Path::new("")))?;
+    if let Some(threads) = tracer.config().threads {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build_global()?;
+    }
+
+    match mode {
+        Some("coordinator") => {
+            if tracer.config().camera.stereo.is_some() {
+                return Err(anyhow!(
+                    "Stereo cameras cannot be rendered in distributed mode yet"
+                ));
+            }
+            let listener = TcpListener::bind(matches.value_of("listen").unwrap())?;
+            let timeout: f64 = matches.value_of("timeout").unwrap().parse()?;
+            if timeout.is_nan() || timeout <= 0. {
+                return Err(anyhow!("The timeout must be positive"));
+            }
+            let timeout = Duration::try_from_secs_f64(timeout)?;
+            let image = distributed::coordinate(&tracer, listener, timeout)?;
+            image.save(&save_path(matches))?;
+        }
+        Some("frames") => {
+            let frames = tracer
+                .config()
+                .frame_range()
+                .ok_or_else(|| anyhow!("The scene has neither frames nor camera keyframes"))?;
+            let pattern = matches.value_of("save").unwrap();
+            // Checked before rendering, rather than once every frame was rendered
+            let options = AnimationOptions {
+                frames_per_second: matches.value_of("fps").unwrap().parse()?,
+                dither: matches.is_present("dither"),
+                ..AnimationOptions::default()
+            };
+            options.check()?;
+            for frame in frames.start..=frames.end {
+                let path = animation::frame_path(pattern, frame)
+                    .map(PathBuf::from)
+                    .ok_or_else(|| anyhow!("The save path needs %d or %04d for the frame"))?;
+                let saved = tracer.saved_paths(&path);
+                if saved.iter().all(|path| path.exists()) && !matches.is_present("overwrite") {
+                    eprintln!("Skipping frame {}, {} exists", frame, saved[0].display());
+                    continue;
+                }
+                eprintln!(
+                    "Rendering frame {} ({}-{})",
+                    frame, frames.start, frames.end
+                );
+                tracer.set_frame(frame as f32)?;
+                tracer.save(&path)?;
+            }
+
+            if let Some(animation) = matches.value_of("animation") {
+                // One animation per saved image, e.g. per eye. Skipped frames are only on
+                // disk, so read every frame back.
+                let outputs = tracer.saved_paths(Path::new(animation));
+                for (i, output) in outputs.iter().enumerate() {
+                    let images = (frames.start..=frames.end)
+                        .map(|frame| {
+                            let path = animation::frame_path(pattern, frame).unwrap();
+                            Image::load(&tracer.saved_paths(Path::new(&path))[i])
+                        })
+                        .collect::<Result<Vec<_>>>()?;
+                    image::save_animation(&images, output, options)?;
+                }
+            }
+        }
+        Some("worker") => {
+            let connections = match matches.value_of("connections") {
+                Some(count) => count.parse()?,
+                None => rayon::current_num_threads(),
+            };
+            distributed::work(&tracer, matches.value_of("connect").unwrap(), connections)?;
+        }
+        _ => {
+            tracer.save(&save_path(matches))?;
+        }
+    }
+
+    Ok(())
+}
+
+fn scene_args<'a>() -> Vec<Arg<'a>> {
+    vec![
+        arg!(<scene> "The scene to render, or a .gltf file"),
+        arg!(--width <pixels> "Override the image width").required(false),
+        arg!(--height <pixels> "Override the image height").required(false),
+        arg!(--spp <samples> "Override the samples per pixel").required(false),
+        arg!(--"max-depth" <depth> "Override the maximum number of bounces").required(false),
+        arg!(--fov <degrees> "Override the vertical field of view").required(false),
+        arg!(--aperture <diameter> "Override the lens aperture").required(false),
+        arg!(--threads <count> "Override the number of render threads").required(false),
+        arg!(--seed <seed> "Override the random seed").required(false),
+        arg!(--"tile-size" <size> "The width and height of a render tile").required(false),
+        arg!(--"tile-order" <order> "The order tiles are rendered in")
+            .possible_values(["scanline", "spiral", "hilbert"])
+            .required(false),
+        arg!(--region <region> "Only render the region x,y,width,height").required(false),
+        arg!(--crop "Save only the rendered region instead of the full frame"),
+        arg!(--set <assignment> "Override any scene field, e.g. --set world.0.Sphere.radius=2")
+            .required(false)
+            .multiple_occurrences(true),
+        arg!(--"dry-run" "Print the effective scene settings instead of rendering"),
+    ]
+}
+
+fn save_arg<'a>() -> Arg<'a> {
+    arg!(-s --save <save> "The path to save the render").required(false)
+}
+
+fn save_path(matches: &ArgMatches) -> PathBuf {
+    matches
         .value_of("save")
         .map(PathBuf::from)
-        .unwrap_or_else(|| PathBuf::from("image.png"));
-    tracer.save(&save_path)?;
+        .unwrap_or_else(|| PathBuf::from("image.png"))
+}
 
-    Ok(())
+/// Load the scene with the overrides given on the command line, and validate it
+fn load_config(matches: &ArgMatches) -> Result<(RaytracerConfig, Vec<Problem>)> {
+    let scene = matches.value_of("scene").map(PathBuf::from).unwrap();
+    let source = match scene.extension().and_then(|s| s.to_str()) {
+        // A glTF file on its own is rendered through its camera with default settings
+        Some("gltf") => gltf::scene_source(&scene.file_name().unwrap().to_string_lossy()),
+        _ => fs::read_to_string(&scene)?,
+    };
+
+    // Specific flags are applied first, so `--set` has the final say
+    let mut overrides: Vec<String> = OVERRIDE_FLAGS
+        .iter()
+        .filter_map(|(flag, field)| {
+            matches
+                .value_of(flag)
+                .map(|value| format!("{}={}", field, value))
+        })
+        .collect();
+    if let Some(order) = matches.value_of("tile-order") {
+        let order = match order {
+            "spiral" => "Spiral",
+            "hilbert" => "Hilbert",
+            _ => "Scanline",
+        };
+        overrides.push(format!("tile_order={}", order));
+    }
+    if let Some(region) = matches.value_of("region") {
+        overrides.push(format!("region={}", parse_region(region)?));
+    }
+    if let Some(assignments) = matches.values_of("set") {
+        overrides.extend(assignments.map(String::from));
+    }
+
+    let mut config = RaytracerConfig::from_source(&source, &overrides)?;
+    if config.camera.lens.is_some() && (matches.is_present("fov") || matches.is_present("aperture"))
+    {
+        return Err(anyhow!(
+            "The camera has a lens, so set its focal length or f-stop instead of --fov or --aperture"
+        ));
+    }
+    if matches.is_present("crop") {
+        let region = config
+            .region
+            .as_mut()
+            .ok_or_else(|| anyhow!("--crop requires a region to be set"))?;
+        region.crop = true;
+    }
+
+    let problems = validate::validate(&config, &source);
+    Ok((config, problems))
+}
+
+fn build_tracer(config: RaytracerConfig, directory: &Path) -> Result<Tracer> {
+    let config = config.prepare(directory)?;
+    for problem in config.warnings() {
+        eprintln!("{}", problem);
+    }
+    Ok(Tracer::new(config))
+}
+
+/// Parse x,y,width,height into the JSON of a region
+fn parse_region(region: &str) -> Result<serde_json::Value> {
+    let values = region
+        .split(',')
+        .map(|value| value.trim().parse::<i32>())
+        .collect::<Result<Vec<_>, _>>()?;
+    match values[..] {
+        [x, y, width, height] => Ok(serde_json::json!({
+            "x": x,
+            "y": y,
+            "width": width,
+            "height": height,
+        })),
+        _ => Err(anyhow!("Expected the region as x,y,width,height")),
+    }
 }
@@ -1,9 +1,11 @@
 use serde::{Deserialize, Serialize};
 
+use crate::primitive::random;
 use crate::{object::HitRecord, ray::Ray, vec3::Color};
-use rand::{prelude::SmallRng, Rng, SeedableRng};
 
-use super::{ScatterResult, Scatterable};
+use crate::validate::{join, Problems, Validate};
+
+use super::{ScatterResult, Scatterable, SurfaceMaps};
 
 fn reflectance(cosine: f32, ref_idx: f32) -> f32 {
     // Shlick's approximation
@@ -12,9 +14,11 @@ fn reflectance(cosine: f32, ref_idx: f32) -> f32 {
     r0 + (1. - r0) * (1. - cosine).powi(5)
 }
 
-#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
 pub struct Dielectric {
     pub refractive_index: f32,
+    #[serde(flatten)]
+    pub maps: SurfaceMaps,
 }
 
 impl Scatterable for Dielectric {
@@ -31,8 +35,7 @@ impl Scatterable for Dielectric {
         let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
 
         let cannot_refract = (refraction_ratio * sin_theta) > 1.;
-        let mut rng = SmallRng::from_entropy();
-        let should_reflect = reflectance(cos_theta, refraction_ratio) > rng.gen::<f32>();
+        let should_reflect = reflectance(cos_theta, refraction_ratio) > random::gen::<f32>();
 
         // Cannot refract
         let direction = if cannot_refract || should_reflect {
@@ -42,8 +45,20 @@ impl Scatterable for Dielectric {
         };
 
         Some(ScatterResult {
-            ray: Ray::new(record.point(), direction),
+            ray: Ray::new(record.point(), direction).with_time(r_in.time()),
             attenuation: Color::new(1., 1., 1.),
         })
     }
 }
+
+impl Validate for Dielectric {
+    fn validate(&self, path: &str, problems: &mut Problems) {
+        self.maps.validate(path, problems);
+        if self.refractive_index <= 0. {
+            problems.error(
+                &join(path, "refractive_index"),
+                "The refractive index must be positive",
+            );
+        }
+    }
+}
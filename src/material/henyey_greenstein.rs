@@ -0,0 +1,97 @@
+use std::f32::consts::PI;
+
+use serde::{Deserialize, Serialize};
+
+use crate::primitive::random;
+use crate::{
+    object::HitRecord,
+    ray::Ray,
+    vec3::{Color, Vec3},
+};
+
+use crate::validate::{join, Problems, Validate};
+
+use super::{validate_albedo, ScatterResult, Scatterable};
+
+/// A phase function for volumes that favours scattering forward for a positive `g`, like
+/// haze and clouds, or backward for a negative one. Zero scatters like `Isotropic`.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy)]
+pub struct HenyeyGreenstein {
+    pub albedo: Color,
+    /// The average cosine of the angle a ray is turned by, in (-1, 1)
+    pub g: f32,
+}
+
+impl HenyeyGreenstein {
+    /// Sample the cosine of the angle between the incoming and scattered directions
+    fn sample_cos_theta(&self) -> f32 {
+        let xi = random::gen::<f32>();
+        if self.g.abs() < 1e-3 {
+            return 1. - 2. * xi;
+        }
+        let g = self.g;
+        let s = (1. - g * g) / (1. - g + 2. * g * xi);
+        ((1. + g * g - s * s) / (2. * g)).clamp(-1., 1.)
+    }
+
+    /// A random direction scattered from the unit direction the light travels in
+    pub fn scatter_direction(&self, forward: Vec3) -> Vec3 {
+        let helper = if forward.x().abs() > 0.9 {
+            Vec3::new(0., 1., 0.)
+        } else {
+            Vec3::new(1., 0., 0.)
+        };
+        let side = helper.cross(forward).unit_vector();
+        let up = forward.cross(side);
+
+        let cos_theta = self.sample_cos_theta();
+        let sin_theta = (1. - cos_theta * cos_theta).max(0.).sqrt();
+        let phi = 2. * PI * random::gen::<f32>();
+        sin_theta * (phi.cos() * side + phi.sin() * up) + cos_theta * forward
+    }
+}
+
+impl Scatterable for HenyeyGreenstein {
+    fn scatter(&self, r_in: &Ray, record: &HitRecord) -> Option<ScatterResult> {
+        let direction = self.scatter_direction(r_in.direction().unit_vector());
+        Some(ScatterResult {
+            ray: Ray::new(record.point(), direction).with_time(r_in.time()),
+            attenuation: self.albedo,
+        })
+    }
+
+    fn reflectance(&self, r_in: &Ray, _record: &HitRecord, direction: Vec3) -> Option<Color> {
+        let cos_theta = r_in.direction().unit_vector().dot(direction);
+        let g = self.g;
+        let denominator = 1. + g * g - 2. * g * cos_theta;
+        Some(self.albedo * ((1. - g * g) / (4. * PI * denominator * denominator.sqrt())))
+    }
+}
+
+impl Validate for HenyeyGreenstein {
+    fn validate(&self, path: &str, problems: &mut Problems) {
+        validate_albedo(self.albedo, path, problems);
+        if self.g <= -1. || self.g >= 1. {
+            problems.error(&join(path, "g"), "g must be strictly between -1 and 1");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_average_cosine_is_g() {
+        random::seed(7);
+        for g in [-0.6, 0., 0.8] {
+            let phase = HenyeyGreenstein {
+                albedo: Color::new(1., 1., 1.),
+                g,
+            };
+            let samples = 20000;
+            let mean = (0..samples).map(|_| phase.sample_cos_theta()).sum::<f32>() / samples as f32;
+            assert!((mean - g).abs() < 0.02, "{} != {}", mean, g);
+        }
+    }
+}
@@ -0,0 +1,38 @@
+use std::f32::consts::PI;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    object::HitRecord,
+    ray::Ray,
+    vec3::{Color, Vec3},
+};
+
+use crate::validate::{Problems, Validate};
+
+use super::{validate_albedo, ScatterResult, Scatterable};
+
+/// A phase function for volumes, scattering equally in every direction
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy)]
+pub struct Isotropic {
+    pub albedo: Color,
+}
+
+impl Scatterable for Isotropic {
+    fn scatter(&self, r_in: &Ray, record: &HitRecord) -> Option<ScatterResult> {
+        Some(ScatterResult {
+            ray: Ray::new(record.point(), Vec3::new_random_unit_vector()).with_time(r_in.time()),
+            attenuation: self.albedo,
+        })
+    }
+
+    fn reflectance(&self, _r_in: &Ray, _record: &HitRecord, _direction: Vec3) -> Option<Color> {
+        Some(self.albedo / (4. * PI))
+    }
+}
+
+impl Validate for Isotropic {
+    fn validate(&self, path: &str, problems: &mut Problems) {
+        validate_albedo(self.albedo, path, problems);
+    }
+}
@@ -1,3 +1,5 @@
+use std::f32::consts::PI;
+
 use serde::{Deserialize, Serialize};
 
 use crate::{
@@ -6,23 +8,38 @@ use crate::{
     vec3::{Color, Vec3},
 };
 
-use super::{ScatterResult, Scatterable};
+use crate::validate::{Problems, Validate};
+
+use super::{validate_albedo, ScatterResult, Scatterable, SurfaceMaps};
 
-#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
 pub struct Lambertian {
     pub albedo: Color,
+    #[serde(flatten)]
+    pub maps: SurfaceMaps,
 }
 
 impl Scatterable for Lambertian {
-    fn scatter(&self, _: &Ray, record: &HitRecord) -> Option<ScatterResult> {
+    fn scatter(&self, r_in: &Ray, record: &HitRecord) -> Option<ScatterResult> {
         let mut scatter_direction = record.normal() + Vec3::new_random_unit_vector();
         if scatter_direction.is_near_zero() {
             scatter_direction = record.normal();
         }
 
         Some(ScatterResult {
-            ray: Ray::new(record.point(), scatter_direction),
+            ray: Ray::new(record.point(), scatter_direction).with_time(r_in.time()),
             attenuation: self.albedo,
         })
     }
+
+    fn reflectance(&self, _r_in: &Ray, record: &HitRecord, direction: Vec3) -> Option<Color> {
+        Some(self.albedo * (record.normal().dot(direction).max(0.) / PI))
+    }
+}
+
+impl Validate for Lambertian {
+    fn validate(&self, path: &str, problems: &mut Problems) {
+        self.maps.validate(path, problems);
+        validate_albedo(self.albedo, path, problems);
+    }
 }
@@ -0,0 +1,187 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::image::Image;
+use crate::object::HitRecord;
+use crate::validate::{join, Problems, Validate};
+
+/// Images bending a material's shading normal, for detail the geometry does not have. They
+/// are placed by the surface coordinates of the hit along the shading frame's tangents, and
+/// read as stored rather than as colors.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct SurfaceMaps {
+    // Unset maps are still serialized, since overrides can only set fields the serialized
+    // scene has, e.g. `--set world.0.Sphere.material.Lambertian.bump_map=rock.pgm`
+    /// A tangent-space normal map, with red along the tangent, green along the bitangent and
+    /// blue along the normal
+    #[serde(default)]
+    pub normal_map: Option<PathBuf>,
+    /// Scales the sideways lean of the normal map, flattening it below 1
+    #[serde(default = "default_scale")]
+    pub normal_scale: f32,
+    /// A grayscale height map, brighter being higher
+    #[serde(default)]
+    pub bump_map: Option<PathBuf>,
+    /// How high white is above black in the bump map, in units of the surface coordinates
+    #[serde(default = "default_scale")]
+    pub bump_scale: f32,
+    #[serde(skip)]
+    normal_image: Option<Arc<Image>>,
+    #[serde(skip)]
+    bump_image: Option<Arc<Image>>,
+}
+
+fn default_scale() -> f32 {
+    1.
+}
+
+impl Default for SurfaceMaps {
+    fn default() -> Self {
+        Self {
+            normal_map: None,
+            normal_scale: default_scale(),
+            bump_map: None,
+            bump_scale: default_scale(),
+            normal_image: None,
+            bump_image: None,
+        }
+    }
+}
+
+impl SurfaceMaps {
+    /// Maps with a normal map already loaded, e.g. from an imported scene
+    pub fn with_normal_image(image: Arc<Image>, normal_scale: f32) -> Self {
+        Self {
+            normal_scale,
+            normal_image: Some(image),
+            ..Self::default()
+        }
+    }
+
+    pub fn load_files(&mut self, directory: &Path) -> Result<()> {
+        if let Some(path) = &self.normal_map {
+            self.normal_image = Some(Arc::new(Image::load_raw(&directory.join(path))?));
+        }
+        if let Some(path) = &self.bump_map {
+            self.bump_image = Some(Arc::new(Image::load_raw(&directory.join(path))?));
+        }
+        Ok(())
+    }
+
+    /// The hit with its shading normal bent by the maps, the normal map first
+    pub fn apply<'a>(&self, record: HitRecord<'a>) -> HitRecord<'a> {
+        let (u, v) = record.uv();
+        let (u, v) = (u.rem_euclid(1.), v.rem_euclid(1.));
+        let mut record = record;
+        if let Some(image) = &self.normal_image {
+            let color = image.sample_bilinear(u, v);
+            let (tangent, bitangent, normal) = record.shading_frame();
+            let bent = self.normal_scale * (2. * color.x() - 1.) * tangent
+                + self.normal_scale * (2. * color.y() - 1.) * bitangent
+                + (2. * color.z() - 1.) * normal;
+            if !bent.is_near_zero() {
+                record = record.with_shading_normal(bent.unit_vector());
+            }
+        }
+        if let Some(image) = &self.bump_image {
+            let height = |u: f32, v: f32| {
+                let color = image.sample_bilinear(u, v);
+                (color.x() + color.y() + color.z()) / 3.
+            };
+            // Central differences across a pixel
+            let (du, dv) = (1. / image.width() as f32, 1. / image.height() as f32);
+            let slope_u = (height(u + du, v) - height(u - du, v)) / (2. * du);
+            let slope_v = (height(u, v + dv) - height(u, v - dv)) / (2. * dv);
+            let (tangent, bitangent, normal) = record.shading_frame();
+            let bent = normal - self.bump_scale * (slope_u * tangent + slope_v * bitangent);
+            record = record.with_shading_normal(bent.unit_vector());
+        }
+        record
+    }
+}
+
+impl Validate for SurfaceMaps {
+    fn validate(&self, path: &str, problems: &mut Problems) {
+        if !self.normal_scale.is_finite() {
+            problems.error(&join(path, "normal_scale"), "The scale must be finite");
+        }
+        if !self.bump_scale.is_finite() {
+            problems.error(&join(path, "bump_scale"), "The scale must be finite");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::{Lambertian, Material};
+    use crate::vec3::{Color, Vec3};
+
+    fn material() -> Material {
+        Material::Lambertian(Lambertian {
+            albedo: Color::new(0.5, 0.5, 0.5),
+            maps: SurfaceMaps::default(),
+        })
+    }
+
+    #[test]
+    fn test_normal_map_bends_along_tangent() {
+        let mut image = Image::new(1, 1);
+        image.set(0, 0, Color::new(1., 0.5, 1.));
+        let maps = SurfaceMaps::with_normal_image(Arc::new(image), 1.);
+        let material = material();
+        let record = HitRecord::new(
+            Vec3::new(0., 0., 0.),
+            Vec3::new(0., 0., 1.),
+            1.,
+            true,
+            &material,
+        )
+        .with_tangents(Vec3::new(1., 0., 0.), Vec3::new(0., 1., 0.));
+
+        let bent = maps.apply(record);
+        let expected = Vec3::new(1., 0., 1.).unit_vector();
+        assert!((bent.normal() - expected).length() < 1e-5);
+        assert_eq!(bent.geometric_normal(), Vec3::new(0., 0., 1.));
+        let (tangent, bitangent, normal) = bent.shading_frame();
+        assert!(tangent.dot(normal).abs() < 1e-5 && bitangent.dot(normal).abs() < 1e-5);
+        assert!(bitangent.dot(Vec3::new(0., 1., 0.)) > 0.99);
+    }
+
+    #[test]
+    fn test_bump_map_leans_downhill_but_not_through() {
+        // A ramp rising along u
+        let mut image = Image::new(4, 1);
+        for x in 0..4 {
+            let height = x as f32 / 3.;
+            image.set(x, 0, Color::new(height, height, height));
+        }
+        let mut maps = SurfaceMaps {
+            bump_image: Some(Arc::new(image)),
+            bump_scale: 0.1,
+            ..SurfaceMaps::default()
+        };
+        let material = material();
+        let record = HitRecord::new(
+            Vec3::new(0., 0., 0.),
+            Vec3::new(0., 0., 1.),
+            1.,
+            true,
+            &material,
+        )
+        .with_tangents(Vec3::new(1., 0., 0.), Vec3::new(0., 1., 0.))
+        .with_uv(0.5, 0.5);
+        let bent = maps.apply(record);
+        assert!(bent.normal().x() < -0.1 && bent.normal().z() > 0.);
+        assert!(bent.normal().y().abs() < 1e-5);
+
+        // Bumps too steep for the surface stay on its side
+        maps.bump_scale = 1000.;
+        let bent = maps.apply(record);
+        assert!(bent.normal().z() > 0.);
+        assert!(!bent.agrees_on_side(Vec3::new(-1., 0., -0.01)));
+    }
+}
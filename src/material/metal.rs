@@ -6,12 +6,16 @@ use crate::{
     vec3::{Color, Vec3},
 };
 
-use super::{ScatterResult, Scatterable};
+use crate::validate::{join, Problems, Validate};
 
-#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy)]
+use super::{validate_albedo, ScatterResult, Scatterable, SurfaceMaps};
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
 pub struct Metal {
     pub albedo: Color,
     pub fuzz: f32,
+    #[serde(flatten)]
+    pub maps: SurfaceMaps,
 }
 
 impl Scatterable for Metal {
@@ -20,7 +24,8 @@ impl Scatterable for Metal {
         let scattered = Ray::new(
             record.point(),
             reflected + self.fuzz * Vec3::new_random_in_unit_sphere(),
-        );
+        )
+        .with_time(r_in.time());
 
         if scattered.direction().dot(record.normal()) > 0. {
             Some(ScatterResult {
@@ -32,3 +37,16 @@ impl Scatterable for Metal {
         }
     }
 }
+
+impl Validate for Metal {
+    fn validate(&self, path: &str, problems: &mut Problems) {
+        self.maps.validate(path, problems);
+        validate_albedo(self.albedo, path, problems);
+        if !(0. ..=1.).contains(&self.fuzz) {
+            problems.warning(
+                &join(path, "fuzz"),
+                "Fuzz outside of [0, 1] scatters rays below the surface",
+            );
+        }
+    }
+}
@@ -1,15 +1,27 @@
+use std::collections::BTreeMap;
+
+use std::path::Path;
+
+use anyhow::{anyhow, Result};
 use enum_dispatch::enum_dispatch;
 use serde::{Deserialize, Serialize};
 
 use crate::object::HitRecord;
 use crate::ray::Ray;
-use crate::vec3::Color;
+use crate::validate::{join, Problems, Validate};
+use crate::vec3::{Color, Vec3};
 
 mod dielectric;
+mod henyey_greenstein;
+mod isotropic;
 mod lambertian;
+mod maps;
 mod metal;
 pub use dielectric::Dielectric;
+pub use henyey_greenstein::HenyeyGreenstein;
+pub use isotropic::Isotropic;
 pub use lambertian::Lambertian;
+pub use maps::SurfaceMaps;
 pub use metal::Metal;
 
 pub struct ScatterResult {
@@ -20,12 +32,135 @@ pub struct ScatterResult {
 #[enum_dispatch]
 pub trait Scatterable {
     fn scatter(&self, r_in: &Ray, record: &HitRecord) -> Option<ScatterResult>;
+
+    /// How much of the light arriving from the unit direction is scattered back along the
+    /// ray, per unit of solid angle, or None for materials that only scatter into a few
+    /// directions and cannot be lit by lights directly
+    fn reflectance(&self, _r_in: &Ray, _record: &HitRecord, _direction: Vec3) -> Option<Color> {
+        None
+    }
 }
 
 #[enum_dispatch(Scatterable)]
-#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
 pub enum Material {
     Lambertian,
     Metal,
     Dielectric,
+    Isotropic,
+    HenyeyGreenstein,
+}
+
+impl Material {
+    /// The maps bending the shading normal, for materials of surfaces
+    pub fn maps(&self) -> Option<&SurfaceMaps> {
+        match self {
+            Material::Lambertian(m) => Some(&m.maps),
+            Material::Metal(m) => Some(&m.maps),
+            Material::Dielectric(m) => Some(&m.maps),
+            Material::Isotropic(_) | Material::HenyeyGreenstein(_) => None,
+        }
+    }
+
+    pub fn load_files(&mut self, directory: &Path) -> Result<()> {
+        match self {
+            Material::Lambertian(m) => m.maps.load_files(directory),
+            Material::Metal(m) => m.maps.load_files(directory),
+            Material::Dielectric(m) => m.maps.load_files(directory),
+            Material::Isotropic(_) | Material::HenyeyGreenstein(_) => Ok(()),
+        }
+    }
+
+    /// The hit shaded with the material's maps, if it has any
+    pub fn shade<'a>(&self, record: HitRecord<'a>) -> HitRecord<'a> {
+        match self.maps() {
+            Some(maps) => maps.apply(record),
+            None => record,
+        }
+    }
+}
+
+/// Materials shared by name across the scene
+pub type MaterialLibrary = BTreeMap<String, Material>;
+
+/// A material given inline, or by its name in the scene's material library
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+#[serde(untagged)]
+pub enum MaterialRef {
+    Named(String),
+    Inline(Material),
+}
+
+impl MaterialRef {
+    /// Replace a reference by name with the material from the library
+    pub fn resolve(&mut self, library: &MaterialLibrary) -> Result<()> {
+        if let MaterialRef::Named(name) = self {
+            let material = library
+                .get(name)
+                .ok_or_else(|| anyhow!("Unknown material '{}'", name))?;
+            *self = MaterialRef::Inline(material.clone());
+        }
+        Ok(())
+    }
+
+    /// Load the maps of an inline material, as materials given by name are loaded with the
+    /// library
+    pub fn load_files(&mut self, directory: &Path) -> Result<()> {
+        match self {
+            MaterialRef::Inline(material) => material.load_files(directory),
+            MaterialRef::Named(_) => Ok(()),
+        }
+    }
+
+    /// The material, which is resolved when the scene is prepared if it was given by name
+    pub fn material(&self) -> &Material {
+        match self {
+            MaterialRef::Inline(material) => material,
+            MaterialRef::Named(name) => {
+                panic!("Material '{}' is resolved when the scene is prepared", name)
+            }
+        }
+    }
+}
+
+impl From<Material> for MaterialRef {
+    fn from(material: Material) -> Self {
+        MaterialRef::Inline(material)
+    }
+}
+
+impl Validate for MaterialRef {
+    fn validate(&self, path: &str, problems: &mut Problems) {
+        match self {
+            MaterialRef::Named(name) => {
+                if !problems.knows_material(name) {
+                    problems.error(path, format!("There is no material named '{}'", name));
+                }
+            }
+            MaterialRef::Inline(material) => material.validate(path, problems),
+        }
+    }
+}
+
+impl Validate for Material {
+    fn validate(&self, path: &str, problems: &mut Problems) {
+        match self {
+            Material::Lambertian(m) => m.validate(&join(path, "Lambertian"), problems),
+            Material::Metal(m) => m.validate(&join(path, "Metal"), problems),
+            Material::Dielectric(m) => m.validate(&join(path, "Dielectric"), problems),
+            Material::Isotropic(m) => m.validate(&join(path, "Isotropic"), problems),
+            Material::HenyeyGreenstein(m) => m.validate(&join(path, "HenyeyGreenstein"), problems),
+        }
+    }
+}
+
+/// Warn about albedos that reflect more light than they receive
+fn validate_albedo(albedo: Color, path: &str, problems: &mut Problems) {
+    let components = [albedo.x(), albedo.y(), albedo.z()];
+    if components.iter().any(|c| !(0. ..=1.).contains(c)) {
+        problems.warning(
+            &join(path, "albedo"),
+            "Albedo components outside of [0, 1] create or absorb more than all light",
+        );
+    }
 }
@@ -0,0 +1,143 @@
+use std::path::Path;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::aabb::Aabb;
+use crate::material::{MaterialLibrary, MaterialRef};
+use crate::object::sphere::sphere_box;
+use crate::object::{angle_around_axis, Frame, HitRecord, Hittable, LoadFiles, ResolveMaterials};
+use crate::primitive::roots;
+use crate::ray::Ray;
+use crate::validate::{join, Problems, Validate};
+use crate::vec3::{Point, Vec3};
+
+/// A cylinder from `start` to `end` with hemispheres on both ends, i.e. every point within
+/// `radius` of the segment between them
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct Capsule {
+    start: Point,
+    end: Point,
+    radius: f32,
+    material: MaterialRef,
+}
+
+impl Capsule {
+    pub fn new<M: Into<MaterialRef>>(start: Point, end: Point, radius: f32, material: M) -> Self {
+        Self {
+            start,
+            end,
+            radius,
+            material: material.into(),
+        }
+    }
+}
+
+impl Hittable for Capsule {
+    fn hit(&self, ray: Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+        let axis = self.end - self.start;
+        let length = axis.length();
+        let frame = Frame::new(self.start, axis);
+        let (o, d) = frame.ray_to_local(ray);
+        let r = self.radius;
+
+        let mut closest: Option<(f32, Vec3)> = None;
+        let mut consider = |t: f32, normal: Vec3| {
+            if (t_min..=t_max).contains(&t) && closest.is_none_or(|(best, _)| t < best) {
+                closest = Some((t, normal));
+            }
+        };
+
+        // The side, between the ends
+        let a = d.x() * d.x() + d.y() * d.y();
+        let b = 2. * (o.x() * d.x() + o.y() * d.y());
+        let c = o.x() * o.x() + o.y() * o.y() - r * r;
+        for &t in roots::quadratic(a as f64, b as f64, c as f64).iter() {
+            let p = o + t as f32 * d;
+            if (0. ..=length).contains(&p.z()) {
+                consider(t as f32, Vec3::new(p.x(), p.y(), 0.));
+            }
+        }
+
+        // The hemispheres, each only beyond its end of the segment
+        for (center, beyond) in [(0., -1.), (length, 1.)] {
+            let oc = o - Vec3::new(0., 0., center);
+            let b = 2. * oc.dot(d);
+            let c = oc.length_squared() - r * r;
+            for &t in roots::quadratic(d.length_squared() as f64, b as f64, c as f64).iter() {
+                let normal = oc + t as f32 * d;
+                if normal.z() * beyond > 0. {
+                    consider(t as f32, normal);
+                }
+            }
+        }
+
+        // Around the axis, then along the whole length from tip to tip
+        let (t, normal) = closest?;
+        let p = o + t * d;
+        let u = angle_around_axis(p);
+        let v = ((p.z() + r) / (length + 2. * r)).clamp(0., 1.);
+        let outward_normal = frame.vector_to_world(normal).unit_vector();
+        Some(HitRecord::facing(ray, t, outward_normal, self.material.material()).with_uv(u, v))
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let start = sphere_box(self.start, self.radius);
+        Some(start.surrounding(sphere_box(self.end, self.radius)))
+    }
+}
+
+impl ResolveMaterials for Capsule {
+    fn resolve_materials(&mut self, library: &MaterialLibrary) -> Result<()> {
+        self.material.resolve(library)
+    }
+}
+
+impl LoadFiles for Capsule {
+    fn load_files(&mut self, directory: &Path) -> Result<()> {
+        self.material.load_files(directory)
+    }
+}
+
+impl Validate for Capsule {
+    fn validate(&self, path: &str, problems: &mut Problems) {
+        if self.start == self.end {
+            problems.error(
+                &join(path, "end"),
+                "The end must differ from the start, use a sphere instead",
+            );
+        }
+        if self.radius <= 0. {
+            problems.error(&join(path, "radius"), "The radius must be positive");
+        }
+        self.material.validate(&join(path, "material"), problems);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::{Lambertian, Material, SurfaceMaps};
+
+    #[test]
+    fn test_hit_rounded_end() {
+        let material = Material::Lambertian(Lambertian {
+            albedo: Vec3::new(0.5, 0.5, 0.5),
+            maps: SurfaceMaps::default(),
+        });
+        let capsule = Capsule::new(Vec3::new(0., 0., 0.), Vec3::new(2., 0., 0.), 1., material);
+
+        // Along the axis, the tip of the far hemisphere
+        let ray = Ray::new(Vec3::new(10., 0., 0.), Vec3::new(-1., 0., 0.));
+        let record = capsule.hit(ray, 0., f32::MAX).unwrap();
+        assert!((record.t() - 7.).abs() < 1e-4);
+        assert!((record.normal() - Vec3::new(1., 0., 0.)).length() < 1e-4);
+        assert!((record.uv().1 - 1.).abs() < 1e-4);
+
+        // From inside, the hemisphere and not the inner half of its sphere
+        let ray = Ray::new(Vec3::new(1., 0., 0.), Vec3::new(-1., 0., 0.));
+        let record = capsule.hit(ray, 0.001, f32::MAX).unwrap();
+        assert!((record.t() - 2.).abs() < 1e-4);
+        assert!(!record.is_front_face());
+    }
+}
@@ -0,0 +1,168 @@
+use std::path::Path;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::aabb::Aabb;
+use crate::material::{MaterialLibrary, MaterialRef};
+use crate::object::disk::disk_box;
+use crate::object::{angle_around_axis, Frame, HitRecord, Hittable, LoadFiles, ResolveMaterials};
+use crate::primitive::roots;
+use crate::ray::Ray;
+use crate::validate::{join, Problems, Validate};
+use crate::vec3::{Point, Vec3};
+
+/// A cone from a circle around `base` to a circle around `top`, which is a point when
+/// `top_radius` is zero and a truncated cone otherwise
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct Cone {
+    base: Point,
+    top: Point,
+    base_radius: f32,
+    #[serde(default)]
+    top_radius: f32,
+    /// Whether the ends are closed off by disks
+    #[serde(default = "default_capped")]
+    capped: bool,
+    material: MaterialRef,
+}
+
+pub(super) fn default_capped() -> bool {
+    true
+}
+
+impl Cone {
+    pub fn new<M: Into<MaterialRef>>(
+        base: Point,
+        top: Point,
+        base_radius: f32,
+        material: M,
+    ) -> Self {
+        Self {
+            base,
+            top,
+            base_radius,
+            top_radius: 0.,
+            capped: default_capped(),
+            material: material.into(),
+        }
+    }
+
+    pub fn is_capped(&self) -> bool {
+        self.capped
+    }
+
+    fn frustum(&self) -> Frustum {
+        Frustum {
+            base: self.base,
+            top: self.top,
+            base_radius: self.base_radius,
+            top_radius: self.top_radius,
+            capped: self.capped,
+        }
+    }
+}
+
+impl Hittable for Cone {
+    fn hit(&self, ray: Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+        let (t, outward_normal, (u, v)) = self.frustum().hit(ray, t_min, t_max)?;
+        Some(HitRecord::facing(ray, t, outward_normal, self.material.material()).with_uv(u, v))
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(self.frustum().bounding_box())
+    }
+}
+
+impl ResolveMaterials for Cone {
+    fn resolve_materials(&mut self, library: &MaterialLibrary) -> Result<()> {
+        self.material.resolve(library)
+    }
+}
+
+impl LoadFiles for Cone {
+    fn load_files(&mut self, directory: &Path) -> Result<()> {
+        self.material.load_files(directory)
+    }
+}
+
+impl Validate for Cone {
+    fn validate(&self, path: &str, problems: &mut Problems) {
+        if self.base == self.top {
+            problems.error(&join(path, "top"), "The top must differ from the base");
+        }
+        if self.base_radius < 0. || self.top_radius < 0. {
+            problems.error(path, "The radii must not be negative");
+        } else if self.base_radius == 0. && self.top_radius == 0. {
+            problems.error(path, "At least one of the radii must be positive");
+        }
+        self.material.validate(&join(path, "material"), problems);
+    }
+}
+
+/// A cone cut off at both ends, with the radius going linearly from the base to the top.
+/// Cylinders are the case of equal radii.
+#[derive(Debug, Clone, Copy)]
+pub(super) struct Frustum {
+    pub base: Point,
+    pub top: Point,
+    pub base_radius: f32,
+    pub top_radius: f32,
+    pub capped: bool,
+}
+
+impl Frustum {
+    /// The closest hit's t, outward normal and surface coordinates. Around the side, u is the
+    /// angle and v the height. On the caps, u is the angle and v the distance from the center.
+    pub fn hit(self, ray: Ray, t_min: f32, t_max: f32) -> Option<(f32, Vec3, (f32, f32))> {
+        let axis = self.top - self.base;
+        let height = axis.length();
+        let frame = Frame::new(self.base, axis);
+        let (o, d) = frame.ray_to_local(ray);
+
+        let mut closest: Option<(f32, Vec3, (f32, f32))> = None;
+        let mut consider = |t: f32, normal: Vec3, uv: (f32, f32)| {
+            if (t_min..=t_max).contains(&t) && closest.is_none_or(|(best, _, _)| t < best) {
+                closest = Some((t, normal, uv));
+            }
+        };
+
+        // The side is where x² + y² = (r0 + k z)², between the ends
+        let slope = (self.top_radius - self.base_radius) / height;
+        let radius_at = |z: f32| self.base_radius + slope * z;
+        let origin_radius = radius_at(o.z());
+        let a = d.x() * d.x() + d.y() * d.y() - slope * slope * d.z() * d.z();
+        let b = 2. * (o.x() * d.x() + o.y() * d.y() - slope * d.z() * origin_radius);
+        let c = o.x() * o.x() + o.y() * o.y() - origin_radius * origin_radius;
+        for &t in roots::quadratic(a as f64, b as f64, c as f64).iter() {
+            let t = t as f32;
+            let p = o + t * d;
+            if (0. ..=height).contains(&p.z()) {
+                let normal = Vec3::new(p.x(), p.y(), -slope * radius_at(p.z()));
+                consider(t, normal, (angle_around_axis(p), p.z() / height));
+            }
+        }
+
+        if self.capped && d.z() != 0. {
+            for (z, radius, facing) in [(0., self.base_radius, -1.), (height, self.top_radius, 1.)]
+            {
+                let t = (z - o.z()) / d.z();
+                let p = o + t * d;
+                let distance = (p.x() * p.x() + p.y() * p.y()).sqrt();
+                if radius > 0. && distance <= radius {
+                    let normal = Vec3::new(0., 0., facing);
+                    consider(t, normal, (angle_around_axis(p), distance / radius));
+                }
+            }
+        }
+
+        closest.map(|(t, normal, uv)| (t, frame.vector_to_world(normal).unit_vector(), uv))
+    }
+
+    pub fn bounding_box(self) -> Aabb {
+        let axis = self.top - self.base;
+        disk_box(self.base, axis, self.base_radius)
+            .surrounding(disk_box(self.top, axis, self.top_radius))
+            .padded(1e-4)
+    }
+}
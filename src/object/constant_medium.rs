@@ -0,0 +1,163 @@
+use std::path::Path;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::aabb::Aabb;
+use crate::material::{MaterialLibrary, MaterialRef};
+use crate::object::{HitRecord, Hittable, LoadFiles, Object, ResolveMaterials, Span};
+use crate::primitive::random;
+use crate::ray::Ray;
+use crate::validate::{join, Problems, Validate};
+use crate::vec3::Vec3;
+
+/// Fog or smoke of even density filling a closed boundary object. Rays scatter at random
+/// distances inside it by the material, which should be a phase function like `Isotropic`.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct ConstantMedium {
+    boundary: Box<Object>,
+    /// The chance of scattering per unit of distance travelled
+    density: f32,
+    material: MaterialRef,
+}
+
+impl ConstantMedium {
+    pub fn new<M: Into<MaterialRef>>(boundary: Object, density: f32, material: M) -> Self {
+        Self {
+            boundary: Box::new(boundary),
+            density,
+            material: material.into(),
+        }
+    }
+
+    pub fn boundary(&self) -> &Object {
+        &self.boundary
+    }
+
+    /// The stretches of the ray inside the boundary, clipped to the bounds
+    fn inside(&self, ray: Ray, t_min: f32, t_max: f32) -> Vec<(f32, f32)> {
+        self.boundary
+            .spans(ray)
+            .into_iter()
+            .map(|span| {
+                let enter = span.enter().map_or(t_min, |record| record.t().max(t_min));
+                let exit = span.exit().map_or(t_max, |record| record.t().min(t_max));
+                (enter, exit)
+            })
+            .filter(|(enter, exit)| enter < exit)
+            .collect()
+    }
+}
+
+impl Hittable for ConstantMedium {
+    /// A medium has no surface for rays to hit
+    fn hit(&self, _ray: Ray, _t_min: f32, _t_max: f32) -> Option<HitRecord<'_>> {
+        None
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.boundary.bounding_box()
+    }
+
+    /// A medium has no surface to combine with other solids
+    fn spans(&self, _ray: Ray) -> Vec<Span<'_>> {
+        Vec::new()
+    }
+
+    fn collide(&self, ray: Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+        let speed = ray.direction().length();
+        for (enter, exit) in self.inside(ray, t_min, t_max) {
+            // Sample the free flight distance, which is memoryless, so each span can be
+            // sampled on its own
+            let distance = -(1. - random::gen::<f32>()).ln() / self.density;
+            let t = enter + distance / speed;
+            if t < exit {
+                // Phase functions ignore the normal, so any will do
+                let normal = Vec3::new(1., 0., 0.);
+                return Some(HitRecord::new(
+                    ray.at(t),
+                    normal,
+                    t,
+                    true,
+                    self.material.material(),
+                ));
+            }
+        }
+        None
+    }
+
+    fn transmittance(&self, ray: Ray, t_min: f32, t_max: f32) -> f32 {
+        let speed = ray.direction().length();
+        let length: f32 = self
+            .inside(ray, t_min, t_max)
+            .iter()
+            .map(|(enter, exit)| (exit - enter) * speed)
+            .sum();
+        (-self.density * length).exp()
+    }
+}
+
+impl ResolveMaterials for ConstantMedium {
+    fn resolve_materials(&mut self, library: &MaterialLibrary) -> Result<()> {
+        self.boundary.resolve_materials(library)?;
+        self.material.resolve(library)
+    }
+}
+
+impl LoadFiles for ConstantMedium {
+    fn load_files(&mut self, directory: &Path) -> Result<()> {
+        self.material.load_files(directory)?;
+        self.boundary.load_files(directory)
+    }
+}
+
+impl Validate for ConstantMedium {
+    fn validate(&self, path: &str, problems: &mut Problems) {
+        if self.density <= 0. {
+            problems.error(&join(path, "density"), "The density must be positive");
+        }
+        self.boundary.validate(&join(path, "boundary"), problems);
+        self.material.validate(&join(path, "material"), problems);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::{Isotropic, Lambertian, Material, SurfaceMaps};
+    use crate::object::Sphere;
+
+    #[test]
+    fn test_scatters_inside_boundary() {
+        let grey = Material::Lambertian(Lambertian {
+            albedo: Vec3::new(0.5, 0.5, 0.5),
+            maps: SurfaceMaps::default(),
+        });
+        let boundary = Object::Sphere(Sphere::new(Vec3::new(0., 0., 0.), 1., grey));
+        let fog = Material::Isotropic(Isotropic {
+            albedo: Vec3::new(1., 1., 1.),
+        });
+        let medium = ConstantMedium::new(boundary, 0.5, fog);
+
+        // Passing through 2 units of density 0.5 scatters with a chance of 1 - e^-1
+        random::seed(3);
+        let ray = Ray::new(Vec3::new(0., 0., -5.), Vec3::new(0., 0., 2.));
+        let samples = 10000;
+        let mut scattered = 0;
+        assert!(medium.hit(ray, 0.001, f32::MAX).is_none());
+        for _ in 0..samples {
+            if let Some(record) = medium.collide(ray, 0.001, f32::MAX) {
+                assert!(record.point().length() <= 1. + 1e-4);
+                scattered += 1;
+            }
+        }
+        let expected = 1. - (-1f32).exp();
+        assert!((scattered as f32 / samples as f32 - expected).abs() < 0.02);
+        let transmittance = medium.transmittance(ray, 0.001, f32::MAX);
+        assert!((transmittance - (-1f32).exp()).abs() < 1e-4);
+
+        // Starting inside, the ray can scatter right away
+        let ray = Ray::new(Vec3::new(0., 0., 0.), Vec3::new(1., 0., 0.));
+        assert!((0..100).any(|_| medium.collide(ray, 0.001, f32::MAX).is_some()));
+    }
+}
@@ -0,0 +1,416 @@
+use std::path::Path;
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::aabb::Aabb;
+use crate::material::MaterialLibrary;
+use crate::object::{HitRecord, Hittable, LoadFiles, Object, ResolveMaterials, Span};
+use crate::ray::Ray;
+use crate::validate::{join, Problems, Validate};
+
+/// Everything inside any of the objects, without the surfaces hidden inside the others
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct Union {
+    objects: Vec<Object>,
+}
+
+/// Only what is inside all of the objects, e.g. a lens from two spheres
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct Intersection {
+    objects: Vec<Object>,
+}
+
+/// The first object with the others cut away. The cut surfaces take the material of the
+/// object that cut them.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct Difference {
+    objects: Vec<Object>,
+}
+
+impl Union {
+    pub fn new(objects: Vec<Object>) -> Self {
+        Self { objects }
+    }
+
+    pub fn objects(&self) -> &[Object] {
+        &self.objects
+    }
+}
+
+impl Intersection {
+    pub fn new(objects: Vec<Object>) -> Self {
+        Self { objects }
+    }
+
+    pub fn objects(&self) -> &[Object] {
+        &self.objects
+    }
+}
+
+impl Difference {
+    pub fn new(objects: Vec<Object>) -> Self {
+        Self { objects }
+    }
+
+    pub fn objects(&self) -> &[Object] {
+        &self.objects
+    }
+}
+
+impl Hittable for Union {
+    fn hit(&self, ray: Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+        first_boundary(&self.spans(ray), t_min, t_max)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.objects.bounding_box()
+    }
+
+    fn spans(&self, ray: Ray) -> Vec<Span<'_>> {
+        if misses(self.bounding_box(), ray) {
+            return Vec::new();
+        }
+        let mut spans = self.objects.iter().map(|object| object.spans(ray));
+        let first = spans.next().unwrap_or_default();
+        spans.fold(first, |a, b| combine(&a, &b, |a, b| a || b))
+    }
+}
+
+impl Hittable for Intersection {
+    fn hit(&self, ray: Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+        first_boundary(&self.spans(ray), t_min, t_max)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        // Any bounded object bounds the intersection
+        self.objects
+            .iter()
+            .filter_map(Hittable::bounding_box)
+            .reduce(Aabb::intersection)
+    }
+
+    fn spans(&self, ray: Ray) -> Vec<Span<'_>> {
+        if misses(self.bounding_box(), ray) {
+            return Vec::new();
+        }
+        let mut spans = self.objects.iter().map(|object| object.spans(ray));
+        let first = spans.next().unwrap_or_default();
+        spans.fold(first, |a, b| combine(&a, &b, |a, b| a && b))
+    }
+}
+
+impl Hittable for Difference {
+    fn hit(&self, ray: Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+        first_boundary(&self.spans(ray), t_min, t_max)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.objects.first()?.bounding_box()
+    }
+
+    fn spans(&self, ray: Ray) -> Vec<Span<'_>> {
+        if misses(self.bounding_box(), ray) {
+            return Vec::new();
+        }
+        let mut spans = self.objects.iter().map(|object| object.spans(ray));
+        let first = spans.next().unwrap_or_default();
+        spans.fold(first, |a, b| combine(&a, &b, |a, b| a && !b))
+    }
+}
+
+/// Whether the ray misses the box, so it cannot be inside the object from t = 0 on
+fn misses(aabb: Option<Aabb>, ray: Ray) -> bool {
+    aabb.is_some_and(|aabb| !aabb.hit(ray, 0., f32::MAX))
+}
+
+/// The closest end of a span within the range
+fn first_boundary<'a>(spans: &[Span<'a>], t_min: f32, t_max: f32) -> Option<HitRecord<'a>> {
+    spans
+        .iter()
+        .flat_map(|span| [span.enter(), span.exit()])
+        .flatten()
+        .find(|record| (t_min..=t_max).contains(&record.t()))
+}
+
+/// Combine two ordered lists of spans into the spans where `inside` holds, given whether
+/// the ray is inside each of them. The ends are marked as entering or exiting the result,
+/// since e.g. exiting a subtracted object enters the difference.
+fn combine<'a>(
+    a: &[Span<'a>],
+    b: &[Span<'a>],
+    inside: impl Fn(bool, bool) -> bool,
+) -> Vec<Span<'a>> {
+    // Where the ray enters or exits either operand, in order
+    let mut events: Vec<(HitRecord, usize, bool)> = [a, b]
+        .iter()
+        .enumerate()
+        .flat_map(|(operand, spans)| {
+            spans.iter().flat_map(move |span| {
+                [
+                    span.enter().map(|record| (record, operand, true)),
+                    span.exit().map(|record| (record, operand, false)),
+                ]
+            })
+        })
+        .flatten()
+        .collect();
+    events.sort_by(|(a, _, _), (b, _, _)| a.t().total_cmp(&b.t()));
+
+    // Spans without an entry start before the ray does
+    let mut within = [a, b].map(|spans| spans.first().is_some_and(|span| span.enter().is_none()));
+    let mut was_inside = inside(within[0], within[1]);
+    let mut enter = None;
+    let mut spans = Vec::new();
+    for (record, operand, entering) in events {
+        within[operand] = entering;
+        let is_inside = inside(within[0], within[1]);
+        match (was_inside, is_inside) {
+            (false, true) => enter = Some(record.with_front_face(true)),
+            (true, false) => {
+                spans.push(Span::new(enter.take(), Some(record.with_front_face(false))))
+            }
+            _ => {}
+        }
+        was_inside = is_inside;
+    }
+    if was_inside {
+        spans.push(Span::new(enter, None));
+    }
+    spans
+}
+
+fn resolve_all(objects: &mut [Object], library: &MaterialLibrary) -> Result<()> {
+    objects
+        .iter_mut()
+        .try_for_each(|object| object.resolve_materials(library))
+}
+
+impl ResolveMaterials for Union {
+    fn resolve_materials(&mut self, library: &MaterialLibrary) -> Result<()> {
+        resolve_all(&mut self.objects, library)
+    }
+}
+
+impl ResolveMaterials for Intersection {
+    fn resolve_materials(&mut self, library: &MaterialLibrary) -> Result<()> {
+        resolve_all(&mut self.objects, library)
+    }
+}
+
+impl ResolveMaterials for Difference {
+    fn resolve_materials(&mut self, library: &MaterialLibrary) -> Result<()> {
+        resolve_all(&mut self.objects, library)
+    }
+}
+
+fn load_all(objects: &mut [Object], directory: &Path) -> Result<()> {
+    objects
+        .iter_mut()
+        .try_for_each(|object| object.load_files(directory))?;
+    // Meshes are only known to be open once they are loaded
+    if objects.iter().any(is_open) {
+        return Err(anyhow!(
+            "A mesh combined with other objects is not closed, so it has no inside"
+        ));
+    }
+    Ok(())
+}
+
+impl LoadFiles for Union {
+    fn load_files(&mut self, directory: &Path) -> Result<()> {
+        load_all(&mut self.objects, directory)
+    }
+}
+
+impl LoadFiles for Intersection {
+    fn load_files(&mut self, directory: &Path) -> Result<()> {
+        load_all(&mut self.objects, directory)
+    }
+}
+
+impl LoadFiles for Difference {
+    fn load_files(&mut self, directory: &Path) -> Result<()> {
+        load_all(&mut self.objects, directory)
+    }
+}
+
+/// Validate the operands, warning when a single one makes the operation pointless
+fn validate_operands(objects: &[Object], path: &str, problems: &mut Problems) {
+    let objects_path = join(path, "objects");
+    match objects.len() {
+        0 => problems.error(&objects_path, "At least one object is needed"),
+        1 => problems.warning(&objects_path, "A single object is left as it is"),
+        _ => {}
+    }
+    for (index, object) in objects.iter().enumerate() {
+        let path = join(&objects_path, &index.to_string());
+        if is_medium(object) {
+            problems.error(
+                &path,
+                "Media have no surface to combine, use the combined object as a boundary instead",
+            );
+        } else if is_open(object) {
+            problems.error(
+                &path,
+                "Open surfaces have no inside to combine, use closed objects instead",
+            );
+        }
+        object.validate(&path, problems);
+    }
+}
+
+/// Whether the object is a medium, even when named or placed by an instance
+fn is_medium(object: &Object) -> bool {
+    match object {
+        Object::ConstantMedium(_) | Object::Volume(_) => true,
+        Object::Named(named) => is_medium(named.object()),
+        Object::Instance(instance) => is_medium(instance.object()),
+        _ => false,
+    }
+}
+
+/// Whether the object is a surface without an inside, even when named or placed by an
+/// instance. A plane bounds the half space behind it, so it is not open.
+fn is_open(object: &Object) -> bool {
+    match object {
+        Object::Quad(_) | Object::Disk(_) | Object::Heightfield(_) => true,
+        Object::Cylinder(cylinder) => !cylinder.is_capped(),
+        Object::Cone(cone) => !cone.is_capped(),
+        Object::Mesh(mesh) => mesh.is_closed() == Some(false),
+        Object::Named(named) => is_open(named.object()),
+        Object::Instance(instance) => is_open(instance.object()),
+        _ => false,
+    }
+}
+
+impl Validate for Union {
+    fn validate(&self, path: &str, problems: &mut Problems) {
+        validate_operands(&self.objects, path, problems);
+    }
+}
+
+impl Validate for Intersection {
+    fn validate(&self, path: &str, problems: &mut Problems) {
+        validate_operands(&self.objects, path, problems);
+    }
+}
+
+impl Validate for Difference {
+    fn validate(&self, path: &str, problems: &mut Problems) {
+        validate_operands(&self.objects, path, problems);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::{Isotropic, Lambertian, Material, SurfaceMaps};
+    use crate::object::mesh::TriangleMesh;
+    use crate::object::{ConstantMedium, Cylinder, Mesh, Named, Plane, Sphere};
+    use crate::vec3::Vec3;
+
+    fn sphere(x: f32, radius: f32) -> Object {
+        let material = Material::Lambertian(Lambertian {
+            albedo: Vec3::new(0.5, 0.5, 0.5),
+            maps: SurfaceMaps::default(),
+        });
+        Object::Sphere(Sphere::new(Vec3::new(x, 0., 0.), radius, material))
+    }
+
+    fn along_x() -> Ray {
+        Ray::new(Vec3::new(-10., 0., 0.), Vec3::new(1., 0., 0.))
+    }
+
+    fn ends(spans: &[Span]) -> Vec<(f32, f32)> {
+        let t = |record: Option<HitRecord>| record.map_or(f32::NAN, HitRecord::t);
+        spans
+            .iter()
+            .map(|span| (t(span.enter()), t(span.exit())))
+            .collect()
+    }
+
+    #[test]
+    fn test_operations() {
+        // Spheres covering x in [-2, 2] and [1, 3]
+        let (a, b) = (|| sphere(0., 2.), || sphere(2., 1.));
+
+        let union = Union::new(vec![a(), b()]);
+        assert_eq!(ends(&union.spans(along_x())), vec![(8., 13.)]);
+
+        let intersection = Intersection::new(vec![a(), b()]);
+        assert_eq!(ends(&intersection.spans(along_x())), vec![(11., 12.)]);
+
+        let difference = Difference::new(vec![a(), b()]);
+        assert_eq!(ends(&difference.spans(along_x())), vec![(8., 11.)]);
+    }
+
+    #[test]
+    fn test_difference_cut_faces_the_ray() {
+        // A hollow shell, entered through the inner sphere from the inside
+        let shell = Difference::new(vec![sphere(0., 2.), sphere(0., 1.)]);
+        let ray = Ray::new(Vec3::new(0., 0., 0.), Vec3::new(1., 0., 0.));
+        let record = shell.hit(ray, 0.001, f32::MAX).unwrap();
+        assert!((record.t() - 1.).abs() < 1e-5);
+        assert!(record.is_front_face());
+        assert_eq!(record.normal(), Vec3::new(-1., 0., 0.));
+
+        let record = shell.hit(ray, 1.001, f32::MAX).unwrap();
+        assert!((record.t() - 2.).abs() < 1e-5);
+        assert!(!record.is_front_face());
+    }
+
+    #[test]
+    fn test_media_are_not_operands() {
+        let fog = Material::Isotropic(Isotropic {
+            albedo: Vec3::new(1., 1., 1.),
+        });
+        let medium = ConstantMedium::new(sphere(2., 1.), 0.5, fog);
+        let union = Union::new(vec![
+            sphere(0., 2.),
+            Object::Named(Named::new("fog", Object::ConstantMedium(medium))),
+        ]);
+
+        let mut problems = Problems::new(Vec::new());
+        union.validate("Union", &mut problems);
+        let paths: Vec<_> = problems
+            .into_vec()
+            .into_iter()
+            .map(|problem| problem.path)
+            .collect();
+        assert_eq!(paths, ["Union.objects.1"]);
+    }
+
+    #[test]
+    fn test_open_surfaces_are_not_operands() {
+        let grey = || {
+            Material::Lambertian(Lambertian {
+                albedo: Vec3::new(0.5, 0.5, 0.5),
+                maps: SurfaceMaps::default(),
+            })
+        };
+        let (base, top) = (Vec3::new(0., 0., 0.), Vec3::new(0., 1., 0.));
+        let pipe = Cylinder::new(base, top, 1., grey()).uncapped();
+        let triangle = TriangleMesh {
+            positions: vec![base, top, Vec3::new(1., 0., 0.)],
+            triangles: vec![[0, 1, 2]],
+            ..TriangleMesh::default()
+        };
+        let sheet = Mesh::from_triangles("sheet.ply".into(), triangle, grey(), None);
+        let union = Union::new(vec![
+            sphere(0., 2.),
+            Object::Named(Named::new("pipe", Object::Cylinder(pipe))),
+            Object::Mesh(sheet),
+            Object::Plane(Plane::new(base, top, grey())),
+        ]);
+
+        let mut problems = Problems::new(Vec::new());
+        union.validate("Union", &mut problems);
+        let paths: Vec<_> = problems
+            .into_vec()
+            .into_iter()
+            .map(|problem| problem.path)
+            .collect();
+        assert_eq!(paths, ["Union.objects.1", "Union.objects.2"]);
+    }
+}
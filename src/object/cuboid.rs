@@ -0,0 +1,159 @@
+use std::path::Path;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::aabb::Aabb;
+use crate::material::{MaterialLibrary, MaterialRef};
+use crate::object::{HitRecord, Hittable, LoadFiles, ResolveMaterials};
+use crate::ray::Ray;
+use crate::validate::{join, Problems, Validate};
+use crate::vec3::{Point, Vec3};
+
+/// An axis-aligned box between the corners `min` and `max`. Use an instance to rotate it.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct Cuboid {
+    min: Point,
+    max: Point,
+    material: MaterialRef,
+}
+
+impl Cuboid {
+    pub fn new<M: Into<MaterialRef>>(min: Point, max: Point, material: M) -> Self {
+        Self {
+            min,
+            max,
+            material: material.into(),
+        }
+    }
+}
+
+impl Hittable for Cuboid {
+    fn hit(&self, ray: Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+        let origin = [ray.origin().x(), ray.origin().y(), ray.origin().z()];
+        let direction = [
+            ray.direction().x(),
+            ray.direction().y(),
+            ray.direction().z(),
+        ];
+        let min = [self.min.x(), self.min.y(), self.min.z()];
+        let max = [self.max.x(), self.max.y(), self.max.z()];
+
+        // Where the ray enters and leaves the box, and through which face
+        let (mut near, mut far) = (f32::MIN, f32::MAX);
+        let (mut near_face, mut far_face) = ((0, 0.), (0, 0.));
+        for axis in 0..3 {
+            if direction[axis] == 0. {
+                if origin[axis] < min[axis] || origin[axis] > max[axis] {
+                    return None;
+                }
+                continue;
+            }
+            let inverse = 1. / direction[axis];
+            let (mut t0, mut t1) = (
+                (min[axis] - origin[axis]) * inverse,
+                (max[axis] - origin[axis]) * inverse,
+            );
+            let (mut sign0, mut sign1) = (-1., 1.);
+            if inverse < 0. {
+                std::mem::swap(&mut t0, &mut t1);
+                std::mem::swap(&mut sign0, &mut sign1);
+            }
+            if t0 > near {
+                near = t0;
+                near_face = (axis, sign0);
+            }
+            if t1 < far {
+                far = t1;
+                far_face = (axis, sign1);
+            }
+        }
+        if far < near {
+            return None;
+        }
+
+        let (t, (axis, sign)) = if (t_min..=t_max).contains(&near) {
+            (near, near_face)
+        } else if (t_min..=t_max).contains(&far) {
+            (far, far_face)
+        } else {
+            return None;
+        };
+
+        let mut normal = [0.; 3];
+        normal[axis] = sign;
+        let outward_normal = Vec3::new(normal[0], normal[1], normal[2]);
+
+        // The face's coordinates along the two other axes, in [0, 1]
+        let point = ray.at(t);
+        let point = [point.x(), point.y(), point.z()];
+        let along = |axis: usize| (point[axis] - min[axis]) / (max[axis] - min[axis]);
+        let (u, v) = (along((axis + 1) % 3), along((axis + 2) % 3));
+
+        let hit = HitRecord::facing(ray, t, outward_normal, self.material.material());
+        Some(hit.with_uv(u, v))
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(Aabb::new(self.min, self.max))
+    }
+}
+
+impl ResolveMaterials for Cuboid {
+    fn resolve_materials(&mut self, library: &MaterialLibrary) -> Result<()> {
+        self.material.resolve(library)
+    }
+}
+
+impl LoadFiles for Cuboid {
+    fn load_files(&mut self, directory: &Path) -> Result<()> {
+        self.material.load_files(directory)
+    }
+}
+
+impl Validate for Cuboid {
+    fn validate(&self, path: &str, problems: &mut Problems) {
+        let size = self.max - self.min;
+        if size.x() <= 0. || size.y() <= 0. || size.z() <= 0. {
+            problems.error(
+                &join(path, "max"),
+                "Every coordinate of max must be larger than that of min",
+            );
+        }
+        self.material.validate(&join(path, "material"), problems);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::{Lambertian, Material, SurfaceMaps};
+
+    fn unit_box() -> Cuboid {
+        let material = Material::Lambertian(Lambertian {
+            albedo: Vec3::new(0.5, 0.5, 0.5),
+            maps: SurfaceMaps::default(),
+        });
+        Cuboid::new(Vec3::new(-1., -1., -1.), Vec3::new(1., 1., 1.), material)
+    }
+
+    #[test]
+    fn test_hit_face_normal() {
+        let ray = Ray::new(Vec3::new(0.5, 5., 0.), Vec3::new(0., -1., 0.));
+        let cuboid = unit_box();
+        let record = cuboid.hit(ray, 0., f32::MAX).unwrap();
+        assert!((record.t() - 4.).abs() < 1e-5);
+        assert_eq!(record.normal(), Vec3::new(0., 1., 0.));
+        assert!(record.is_front_face());
+    }
+
+    #[test]
+    fn test_hit_from_inside() {
+        let ray = Ray::new(Vec3::new(0., 0., 0.), Vec3::new(1., 0., 0.));
+        let cuboid = unit_box();
+        let record = cuboid.hit(ray, 0.001, f32::MAX).unwrap();
+        assert!((record.t() - 1.).abs() < 1e-5);
+        assert_eq!(record.normal(), Vec3::new(-1., 0., 0.));
+        assert!(!record.is_front_face());
+    }
+}
@@ -0,0 +1,135 @@
+use std::path::Path;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::aabb::Aabb;
+use crate::material::{MaterialLibrary, MaterialRef};
+use crate::object::cone::{default_capped, Frustum};
+use crate::object::{HitRecord, Hittable, LoadFiles, ResolveMaterials};
+use crate::ray::Ray;
+use crate::validate::{join, Problems, Validate};
+use crate::vec3::Point;
+
+/// A cylinder from the center of its base to the center of its top
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct Cylinder {
+    base: Point,
+    top: Point,
+    radius: f32,
+    /// Whether the ends are closed off by disks, or left open like a pipe
+    #[serde(default = "default_capped")]
+    capped: bool,
+    material: MaterialRef,
+}
+
+impl Cylinder {
+    pub fn new<M: Into<MaterialRef>>(base: Point, top: Point, radius: f32, material: M) -> Self {
+        Self {
+            base,
+            top,
+            radius,
+            capped: default_capped(),
+            material: material.into(),
+        }
+    }
+
+    /// The same cylinder without its ends
+    #[must_use]
+    pub fn uncapped(self) -> Self {
+        Self {
+            capped: false,
+            ..self
+        }
+    }
+
+    pub fn is_capped(&self) -> bool {
+        self.capped
+    }
+
+    fn frustum(&self) -> Frustum {
+        Frustum {
+            base: self.base,
+            top: self.top,
+            base_radius: self.radius,
+            top_radius: self.radius,
+            capped: self.capped,
+        }
+    }
+}
+
+impl Hittable for Cylinder {
+    fn hit(&self, ray: Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+        let (t, outward_normal, (u, v)) = self.frustum().hit(ray, t_min, t_max)?;
+        Some(HitRecord::facing(ray, t, outward_normal, self.material.material()).with_uv(u, v))
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(self.frustum().bounding_box())
+    }
+}
+
+impl ResolveMaterials for Cylinder {
+    fn resolve_materials(&mut self, library: &MaterialLibrary) -> Result<()> {
+        self.material.resolve(library)
+    }
+}
+
+impl LoadFiles for Cylinder {
+    fn load_files(&mut self, directory: &Path) -> Result<()> {
+        self.material.load_files(directory)
+    }
+}
+
+impl Validate for Cylinder {
+    fn validate(&self, path: &str, problems: &mut Problems) {
+        if self.base == self.top {
+            problems.error(&join(path, "top"), "The top must differ from the base");
+        }
+        if self.radius <= 0. {
+            problems.error(&join(path, "radius"), "The radius must be positive");
+        }
+        self.material.validate(&join(path, "material"), problems);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::{Lambertian, Material, SurfaceMaps};
+    use crate::vec3::Vec3;
+
+    fn pipe() -> Cylinder {
+        let material = Material::Lambertian(Lambertian {
+            albedo: Vec3::new(0.5, 0.5, 0.5),
+            maps: SurfaceMaps::default(),
+        });
+        Cylinder::new(Vec3::new(0., 0., 0.), Vec3::new(0., 2., 0.), 1., material)
+    }
+
+    #[test]
+    fn test_hit_side_and_cap() {
+        let pipe = pipe();
+        let side = Ray::new(Vec3::new(5., 1.5, 0.), Vec3::new(-1., 0., 0.));
+        let record = pipe.hit(side, 0., f32::MAX).unwrap();
+        assert!((record.t() - 4.).abs() < 1e-5);
+        assert!((record.normal() - Vec3::new(1., 0., 0.)).length() < 1e-5);
+        assert!((record.uv().1 - 0.75).abs() < 1e-5);
+
+        let top = Ray::new(Vec3::new(0.5, 5., 0.), Vec3::new(0., -1., 0.));
+        let record = pipe.hit(top, 0., f32::MAX).unwrap();
+        assert!((record.t() - 3.).abs() < 1e-5);
+        assert!((record.normal() - Vec3::new(0., 1., 0.)).length() < 1e-5);
+    }
+
+    #[test]
+    fn test_uncapped_hits_inside() {
+        // Looking down the open pipe hits the far wall from the inside
+        let ray = Ray::new(Vec3::new(0., 5., 0.), Vec3::new(0.3, -1., 0.).unit_vector());
+        let uncapped = pipe().uncapped();
+        let record = uncapped.hit(ray, 0., f32::MAX).unwrap();
+        assert!(!record.is_front_face());
+        assert!((record.point().x() - 1.).abs() < 1e-4);
+        assert!(pipe().hit(ray, 0., f32::MAX).unwrap().is_front_face());
+    }
+}
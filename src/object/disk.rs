@@ -0,0 +1,104 @@
+use std::f32::consts::PI;
+use std::path::Path;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::aabb::Aabb;
+use crate::material::{MaterialLibrary, MaterialRef};
+use crate::object::{tangents, HitRecord, Hittable, LoadFiles, ResolveMaterials};
+use crate::ray::Ray;
+use crate::validate::{join, Problems, Validate};
+use crate::vec3::{Point, Vec3};
+
+/// A flat circle around `center`, facing along `normal`
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct Disk {
+    center: Point,
+    normal: Vec3,
+    radius: f32,
+    material: MaterialRef,
+}
+
+impl Disk {
+    pub fn new<M: Into<MaterialRef>>(
+        center: Point,
+        normal: Vec3,
+        radius: f32,
+        material: M,
+    ) -> Self {
+        Self {
+            center,
+            normal,
+            radius,
+            material: material.into(),
+        }
+    }
+}
+
+impl Hittable for Disk {
+    fn hit(&self, ray: Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+        let normal = self.normal.unit_vector();
+        let denominator = normal.dot(ray.direction());
+        if denominator.abs() < 1e-8 {
+            return None;
+        }
+        let t = (self.center - ray.origin()).dot(normal) / denominator;
+        if t < t_min || t_max < t {
+            return None;
+        }
+        let offset = ray.at(t) - self.center;
+        if offset.length_squared() > self.radius * self.radius {
+            return None;
+        }
+
+        // The angle around the center and the distance from it
+        let (tangent, bitangent) = tangents(normal);
+        let u = (offset.dot(bitangent).atan2(offset.dot(tangent)) + PI) / (2. * PI);
+        let v = offset.length() / self.radius;
+        // u grows around the center and v away from it
+        let record = HitRecord::facing(ray, t, normal, self.material.material());
+        Some(
+            record
+                .with_tangents(normal.cross(offset), offset)
+                .with_uv(u, v),
+        )
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(disk_box(self.center, self.normal, self.radius).padded(1e-4))
+    }
+}
+
+impl ResolveMaterials for Disk {
+    fn resolve_materials(&mut self, library: &MaterialLibrary) -> Result<()> {
+        self.material.resolve(library)
+    }
+}
+
+impl LoadFiles for Disk {
+    fn load_files(&mut self, directory: &Path) -> Result<()> {
+        self.material.load_files(directory)
+    }
+}
+
+impl Validate for Disk {
+    fn validate(&self, path: &str, problems: &mut Problems) {
+        if self.normal.length_squared() == 0. {
+            problems.error(&join(path, "normal"), "The normal must not be zero");
+        }
+        if self.radius <= 0. {
+            problems.error(&join(path, "radius"), "The radius must be positive");
+        }
+        self.material.validate(&join(path, "material"), problems);
+    }
+}
+
+/// The box around a circle facing along the normal, shared by objects with circular ends
+pub(super) fn disk_box(center: Point, normal: Vec3, radius: f32) -> Aabb {
+    // How far the rim reaches along each axis
+    let normal = normal.unit_vector();
+    let reach = |axis: f32| radius * (1. - axis * axis).max(0.).sqrt();
+    let extent = Vec3::new(reach(normal.x()), reach(normal.y()), reach(normal.z()));
+    Aabb::new(center - extent, center + extent)
+}
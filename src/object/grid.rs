@@ -0,0 +1,277 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::validate::{join, Problems, Validate};
+use crate::vec3::Vec3;
+
+/// Values on a regular 3D grid filling the unit cube, with x changing fastest, then y, then z
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct Grid {
+    resolution: [usize; 3],
+    values: Vec<f32>,
+}
+
+impl Grid {
+    pub fn new(resolution: [usize; 3], values: Vec<f32>) -> Result<Self> {
+        let expected: usize = resolution.iter().product();
+        if expected == 0 || values.len() != expected {
+            return Err(anyhow!(
+                "A grid of {}x{}x{} needs {} values, but has {}",
+                resolution[0],
+                resolution[1],
+                resolution[2],
+                expected,
+                values.len()
+            ));
+        }
+        Ok(Self { resolution, values })
+    }
+
+    /// The largest value anywhere in the grid
+    pub fn max(&self) -> f32 {
+        self.values.iter().copied().fold(0., f32::max)
+    }
+
+    /// The value at a point in the unit cube, interpolated between the centers of the cells
+    pub fn sample(&self, point: Vec3) -> f32 {
+        let [nx, ny, nz] = self.resolution;
+        let cell = |coordinate: f32, n: usize| {
+            let x = (coordinate * n as f32 - 0.5).clamp(0., (n - 1) as f32);
+            let i = (x as usize).min(n.saturating_sub(2));
+            (i, (x - i as f32).min(1.))
+        };
+        let (x, fx) = cell(point.x(), nx);
+        let (y, fy) = cell(point.y(), ny);
+        let (z, fz) = cell(point.z(), nz);
+
+        let at = |dx: usize, dy: usize, dz: usize| {
+            let index =
+                (x + dx).min(nx - 1) + nx * ((y + dy).min(ny - 1) + ny * (z + dz).min(nz - 1));
+            self.values[index]
+        };
+        let lerp = |a: f32, b: f32, t: f32| a + (b - a) * t;
+        let along_x = |dy, dz| lerp(at(0, dy, dz), at(1, dy, dz), fx);
+        let along_y = |dz| lerp(along_x(0, dz), along_x(1, dz), fy);
+        lerp(along_y(0), along_y(1), fz)
+    }
+
+    /// Read a JSON grid, or raw little-endian 32-bit floats with the given resolution
+    fn load(path: &Path, resolution: Option<[usize; 3]>) -> Result<Self> {
+        let bytes = fs::read(path).map_err(|e| anyhow!("Cannot read {}: {}", path.display(), e))?;
+        if path.extension().is_some_and(|ext| ext == "json") {
+            let grid: Grid = serde_json::from_slice(&bytes)?;
+            return Grid::new(grid.resolution, grid.values);
+        }
+
+        let resolution =
+            resolution.ok_or_else(|| anyhow!("Raw grid {} needs a resolution", path.display()))?;
+        let values = bytes
+            .chunks_exact(4)
+            .map(|chunk| f32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]))
+            .collect();
+        Grid::new(resolution, values)
+    }
+
+    /// Fractal value noise in [0, 1]
+    fn noise(noise: &Noise) -> Result<Self> {
+        let [nx, ny, nz] = noise.resolution;
+        let mut values = Vec::with_capacity(nx * ny * nz);
+        for z in 0..nz {
+            for y in 0..ny {
+                for x in 0..nx {
+                    let point = Vec3::new(
+                        (x as f32 + 0.5) / nx as f32,
+                        (y as f32 + 0.5) / ny as f32,
+                        (z as f32 + 0.5) / nz as f32,
+                    );
+                    values.push(noise.at(point));
+                }
+            }
+        }
+        Grid::new(noise.resolution, values)
+    }
+}
+
+/// Where the values of a grid come from
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub enum GridSource {
+    /// A JSON file with `resolution` and `values`, or a file of raw little-endian 32-bit
+    /// floats with the resolution given here. The path is relative to the scene file.
+    File {
+        path: PathBuf,
+        resolution: Option<[usize; 3]>,
+        #[serde(skip)]
+        grid: Option<Arc<Grid>>,
+    },
+    /// Procedural noise baked into a grid when the scene is loaded
+    Noise(Noise),
+}
+
+impl GridSource {
+    pub fn load_files(&mut self, directory: &Path) -> Result<()> {
+        match self {
+            GridSource::File {
+                path,
+                resolution,
+                grid,
+            } => *grid = Some(Arc::new(Grid::load(&directory.join(path), *resolution)?)),
+            GridSource::Noise(noise) => noise.grid = Some(Arc::new(Grid::noise(noise)?)),
+        }
+        Ok(())
+    }
+
+    /// The grid, which is loaded when the scene is prepared
+    pub fn grid(&self) -> &Grid {
+        let grid = match self {
+            GridSource::File { grid, .. } | GridSource::Noise(Noise { grid, .. }) => grid,
+        };
+        grid.as_deref()
+            .expect("Volume grids are loaded when the scene is prepared")
+    }
+}
+
+impl Validate for GridSource {
+    fn validate(&self, path: &str, problems: &mut Problems) {
+        let resolution = match self {
+            GridSource::File {
+                path: file,
+                resolution,
+                ..
+            } => {
+                let is_json = file.extension().is_some_and(|ext| ext == "json");
+                if resolution.is_none() && !is_json {
+                    problems.error(
+                        &join(path, "File.resolution"),
+                        "Raw grid files need a resolution",
+                    );
+                }
+                *resolution
+            }
+            GridSource::Noise(noise) => Some(noise.resolution),
+        };
+        if resolution.is_some_and(|resolution| resolution.contains(&0)) {
+            problems.error(path, "The resolution must not be zero along any axis");
+        }
+    }
+}
+
+/// Octaves of value noise, each twice the frequency and half the amplitude of the last
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct Noise {
+    resolution: [usize; 3],
+    /// The number of noise cells across the grid in the first octave
+    #[serde(default = "default_frequency")]
+    frequency: f32,
+    #[serde(default = "default_octaves")]
+    octaves: u32,
+    #[serde(default)]
+    seed: u32,
+    /// Fade the noise out towards the sides of the grid, for puffs of cloud or smoke
+    #[serde(default = "default_rounded")]
+    rounded: bool,
+    #[serde(skip)]
+    grid: Option<Arc<Grid>>,
+}
+
+fn default_frequency() -> f32 {
+    4.
+}
+
+fn default_octaves() -> u32 {
+    4
+}
+
+fn default_rounded() -> bool {
+    true
+}
+
+impl Noise {
+    fn at(&self, point: Vec3) -> f32 {
+        let mut value = 0.;
+        let mut amplitude = 0.5;
+        let mut frequency = self.frequency;
+        for octave in 0..self.octaves {
+            value += amplitude * value_noise(point * frequency, self.seed.wrapping_add(octave));
+            amplitude /= 2.;
+            frequency *= 2.;
+        }
+        // Scale the total amplitude of the octaves up to 1
+        let value = value / (1. - amplitude * 2.).max(0.5);
+
+        if self.rounded {
+            let offset = point * 2. - Vec3::new(1., 1., 1.);
+            value * (1. - offset.length_squared()).max(0.)
+        } else {
+            value
+        }
+    }
+}
+
+/// Smoothly interpolated random values at the corners of unit cells, in [0, 1]
+fn value_noise(point: Vec3, seed: u32) -> f32 {
+    let floor = |x: f32| x.floor() as i32;
+    let (x, y, z) = (floor(point.x()), floor(point.y()), floor(point.z()));
+    let smooth = |t: f32| t * t * (3. - 2. * t);
+    let fx = smooth(point.x() - x as f32);
+    let fy = smooth(point.y() - y as f32);
+    let fz = smooth(point.z() - z as f32);
+
+    let corner = |dx: i32, dy: i32, dz: i32| lattice(x + dx, y + dy, z + dz, seed);
+    let lerp = |a: f32, b: f32, t: f32| a + (b - a) * t;
+    let along_x = |dy, dz| lerp(corner(0, dy, dz), corner(1, dy, dz), fx);
+    let along_y = |dz| lerp(along_x(0, dz), along_x(1, dz), fy);
+    lerp(along_y(0), along_y(1), fz)
+}
+
+/// A random value in [0, 1] for each point of the integer lattice
+fn lattice(x: i32, y: i32, z: i32, seed: u32) -> f32 {
+    let mut hash = seed
+        .wrapping_mul(0x9E37_79B9)
+        .wrapping_add(x as u32)
+        .wrapping_mul(0x85EB_CA6B)
+        .wrapping_add(y as u32)
+        .wrapping_mul(0xC2B2_AE35)
+        .wrapping_add(z as u32);
+    hash ^= hash >> 16;
+    hash = hash.wrapping_mul(0x7FEB_352D);
+    hash ^= hash >> 15;
+    hash = hash.wrapping_mul(0x846C_A68B);
+    hash ^= hash >> 16;
+    hash as f32 / u32::MAX as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sample_interpolates() {
+        let grid = Grid::new([2, 1, 1], vec![0., 1.]).unwrap();
+        // Cell centers are at 0.25 and 0.75
+        assert_eq!(grid.sample(Vec3::new(0.25, 0.5, 0.5)), 0.);
+        assert_eq!(grid.sample(Vec3::new(0.5, 0.5, 0.5)), 0.5);
+        assert_eq!(grid.sample(Vec3::new(1., 0.5, 0.5)), 1.);
+        assert!(Grid::new([2, 2, 1], vec![0., 1.]).is_err());
+    }
+
+    #[test]
+    fn test_noise_range() {
+        let noise = Noise {
+            resolution: [8, 8, 8],
+            frequency: 3.,
+            octaves: 5,
+            seed: 1,
+            rounded: true,
+            grid: None,
+        };
+        let grid = Grid::noise(&noise).unwrap();
+        assert!(grid.values.iter().all(|value| (0. ..=1.).contains(value)));
+        assert!(grid.max() > 0.);
+        // Rounded noise fades out at the corners
+        assert!(grid.sample(Vec3::new(0., 0., 0.)) < 0.1);
+    }
+}
@@ -0,0 +1,484 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::aabb::Aabb;
+use crate::image::Image;
+use crate::material::{MaterialLibrary, MaterialRef};
+use crate::object::{hit_triangle, HitRecord, Hittable, LoadFiles, ResolveMaterials};
+use crate::ray::Ray;
+use crate::validate::{join, Problems, Validate};
+use crate::vec3::{Point, Vec3};
+
+/// Terrain from a grid of elevations, spanning `size` along x and z from `corner`, and rising
+/// up to `size.y` above it for the highest value, which is white for images and the largest
+/// height for raw grids. The first row of the grid is at the corner's z, and its first column
+/// at the corner's x.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct Heightfield {
+    corner: Point,
+    size: Vec3,
+    heights: HeightSource,
+    material: MaterialRef,
+    #[serde(skip)]
+    map: Option<Arc<HeightMap>>,
+}
+
+/// Where the elevations come from. Paths are relative to the scene file.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub enum HeightSource {
+    /// A grayscale image such as an 8 or 16-bit PNG, with black the lowest and white the highest
+    Image { path: PathBuf },
+    /// Little-endian 32-bit floats, row by row, with `resolution` as columns and rows. They
+    /// are scaled so the lowest is at the corner and the highest `size.y` above it.
+    Raw {
+        path: PathBuf,
+        resolution: [usize; 2],
+    },
+}
+
+impl HeightSource {
+    fn load(&self, directory: &Path) -> Result<HeightMap> {
+        match self {
+            HeightSource::Image { path } => {
+                let image = Image::load_raw(&directory.join(path))?;
+                let heights = image
+                    .pixels()
+                    .iter()
+                    .map(|color| (color.x() + color.y() + color.z()) / 3.)
+                    .collect();
+                HeightMap::new(image.width() as usize, image.height() as usize, heights)
+            }
+            HeightSource::Raw { path, resolution } => {
+                let path = directory.join(path);
+                let bytes = fs::read(&path)
+                    .map_err(|e| anyhow!("Cannot read {}: {}", path.display(), e))?;
+                let heights: Vec<f32> = bytes
+                    .chunks_exact(4)
+                    .map(|chunk| f32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]))
+                    .collect();
+                let low = heights.iter().copied().fold(f32::MAX, f32::min);
+                let high = heights.iter().copied().fold(f32::MIN, f32::max);
+                let range = if high > low { high - low } else { 1. };
+                let heights = heights
+                    .iter()
+                    .map(|height| (height - low) / range)
+                    .collect();
+                HeightMap::new(resolution[0], resolution[1], heights)
+            }
+        }
+    }
+}
+
+/// The heights at the grid's points with their normals, and the lowest and highest heights
+/// of ever larger blocks of cells for skipping over empty space
+#[derive(Debug, PartialEq)]
+pub struct HeightMap {
+    columns: usize,
+    rows: usize,
+    heights: Vec<f32>,
+    normals: Vec<Vec3>,
+    /// Level 0 bounds single cells, each level above bounds 2x2 blocks of the one below
+    levels: Vec<Level>,
+}
+
+#[derive(Debug, PartialEq)]
+struct Level {
+    columns: usize,
+    rows: usize,
+    bounds: Vec<(f32, f32)>,
+}
+
+impl HeightMap {
+    fn new(columns: usize, rows: usize, heights: Vec<f32>) -> Result<Self> {
+        if columns < 2 || rows < 2 {
+            return Err(anyhow!("A heightfield needs at least 2x2 heights"));
+        }
+        if heights.len() != columns * rows {
+            return Err(anyhow!(
+                "A heightfield of {}x{} needs {} heights, but has {}",
+                columns,
+                rows,
+                columns * rows,
+                heights.len()
+            ));
+        }
+
+        // Normals from central differences, in grid units
+        let at = |x: usize, z: usize| heights[z * columns + x];
+        let mut normals = Vec::with_capacity(heights.len());
+        for z in 0..rows {
+            for x in 0..columns {
+                let slope_x = (at((x + 1).min(columns - 1), z) - at(x.saturating_sub(1), z))
+                    / ((x + 1).min(columns - 1) - x.saturating_sub(1)) as f32;
+                let slope_z = (at(x, (z + 1).min(rows - 1)) - at(x, z.saturating_sub(1)))
+                    / ((z + 1).min(rows - 1) - z.saturating_sub(1)) as f32;
+                normals.push(Vec3::new(-slope_x, 1., -slope_z));
+            }
+        }
+
+        let cells = Level {
+            columns: columns - 1,
+            rows: rows - 1,
+            bounds: (0..rows - 1)
+                .flat_map(|z| (0..columns - 1).map(move |x| (x, z)))
+                .map(|(x, z)| {
+                    let corners = [at(x, z), at(x + 1, z), at(x, z + 1), at(x + 1, z + 1)];
+                    let low = corners.iter().copied().fold(f32::MAX, f32::min);
+                    let high = corners.iter().copied().fold(f32::MIN, f32::max);
+                    (low, high)
+                })
+                .collect(),
+        };
+        let mut levels = vec![cells];
+        while let Some(below) = levels.last().filter(|level| level.columns * level.rows > 1) {
+            levels.push(below.coarser());
+        }
+
+        Ok(Self {
+            columns,
+            rows,
+            heights,
+            normals,
+            levels,
+        })
+    }
+
+    fn height(&self, x: usize, z: usize) -> f32 {
+        self.heights[z * self.columns + x]
+    }
+
+    fn normal(&self, x: usize, z: usize) -> Vec3 {
+        self.normals[z * self.columns + x]
+    }
+
+    /// The lowest and highest height anywhere
+    fn range(&self) -> (f32, f32) {
+        self.levels.last().unwrap().bounds[0]
+    }
+
+    /// The closest hit in grid units, where x and z count cells and y is the height
+    fn hit(&self, ray: Ray, t_min: f32, t_max: f32) -> Option<CellHit> {
+        let mut traversal = Traversal {
+            ray,
+            t_min,
+            t_max,
+            closest: None,
+        };
+        self.visit(self.levels.len() - 1, 0, 0, &mut traversal);
+        traversal.closest
+    }
+
+    /// Descend into the blocks of a level the ray passes through, nearest first, skipping
+    /// those whose range of heights it passes above or below
+    fn visit(&self, level: usize, x: usize, z: usize, traversal: &mut Traversal) {
+        let cells = 1 << level;
+        let (low, high) = self.levels[level].bounds[z * self.levels[level].columns + x];
+        let block = Aabb::new(
+            Vec3::new((x * cells) as f32, low, (z * cells) as f32),
+            Vec3::new(
+                ((x + 1) * cells).min(self.columns - 1) as f32,
+                high,
+                ((z + 1) * cells).min(self.rows - 1) as f32,
+            ),
+        );
+        let ray = traversal.ray;
+        let entry = match block.clip(ray, traversal.t_min, traversal.t_max) {
+            Some((entry, _)) => ray.at(entry),
+            None => return,
+        };
+        if level == 0 {
+            if let Some(hit) = self.hit_cell(x, z, traversal) {
+                traversal.t_max = hit.t;
+                traversal.closest = Some(hit);
+            }
+            return;
+        }
+
+        let below = &self.levels[level - 1];
+        let half = (cells / 2) as f32;
+        let distance = |&(x, z): &(usize, usize)| {
+            let dx = (x as f32 + 0.5) * half - entry.x();
+            let dz = (z as f32 + 0.5) * half - entry.z();
+            dx * dx + dz * dz
+        };
+        let mut children: Vec<(usize, usize)> = [(0, 0), (1, 0), (0, 1), (1, 1)]
+            .iter()
+            .map(|(dx, dz)| (2 * x + dx, 2 * z + dz))
+            .filter(|&(x, z)| x < below.columns && z < below.rows)
+            .collect();
+        children.sort_by(|a, b| distance(a).total_cmp(&distance(b)));
+        for (x, z) in children {
+            self.visit(level - 1, x, z, traversal);
+        }
+    }
+
+    /// Intersect the two triangles of a cell, interpolating the normals of its corners
+    fn hit_cell(&self, x: usize, z: usize, traversal: &Traversal) -> Option<CellHit> {
+        let corner = |dx: usize, dz: usize| {
+            let position = Vec3::new(
+                (x + dx) as f32,
+                self.height(x + dx, z + dz),
+                (z + dz) as f32,
+            );
+            (position, self.normal(x + dx, z + dz))
+        };
+        let (c00, c10, c01, c11) = (corner(0, 0), corner(1, 0), corner(0, 1), corner(1, 1));
+        [(c00, c10, c11), (c00, c11, c01)]
+            .iter()
+            .filter_map(|&(a, b, c)| {
+                let (t, u, v) = hit_triangle(traversal.ray, a.0, b.0, c.0)?;
+                (traversal.t_min..=traversal.t_max)
+                    .contains(&t)
+                    .then(|| CellHit {
+                        t,
+                        normal: (c.0 - a.0).cross(b.0 - a.0),
+                        smooth_normal: (1. - u - v) * a.1 + u * b.1 + v * c.1,
+                    })
+            })
+            .min_by(|a, b| a.t.total_cmp(&b.t))
+    }
+}
+
+/// A hit on one of the triangles of a cell, in grid units
+#[derive(Debug, Clone, Copy)]
+struct CellHit {
+    t: f32,
+    /// The normal of the flat triangle, which was hit
+    normal: Vec3,
+    /// The normal blended from the corners, to shade with
+    smooth_normal: Vec3,
+}
+
+/// A ray's walk down the levels, narrowing as closer hits are found
+struct Traversal {
+    ray: Ray,
+    t_min: f32,
+    t_max: f32,
+    closest: Option<CellHit>,
+}
+
+impl Level {
+    /// The level bounding 2x2 blocks of this one
+    fn coarser(&self) -> Level {
+        let columns = self.columns.div_ceil(2);
+        let rows = self.rows.div_ceil(2);
+        let bounds = (0..rows)
+            .flat_map(|z| (0..columns).map(move |x| (x, z)))
+            .map(|(x, z)| {
+                [(0, 0), (1, 0), (0, 1), (1, 1)]
+                    .iter()
+                    .map(|(dx, dz)| (2 * x + dx, 2 * z + dz))
+                    .filter(|&(x, z)| x < self.columns && z < self.rows)
+                    .map(|(x, z)| self.bounds[z * self.columns + x])
+                    .fold((f32::MAX, f32::MIN), |(low, high), (a, b)| {
+                        (low.min(a), high.max(b))
+                    })
+            })
+            .collect();
+        Level {
+            columns,
+            rows,
+            bounds,
+        }
+    }
+}
+
+impl Heightfield {
+    fn map(&self) -> &HeightMap {
+        self.map
+            .as_deref()
+            .expect("Heightfields are loaded when the scene is prepared")
+    }
+
+    /// How large a grid unit is along each axis
+    fn cell_size(&self) -> Vec3 {
+        let map = self.map();
+        Vec3::new(
+            self.size.x() / (map.columns - 1) as f32,
+            self.size.y(),
+            self.size.z() / (map.rows - 1) as f32,
+        )
+    }
+}
+
+impl Hittable for Heightfield {
+    fn hit(&self, ray: Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+        // Scale the ray into grid units, which keeps t the same
+        let scale = self.cell_size();
+        let divide = |v: Vec3| Vec3::new(v.x() / scale.x(), v.y() / scale.y(), v.z() / scale.z());
+        let local = Ray::new(divide(ray.origin() - self.corner), divide(ray.direction()));
+        let hit = self.map().hit(local, t_min, t_max)?;
+
+        // Normals scale inversely to points
+        let outward_normal = divide(hit.normal).unit_vector();
+        let smooth_normal = divide(hit.smooth_normal).unit_vector();
+        let point = local.at(hit.t);
+        let map = self.map();
+        let u = point.x() / (map.columns - 1) as f32;
+        let v = point.z() / (map.rows - 1) as f32;
+        let record = HitRecord::facing(ray, hit.t, outward_normal, self.material.material());
+        let smooth_normal = if record.is_front_face() {
+            smooth_normal
+        } else {
+            -smooth_normal
+        };
+        Some(
+            record
+                .with_tangents(Vec3::new(1., 0., 0.), Vec3::new(0., 0., 1.))
+                .with_shading_normal(smooth_normal)
+                .with_uv(u, v),
+        )
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let (low, high) = match &self.map {
+            Some(map) => map.range(),
+            None => (0., 1.),
+        };
+        Some(Aabb::new(
+            self.corner + Vec3::new(0., low * self.size.y(), 0.),
+            self.corner + Vec3::new(self.size.x(), high * self.size.y(), self.size.z()),
+        ))
+    }
+}
+
+impl ResolveMaterials for Heightfield {
+    fn resolve_materials(&mut self, library: &MaterialLibrary) -> Result<()> {
+        self.material.resolve(library)
+    }
+}
+
+impl LoadFiles for Heightfield {
+    fn load_files(&mut self, directory: &Path) -> Result<()> {
+        self.material.load_files(directory)?;
+        self.map = Some(Arc::new(self.heights.load(directory)?));
+        Ok(())
+    }
+}
+
+impl Validate for Heightfield {
+    fn validate(&self, path: &str, problems: &mut Problems) {
+        if self.size.x() <= 0. || self.size.z() <= 0. {
+            problems.error(
+                &join(path, "size"),
+                "The size along x and z must be positive",
+            );
+        }
+        if self.size.y() == 0. || self.size.y().is_nan() {
+            problems.error(
+                &join(path, "size"),
+                "The height must not be zero, flat ground is better made with a Quad",
+            );
+        }
+        if let HeightSource::Raw { resolution, .. } = &self.heights {
+            if resolution[0] < 2 || resolution[1] < 2 {
+                problems.error(
+                    &join(path, "heights.Raw.resolution"),
+                    "At least 2x2 heights are needed",
+                );
+            }
+        }
+        self.material.validate(&join(path, "material"), problems);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::{Lambertian, Material, SurfaceMaps};
+    use crate::primitive::random;
+
+    fn heightfield(columns: usize, rows: usize, heights: Vec<f32>) -> Heightfield {
+        Heightfield {
+            corner: Vec3::new(0., 0., 0.),
+            size: Vec3::new(2., 1., 2.),
+            heights: HeightSource::Raw {
+                path: "heights.raw".into(),
+                resolution: [columns, rows],
+            },
+            material: Material::Lambertian(Lambertian {
+                albedo: Vec3::new(0.5, 0.5, 0.5),
+                maps: SurfaceMaps::default(),
+            })
+            .into(),
+            map: Some(Arc::new(HeightMap::new(columns, rows, heights).unwrap())),
+        }
+    }
+
+    #[test]
+    fn test_ramp_normal_and_uv() {
+        // Rises by 1 over 2 units of x
+        let ramp = heightfield(3, 3, vec![0., 0.5, 1., 0., 0.5, 1., 0., 0.5, 1.]);
+        let ray = Ray::new(Vec3::new(1., 5., 0.5), Vec3::new(0., -1., 0.));
+        let record = ramp.hit(ray, 0., f32::MAX).unwrap();
+        assert!((record.point() - Vec3::new(1., 0.5, 0.5)).length() < 1e-4);
+        let expected = Vec3::new(-0.5, 1., 0.).unit_vector();
+        assert!((record.normal() - expected).length() < 1e-4);
+        assert!((record.geometric_normal() - expected).length() < 1e-4);
+        assert!(record.is_front_face());
+        let (u, v) = record.uv();
+        assert!((u - 0.5).abs() < 1e-4 && (v - 0.25).abs() < 1e-4);
+
+        let outside = Ray::new(Vec3::new(3., 5., 0.5), Vec3::new(0., -1., 0.));
+        assert!(ramp.hit(outside, 0., f32::MAX).is_none());
+    }
+
+    #[test]
+    fn test_raw_heights_are_normalized() {
+        let directory = std::env::temp_dir();
+        let path = directory.join("raytracer_heights.raw");
+        let bytes: Vec<u8> = [-2f32, 0., 2., 6.]
+            .iter()
+            .flat_map(|height| height.to_le_bytes())
+            .collect();
+        fs::write(&path, bytes).unwrap();
+        let source = HeightSource::Raw {
+            path: "raytracer_heights.raw".into(),
+            resolution: [2, 2],
+        };
+        let map = source.load(&directory).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(map.heights, [0., 0.25, 0.5, 1.]);
+    }
+
+    #[test]
+    fn test_traversal_matches_every_cell() {
+        random::seed(11);
+        let (columns, rows) = (13, 9);
+        let heights = (0..columns * rows)
+            .map(|_| random::gen_range(0. ..1.))
+            .collect();
+        let terrain = heightfield(columns, rows, heights);
+        let map = terrain.map();
+        for _ in 0..200 {
+            let origin = Vec3::new(
+                random::gen_range(-2. ..14.),
+                random::gen_range(0.5..2.),
+                random::gen_range(-2. ..10.),
+            );
+            let target = Vec3::new(
+                random::gen_range(0. ..12.),
+                random::gen_range(0. ..1.),
+                random::gen_range(0. ..8.),
+            );
+            let ray = Ray::new(origin, target - origin);
+            let traversal = Traversal {
+                ray,
+                t_min: 0.,
+                t_max: f32::MAX,
+                closest: None,
+            };
+            let every_cell = (0..rows - 1)
+                .flat_map(|z| (0..columns - 1).map(move |x| (x, z)))
+                .filter_map(|(x, z)| map.hit_cell(x, z, &traversal))
+                .map(|hit| hit.t)
+                .reduce(f32::min);
+            let walked = map.hit(ray, 0., f32::MAX).map(|hit| hit.t);
+            assert_eq!(walked, every_cell);
+        }
+    }
+}
@@ -0,0 +1,230 @@
+use std::path::Path;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::aabb::Aabb;
+use crate::material::MaterialLibrary;
+use crate::object::{
+    motion_fraction, validate_motion, HitRecord, Hittable, LoadFiles, Object, ResolveMaterials,
+    Span, Transform,
+};
+use crate::ray::Ray;
+use crate::validate::{join, Problems, Validate};
+
+/// The number of steps the motion of an instance is sampled at to bound it
+const MOTION_STEPS: usize = 16;
+
+/// Another object placed in the world by a transform. With an `end_transform`, the transform
+/// is interpolated from `transform` at `time_start` to `end_transform` at `time_end`.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct Instance {
+    object: Box<Object>,
+    #[serde(default)]
+    transform: Transform,
+    end_transform: Option<Transform>,
+    #[serde(default)]
+    time_start: f32,
+    #[serde(default = "default_time_end")]
+    time_end: f32,
+}
+
+fn default_time_end() -> f32 {
+    1.
+}
+
+impl Instance {
+    pub fn new(object: Object, transform: Transform) -> Self {
+        Self {
+            object: Box::new(object),
+            transform,
+            end_transform: None,
+            time_start: 0.,
+            time_end: 1.,
+        }
+    }
+
+    /// Move from `transform` to `end_transform` between the two times
+    #[must_use]
+    pub fn moving_to(self, end_transform: Transform, time_start: f32, time_end: f32) -> Self {
+        Self {
+            end_transform: Some(end_transform),
+            time_start,
+            time_end,
+            ..self
+        }
+    }
+
+    pub fn object(&self) -> &Object {
+        &self.object
+    }
+
+    pub fn transform(&self, time: f32) -> Transform {
+        match self.end_transform {
+            Some(end) => {
+                let t = motion_fraction(time, self.time_start, self.time_end);
+                self.transform.lerp(end, t)
+            }
+            None => self.transform,
+        }
+    }
+}
+
+impl Hittable for Instance {
+    fn hit(&self, ray: Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+        let transform = self.transform(ray.time());
+        let record = self.object.hit(to_local(transform, ray), t_min, t_max)?;
+        Some(to_world(transform, ray, record))
+    }
+
+    fn spans(&self, ray: Ray) -> Vec<Span<'_>> {
+        let transform = self.transform(ray.time());
+        let spans = self.object.spans(to_local(transform, ray));
+        spans
+            .into_iter()
+            .map(|span| span.map(|record| to_world(transform, ray, record)))
+            .collect()
+    }
+
+    fn collide(&self, ray: Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+        let transform = self.transform(ray.time());
+        let record = self
+            .object
+            .collide(to_local(transform, ray), t_min, t_max)?;
+        Some(to_world(transform, ray, record))
+    }
+
+    fn transmittance(&self, ray: Ray, t_min: f32, t_max: f32) -> f32 {
+        let transform = self.transform(ray.time());
+        self.object
+            .transmittance(to_local(transform, ray), t_min, t_max)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let aabb = self.object.bounding_box()?;
+        let end = match self.end_transform {
+            Some(end) => end,
+            None => return Some(self.transform.bounding_box(aabb)),
+        };
+
+        // Rotating corners sweep arcs, which bulge past the boxes at the sampled steps by at
+        // most the sagitta of the arc between two steps
+        let radius = aabb
+            .corners()
+            .iter()
+            .map(|corner| corner.length())
+            .fold(0., f32::max);
+        let largest_scale = [self.transform.scale, end.scale]
+            .iter()
+            .flat_map(|scale| [scale.x().abs(), scale.y().abs(), scale.z().abs()])
+            .fold(0., f32::max);
+        let turn = end.rotate - self.transform.rotate;
+        let step_angle =
+            (turn.x().abs() + turn.y().abs() + turn.z().abs()).to_radians() / MOTION_STEPS as f32;
+        let sagitta = radius * largest_scale * (1. - (step_angle / 2.).cos());
+
+        (0..=MOTION_STEPS)
+            .map(|step| {
+                let transform = self.transform.lerp(end, step as f32 / MOTION_STEPS as f32);
+                transform.bounding_box(aabb)
+            })
+            .reduce(Aabb::surrounding)
+            .map(|aabb| aabb.padded(sagitta))
+    }
+}
+
+/// The ray in object space. The direction is not normalized, so t is the same in both.
+fn to_local(transform: Transform, ray: Ray) -> Ray {
+    Ray::new(
+        transform.inverse_point(ray.origin()),
+        transform.inverse_vector(ray.direction()),
+    )
+    .with_time(ray.time())
+}
+
+/// A hit in object space moved back into the world
+fn to_world(transform: Transform, ray: Ray, record: HitRecord) -> HitRecord {
+    record.moved(
+        ray.at(record.t()),
+        |normal| transform.normal(normal),
+        |vector| transform.vector(vector),
+    )
+}
+
+impl ResolveMaterials for Instance {
+    fn resolve_materials(&mut self, library: &MaterialLibrary) -> Result<()> {
+        self.object.resolve_materials(library)
+    }
+}
+
+impl LoadFiles for Instance {
+    fn load_files(&mut self, directory: &Path) -> Result<()> {
+        self.object.load_files(directory)
+    }
+}
+
+impl Validate for Instance {
+    fn validate(&self, path: &str, problems: &mut Problems) {
+        self.transform.validate(&join(path, "transform"), problems);
+        if let Some(end) = self.end_transform {
+            end.validate(&join(path, "end_transform"), problems);
+            validate_motion(self.time_start, self.time_end, path, problems);
+        }
+        self.object.validate(&join(path, "object"), problems);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::{Lambertian, Material, SurfaceMaps};
+    use crate::object::Sphere;
+    use crate::vec3::Vec3;
+
+    fn unit_sphere() -> Object {
+        let material = Material::Lambertian(Lambertian {
+            albedo: Vec3::new(0.5, 0.5, 0.5),
+            maps: SurfaceMaps::default(),
+        });
+        Object::Sphere(Sphere::new(Vec3::new(0., 0., 0.), 1., material))
+    }
+
+    #[test]
+    fn test_hit_at_ray_time() {
+        let start = Transform::default();
+        let end = Transform {
+            translate: Vec3::new(10., 0., 0.),
+            ..Transform::default()
+        };
+        let instance = Instance::new(unit_sphere(), start).moving_to(end, 0., 1.);
+
+        let ray = Ray::new(Vec3::new(10., 0., 5.), Vec3::new(0., 0., -1.));
+        assert!(instance.hit(ray, 0., f32::MAX).is_none());
+        let record = instance.hit(ray.with_time(1.), 0., f32::MAX).unwrap();
+        assert!((record.point() - Vec3::new(10., 0., 1.)).length() < 1e-5);
+        assert!((record.normal() - Vec3::new(0., 0., 1.)).length() < 1e-5);
+    }
+
+    #[test]
+    fn test_bounding_box_covers_motion() {
+        let offset = Transform {
+            translate: Vec3::new(3., 0., 0.),
+            ..Transform::default()
+        };
+        let half_turn = Transform {
+            rotate: Vec3::new(0., 180., 0.),
+            ..Transform::default()
+        };
+        // Moved out to x = 3, then spun half way around the origin through z = -3
+        let orbit = Instance::new(
+            Object::Instance(Instance::new(unit_sphere(), offset)),
+            Transform::default(),
+        )
+        .moving_to(half_turn, 0., 1.);
+
+        let aabb = orbit.bounding_box().unwrap();
+        assert!(aabb.min().x() <= -4. + 1e-4);
+        assert!(aabb.max().x() >= 4. - 1e-4);
+        assert!(aabb.min().z() <= -4. + 1e-4);
+    }
+}
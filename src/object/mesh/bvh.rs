@@ -0,0 +1,270 @@
+use crate::aabb::Aabb;
+use crate::ray::Ray;
+use crate::vec3::Point;
+
+/// Buckets the centers are sorted into when looking for the cheapest split
+const BINS: usize = 12;
+/// The most primitives a leaf may hold when no split is cheaper
+const MAX_LEAF_SIZE: usize = 8;
+
+/// A bounding volume hierarchy over primitives given by their boxes, split where the surface
+/// area heuristic expects the fewest box and primitive tests
+#[derive(Debug, PartialEq)]
+pub struct Bvh {
+    /// Depth first, so a branch's first child follows it
+    nodes: Vec<Node>,
+    /// The primitives in the order the leaves refer to them
+    order: Vec<usize>,
+}
+
+#[derive(Debug, PartialEq)]
+struct Node {
+    aabb: Aabb,
+    kind: NodeKind,
+}
+
+#[derive(Debug, PartialEq)]
+enum NodeKind {
+    Leaf {
+        start: usize,
+        count: usize,
+    },
+    /// Split along the axis, with the lower child first and the higher one at `second`
+    Branch {
+        axis: usize,
+        second: usize,
+    },
+}
+
+impl Bvh {
+    pub fn new(boxes: &[Aabb]) -> Self {
+        let mut bvh = Self {
+            nodes: Vec::new(),
+            order: (0..boxes.len()).collect(),
+        };
+        if !boxes.is_empty() {
+            let centers: Vec<Point> = boxes.iter().map(|aabb| aabb.center()).collect();
+            bvh.build(boxes, &centers, 0, boxes.len());
+        }
+        bvh
+    }
+
+    /// The box around every primitive, or None if there are none
+    pub fn bounding_box(&self) -> Option<Aabb> {
+        self.nodes.first().map(|node| node.aabb)
+    }
+
+    /// Add the node for the primitives from start to end in the order, returning its index
+    fn build(&mut self, boxes: &[Aabb], centers: &[Point], start: usize, end: usize) -> usize {
+        let aabb = self.order[start..end]
+            .iter()
+            .map(|&i| boxes[i])
+            .reduce(Aabb::surrounding)
+            .unwrap();
+        let index = self.nodes.len();
+        let count = end - start;
+        self.nodes.push(Node {
+            aabb,
+            kind: NodeKind::Leaf { start, count },
+        });
+        if count == 1 {
+            return index;
+        }
+
+        let (axis, middle) = match self.split(boxes, centers, aabb, start, end) {
+            Some(split) => split,
+            None if count <= MAX_LEAF_SIZE => return index,
+            None => self.split_in_half(centers, start, end),
+        };
+        self.build(boxes, centers, start, middle);
+        let second = self.build(boxes, centers, middle, end);
+        self.nodes[index].kind = NodeKind::Branch { axis, second };
+        index
+    }
+
+    /// Partition the primitives at the cheapest split by the surface area heuristic, if it is
+    /// cheaper than testing them all, returning the axis and the index of the split
+    fn split(
+        &mut self,
+        boxes: &[Aabb],
+        centers: &[Point],
+        aabb: Aabb,
+        start: usize,
+        end: usize,
+    ) -> Option<(usize, usize)> {
+        let bounds = Aabb::from_points(self.order[start..end].iter().map(|&i| centers[i]))?;
+        let bin = |axis: usize, center: Point| {
+            let (low, high) = (bounds.min().axis(axis), bounds.max().axis(axis));
+            let fraction = (center.axis(axis) - low) / (high - low);
+            ((fraction * BINS as f32) as usize).min(BINS - 1)
+        };
+
+        let mut best: Option<(f32, usize, usize)> = None;
+        for axis in 0..3 {
+            if bounds.max().axis(axis) <= bounds.min().axis(axis) {
+                continue;
+            }
+            let mut bins: [(Option<Aabb>, usize); BINS] = [(None, 0); BINS];
+            for &i in &self.order[start..end] {
+                let (aabb, count) = &mut bins[bin(axis, centers[i])];
+                *aabb = Some(aabb.map_or(boxes[i], |aabb| aabb.surrounding(boxes[i])));
+                *count += 1;
+            }
+
+            // The area and count of everything below each split, then above it
+            let mut below = [(0., 0); BINS - 1];
+            let mut running: (Option<Aabb>, usize) = (None, 0);
+            for split in 0..BINS - 1 {
+                running = merge(running, bins[split]);
+                below[split] = (running.0.map_or(0., Aabb::surface_area), running.1);
+            }
+            running = (None, 0);
+            for split in (0..BINS - 1).rev() {
+                running = merge(running, bins[split + 1]);
+                let above = (running.0.map_or(0., Aabb::surface_area), running.1);
+                let cost = below[split].0 * below[split].1 as f32 + above.0 * above.1 as f32;
+                if best.is_none_or(|(best, _, _)| cost < best) {
+                    best = Some((cost, axis, split));
+                }
+            }
+        }
+
+        // A split costs a test of both children's boxes on top of the primitives in them
+        let (cost, axis, split) = best?;
+        let count = end - start;
+        if 1. + cost / aabb.surface_area() >= count as f32 {
+            return None;
+        }
+        let middle = start
+            + partition(&mut self.order[start..end], |&i| {
+                bin(axis, centers[i]) <= split
+            });
+        (middle > start && middle < end).then_some((axis, middle))
+    }
+
+    /// Partition the primitives at the median of their centers along the longest axis
+    fn split_in_half(&mut self, centers: &[Point], start: usize, end: usize) -> (usize, usize) {
+        let bounds = Aabb::from_points(self.order[start..end].iter().map(|&i| centers[i])).unwrap();
+        let size = bounds.max() - bounds.min();
+        let axis = (0..3)
+            .max_by(|&a, &b| size.axis(a).total_cmp(&size.axis(b)))
+            .unwrap();
+        let middle = (start + end) / 2;
+        self.order[start..end].select_nth_unstable_by(middle - start, |&a, &b| {
+            centers[a].axis(axis).total_cmp(&centers[b].axis(axis))
+        });
+        (axis, middle)
+    }
+
+    /// The closest hit between t_min and t_max on any primitive. The test is given the index of
+    /// a primitive and the closest hit so far, and returns the t of its hit with what else it
+    /// found out.
+    pub fn hit<T>(
+        &self,
+        ray: Ray,
+        t_min: f32,
+        mut t_max: f32,
+        mut test: impl FnMut(usize, f32) -> Option<(f32, T)>,
+    ) -> Option<(f32, T)> {
+        let mut closest = None;
+        let mut stack = Vec::with_capacity(64);
+        if !self.nodes.is_empty() {
+            stack.push(0);
+        }
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            if !node.aabb.hit(ray, t_min, t_max) {
+                continue;
+            }
+            match node.kind {
+                NodeKind::Leaf { start, count } => {
+                    for &primitive in &self.order[start..start + count] {
+                        if let Some(hit) = test(primitive, t_max) {
+                            t_max = hit.0;
+                            closest = Some(hit);
+                        }
+                    }
+                }
+                NodeKind::Branch { axis, second } => {
+                    // Visit the child nearer along the ray first, so it narrows the search
+                    if ray.direction().axis(axis) < 0. {
+                        stack.push(index + 1);
+                        stack.push(second);
+                    } else {
+                        stack.push(second);
+                        stack.push(index + 1);
+                    }
+                }
+            }
+        }
+        closest
+    }
+}
+
+fn merge(a: (Option<Aabb>, usize), b: (Option<Aabb>, usize)) -> (Option<Aabb>, usize) {
+    let aabb = match (a.0, b.0) {
+        (Some(a), Some(b)) => Some(a.surrounding(b)),
+        (a, b) => a.or(b),
+    };
+    (aabb, a.1 + b.1)
+}
+
+/// Move the items the predicate holds for to the front, returning how many there are
+fn partition<T>(items: &mut [T], predicate: impl Fn(&T) -> bool) -> usize {
+    let mut first = 0;
+    for i in 0..items.len() {
+        if predicate(&items[i]) {
+            items.swap(first, i);
+            first += 1;
+        }
+    }
+    first
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::primitive::random;
+    use crate::vec3::Vec3;
+
+    #[test]
+    fn test_hit_matches_every_primitive() {
+        random::seed(3);
+        let spheres: Vec<(Point, f32)> = (0..300)
+            .map(|_| {
+                let center = Vec3::new(
+                    random::gen_range(-10. ..10.),
+                    random::gen_range(-10. ..10.),
+                    random::gen_range(-10. ..10.),
+                );
+                (center, random::gen_range(0.1..1.))
+            })
+            .collect();
+        let boxes: Vec<Aabb> = spheres
+            .iter()
+            .map(|&(center, radius)| Aabb::new(center, center).padded(radius))
+            .collect();
+        let bvh = Bvh::new(&boxes);
+
+        // Hit the boxes themselves, which the hierarchy must not skip any of
+        let test = |i: usize, ray: Ray, t_max: f32| {
+            let (t, _) = boxes[i].clip(ray, 0., t_max)?;
+            Some((t, i))
+        };
+        for _ in 0..200 {
+            let ray = Ray::new(
+                Vec3::new(0., 0., -30.),
+                Vec3::new(
+                    random::gen_range(-0.4..0.4),
+                    random::gen_range(-0.4..0.4),
+                    1.,
+                ),
+            );
+            let walked = bvh.hit(ray, 0., f32::MAX, |i, t_max| test(i, ray, t_max));
+            let every = (0..boxes.len())
+                .filter_map(|i| test(i, ray, f32::MAX))
+                .min_by(|a, b| a.0.total_cmp(&b.0));
+            assert_eq!(walked.map(|hit| hit.0), every.map(|hit| hit.0));
+        }
+    }
+}
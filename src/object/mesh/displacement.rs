@@ -0,0 +1,254 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::image::Image;
+use crate::object::mesh::subdivide::bits;
+use crate::object::mesh::{Subdivision, TriangleMesh};
+use crate::validate::{join, Problems, Validate};
+use crate::vec3::Vec3;
+
+/// The most triangles subdivision may make, which bounds the memory a mesh takes
+const MAX_TRIANGLES: usize = 1 << 23;
+
+/// Detail added to a mesh as it loads, by subdividing it into small triangles and moving
+/// their vertices out along the surface by a height map. Unlike a bump map this changes the
+/// silhouette and the shadows too.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct Displacement {
+    /// A grayscale image of heights, placed by the surface coordinates of the vertices
+    pub map: PathBuf,
+    /// How far white moves the surface out, with black leaving it where it is. Negative
+    /// scales carve into it.
+    pub scale: f32,
+    /// Subdivide until no edge is longer than this before displacing, in world units
+    pub edge_length: f32,
+    #[serde(default)]
+    pub subdivision: Subdivision,
+}
+
+impl Displacement {
+    /// The mesh, already in world space, subdivided and displaced by the heights, with
+    /// normals smoothed over the displaced surface
+    pub fn apply(&self, mesh: TriangleMesh, heights: &Image) -> Result<TriangleMesh> {
+        if mesh.uvs.is_empty() {
+            return Err(anyhow!(
+                "Displacement needs surface coordinates to place the map"
+            ));
+        }
+        let mut mesh = mesh.welded();
+        while mesh.longest_edge() > self.edge_length {
+            if 4 * mesh.triangles.len() > MAX_TRIANGLES {
+                return Err(anyhow!(
+                    "Subdividing to edges of {} would make more than {} triangles",
+                    self.edge_length,
+                    MAX_TRIANGLES
+                ));
+            }
+            mesh = mesh.subdivided(self.subdivision);
+        }
+
+        // Vertices split at a seam in the surface coordinates take the height of the first
+        // of them, so they all move together
+        let mut seam_heights: HashMap<[u32; 3], f32> = HashMap::new();
+        let heights: Vec<f32> = (0..mesh.positions.len())
+            .map(|i| {
+                *seam_heights
+                    .entry(bits(mesh.positions[i]))
+                    .or_insert_with(|| {
+                        let (u, v) = mesh.uvs[i];
+                        let color = heights.sample_bilinear(wrap(u), wrap(v));
+                        (color.x() + color.y() + color.z()) / 3.
+                    })
+            })
+            .collect();
+        let directions = smooth_normals(&mesh);
+        for (i, position) in mesh.positions.iter_mut().enumerate() {
+            *position += self.scale * heights[i] * directions[i];
+        }
+        mesh.normals = smooth_normals(&mesh)
+            .into_iter()
+            .zip(&directions)
+            .map(|(normal, &direction)| {
+                if normal.is_near_zero() {
+                    direction
+                } else {
+                    normal
+                }
+            })
+            .collect();
+        Ok(mesh)
+    }
+}
+
+/// Repeat the map outside of [0, 1], keeping the edges of the map at 0 and 1 where they are
+fn wrap(coordinate: f32) -> f32 {
+    if (0. ..=1.).contains(&coordinate) {
+        coordinate
+    } else {
+        coordinate.rem_euclid(1.)
+    }
+}
+
+/// Unit normals at the vertices, averaged over the triangles around their positions and
+/// weighted by area. Vertices split only by their attributes, e.g. along a seam in the
+/// surface coordinates, share a normal, so with a shared height the seam does not tear open.
+/// Vertices of degenerate surfaces get a zero normal.
+fn smooth_normals(mesh: &TriangleMesh) -> Vec<Vec3> {
+    let mut sums: HashMap<[u32; 3], Vec3> = HashMap::new();
+    for (triangle, indices) in mesh.triangles.iter().enumerate() {
+        let [a, b, c] = mesh.vertices(triangle);
+        let normal = (b - a).cross(c - a);
+        for &i in indices {
+            *sums
+                .entry(bits(mesh.positions[i]))
+                .or_insert(Vec3::new(0., 0., 0.)) += normal;
+        }
+    }
+    mesh.positions
+        .iter()
+        .map(|&position| {
+            let sum = sums
+                .get(&bits(position))
+                .copied()
+                .unwrap_or(Vec3::new(0., 0., 0.));
+            if sum.is_near_zero() {
+                sum
+            } else {
+                sum.unit_vector()
+            }
+        })
+        .collect()
+}
+
+impl Validate for Displacement {
+    fn validate(&self, path: &str, problems: &mut Problems) {
+        if !self.scale.is_finite() {
+            problems.error(&join(path, "scale"), "The scale must be finite");
+        }
+        if self.edge_length.is_nan() || self.edge_length <= 0. {
+            problems.error(
+                &join(path, "edge_length"),
+                "The edge length must be positive",
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vec3::Color;
+
+    #[test]
+    fn test_displaces_subdivided_square() {
+        let square = TriangleMesh {
+            positions: vec![
+                Vec3::new(0., 0., 0.),
+                Vec3::new(1., 0., 0.),
+                Vec3::new(1., 1., 0.),
+                Vec3::new(0., 1., 0.),
+            ],
+            uvs: vec![(0., 0.), (1., 0.), (1., 1.), (0., 1.)],
+            triangles: vec![[0, 1, 2], [0, 2, 3]],
+            ..TriangleMesh::default()
+        };
+        // Black on the left half and white on the right
+        let mut heights = Image::new(2, 1);
+        heights.set(1, 0, Color::new(1., 1., 1.));
+        let displacement = Displacement {
+            map: "heights.png".into(),
+            scale: 0.5,
+            edge_length: 0.3,
+            subdivision: Subdivision::Uniform,
+        };
+
+        let mesh = displacement.apply(square, &heights).unwrap();
+        mesh.check().unwrap();
+        assert_eq!(mesh.triangles.len(), 2 * 4usize.pow(3));
+        for (position, &(u, _)) in mesh.positions.iter().zip(&mesh.uvs) {
+            if u <= 0.25 {
+                assert!(position.z().abs() < 1e-5);
+            } else if u >= 0.75 {
+                assert!((position.z() - 0.5).abs() < 1e-5);
+            }
+        }
+        // The flats keep facing up, while the slope between them leans away from the rise
+        let normal = |u: f32| {
+            let i = mesh.uvs.iter().position(|&uv| uv == (u, 0.5)).unwrap();
+            mesh.normals[i]
+        };
+        assert!((normal(0.) - Vec3::new(0., 0., 1.)).length() < 1e-5);
+        assert!(normal(0.5).x() < -0.1);
+    }
+
+    #[test]
+    fn test_seams_move_together() {
+        // Two squares meeting at x = 1, where the surface coordinates jump from 1 back to 0
+        let strip = TriangleMesh {
+            positions: vec![
+                Vec3::new(0., 0., 0.),
+                Vec3::new(1., 0., 0.),
+                Vec3::new(1., 1., 0.),
+                Vec3::new(0., 1., 0.),
+                Vec3::new(1., 0., 0.),
+                Vec3::new(2., 0., 0.),
+                Vec3::new(2., 1., 0.),
+                Vec3::new(1., 1., 0.),
+            ],
+            uvs: vec![
+                (0., 0.),
+                (1., 0.),
+                (1., 1.),
+                (0., 1.),
+                (0., 0.),
+                (1., 0.),
+                (1., 1.),
+                (0., 1.),
+            ],
+            triangles: vec![[0, 1, 2], [0, 2, 3], [4, 5, 6], [4, 6, 7]],
+            ..TriangleMesh::default()
+        };
+        // Black on the left edge of the map and white on the right
+        let mut heights = Image::new(2, 1);
+        heights.set(1, 0, Color::new(1., 1., 1.));
+        let displacement = Displacement {
+            map: "heights.png".into(),
+            scale: 0.5,
+            edge_length: 2.,
+            subdivision: Subdivision::Uniform,
+        };
+
+        let mesh = displacement.apply(strip, &heights).unwrap();
+        let seam: Vec<f32> = mesh
+            .positions
+            .iter()
+            .filter(|position| (position.x() - 1.).abs() < 1e-5)
+            .map(|position| position.z())
+            .collect();
+        assert_eq!(seam.len(), 4);
+        assert!(seam.iter().all(|&z| z == seam[0]));
+    }
+
+    #[test]
+    fn test_needs_surface_coordinates() {
+        let triangle = TriangleMesh {
+            positions: vec![
+                Vec3::new(0., 0., 0.),
+                Vec3::new(1., 0., 0.),
+                Vec3::new(0., 1., 0.),
+            ],
+            triangles: vec![[0, 1, 2]],
+            ..TriangleMesh::default()
+        };
+        let displacement = Displacement {
+            map: "heights.png".into(),
+            scale: 1.,
+            edge_length: 0.5,
+            subdivision: Subdivision::Loop,
+        };
+        assert!(displacement.apply(triangle, &Image::new(1, 1)).is_err());
+    }
+}
@@ -0,0 +1,392 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::aabb::Aabb;
+use crate::image::Image;
+use crate::material::{MaterialLibrary, MaterialRef};
+use crate::object::{hit_triangle, HitRecord, Hittable, LoadFiles, ResolveMaterials, Transform};
+use crate::ray::Ray;
+use crate::validate::{join, Problems, Validate};
+use crate::vec3::{Color, Point, Vec3};
+
+mod bvh;
+mod displacement;
+mod ply;
+mod stl;
+mod subdivide;
+pub use bvh::Bvh;
+pub use displacement::Displacement;
+pub use subdivide::Subdivision;
+
+/// The file formats meshes can be loaded from, by extension
+const FORMATS: [&str; 2] = ["ply", "stl"];
+
+/// Triangles loaded from a PLY or STL file, placed in the world by the transform. Vertex
+/// normals in the file smooth the shading, and vertex colors tint the material. A
+/// displacement subdivides the triangles and moves them by a height map as they load.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct Mesh {
+    path: PathBuf,
+    #[serde(default)]
+    transform: Transform,
+    material: MaterialRef,
+    /// An image tinting the material, placed by the surface coordinates of the vertices
+    texture: Option<PathBuf>,
+    displacement: Option<Displacement>,
+    #[serde(skip)]
+    geometry: Option<Arc<Geometry>>,
+    #[serde(skip)]
+    texture_image: Option<Arc<Image>>,
+}
+
+/// Triangles between shared vertices. Normals, surface coordinates and colors are either
+/// empty or given for every vertex.
+#[derive(Debug, Default, PartialEq)]
+pub struct TriangleMesh {
+    pub positions: Vec<Point>,
+    pub normals: Vec<Vec3>,
+    pub uvs: Vec<(f32, f32)>,
+    pub colors: Vec<Color>,
+    pub triangles: Vec<[usize; 3]>,
+}
+
+impl TriangleMesh {
+    /// Read a mesh in the format given by the file's extension
+    pub fn load(path: &Path) -> Result<Self> {
+        let bytes = fs::read(path).map_err(|e| anyhow!("Cannot read {}: {}", path.display(), e))?;
+        let mesh = match format(path).as_deref() {
+            Some("ply") => ply::parse(&bytes),
+            Some("stl") => stl::parse(&bytes),
+            _ => Err(anyhow!("Meshes can only be read from .ply and .stl files")),
+        };
+        let mesh = mesh.map_err(|e| anyhow!("Cannot load {}: {}", path.display(), e))?;
+        mesh.check()?;
+        Ok(mesh)
+    }
+
+    /// Make sure the triangles refer to vertices that exist, and the vertices have either all
+    /// or none of each attribute
+    pub fn check(&self) -> Result<()> {
+        let vertices = self.positions.len();
+        let attributes = [
+            ("normals", self.normals.len()),
+            ("surface coordinates", self.uvs.len()),
+            ("colors", self.colors.len()),
+        ];
+        for (name, count) in attributes {
+            if count != 0 && count != vertices {
+                return Err(anyhow!(
+                    "A mesh of {} vertices has {} {}",
+                    vertices,
+                    count,
+                    name
+                ));
+            }
+        }
+        if let Some(index) = self.triangles.iter().flatten().find(|&&i| i >= vertices) {
+            return Err(anyhow!(
+                "A triangle refers to vertex {} of only {}",
+                index,
+                vertices
+            ));
+        }
+        Ok(())
+    }
+
+    /// The mesh moved into world space
+    #[must_use]
+    pub fn transformed(self, transform: Transform) -> Self {
+        Self {
+            positions: self
+                .positions
+                .into_iter()
+                .map(|p| transform.point(p))
+                .collect(),
+            normals: self
+                .normals
+                .into_iter()
+                .map(|n| transform.normal(n).unit_vector())
+                .collect(),
+            ..self
+        }
+    }
+
+    fn vertices(&self, triangle: usize) -> [Point; 3] {
+        self.triangles[triangle].map(|i| self.positions[i])
+    }
+
+    /// Interpolate a vertex attribute at barycentric weights on a triangle
+    fn blend(&self, values: &[Vec3], triangle: usize, weights: [f32; 3]) -> Option<Vec3> {
+        let [a, b, c] = self.triangles[triangle];
+        (!values.is_empty())
+            .then(|| weights[0] * values[a] + weights[1] * values[b] + weights[2] * values[c])
+    }
+
+    /// The directions in which u and v grow across a triangle, if it has surface coordinates
+    /// that are not degenerate
+    fn tangents(&self, triangle: usize) -> Option<(Vec3, Vec3)> {
+        if self.uvs.is_empty() {
+            return None;
+        }
+        let [a, b, c] = self.vertices(triangle);
+        let [uv_a, uv_b, uv_c] = self.triangles[triangle].map(|i| self.uvs[i]);
+        let (du1, dv1) = (uv_b.0 - uv_a.0, uv_b.1 - uv_a.1);
+        let (du2, dv2) = (uv_c.0 - uv_a.0, uv_c.1 - uv_a.1);
+        let determinant = du1 * dv2 - du2 * dv1;
+        if determinant.abs() < 1e-12 {
+            return None;
+        }
+        let (edge1, edge2) = (b - a, c - a);
+        Some((
+            (dv2 * edge1 - dv1 * edge2) / determinant,
+            (du1 * edge2 - du2 * edge1) / determinant,
+        ))
+    }
+
+    fn blend_uv(&self, triangle: usize, weights: [f32; 3]) -> Option<(f32, f32)> {
+        if self.uvs.is_empty() {
+            return None;
+        }
+        let [a, b, c] = self.triangles[triangle].map(|i| self.uvs[i]);
+        Some((
+            weights[0] * a.0 + weights[1] * b.0 + weights[2] * c.0,
+            weights[0] * a.1 + weights[1] * b.1 + weights[2] * c.1,
+        ))
+    }
+}
+
+fn format(path: &Path) -> Option<String> {
+    path.extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase())
+}
+
+/// A mesh with the hierarchy of boxes around its triangles
+#[derive(Debug, PartialEq)]
+pub struct Geometry {
+    mesh: TriangleMesh,
+    bvh: Bvh,
+}
+
+impl Geometry {
+    pub fn new(mesh: TriangleMesh) -> Self {
+        let boxes: Vec<Aabb> = (0..mesh.triangles.len())
+            .map(|i| Aabb::from_points(mesh.vertices(i)).unwrap())
+            .collect();
+        let bvh = Bvh::new(&boxes);
+        Self { mesh, bvh }
+    }
+}
+
+impl Mesh {
+    /// A mesh already in world space, e.g. from a scene imported from `path`
+    pub fn from_triangles<M: Into<MaterialRef>>(
+        path: PathBuf,
+        mesh: TriangleMesh,
+        material: M,
+        texture: Option<Arc<Image>>,
+    ) -> Self {
+        Self {
+            path,
+            transform: Transform::default(),
+            material: material.into(),
+            texture: None,
+            displacement: None,
+            geometry: Some(Arc::new(Geometry::new(mesh))),
+            texture_image: texture,
+        }
+    }
+
+    /// Whether the triangles enclose a solid, which is only known once they are loaded
+    pub fn is_closed(&self) -> Option<bool> {
+        self.geometry
+            .as_ref()
+            .map(|geometry| geometry.mesh.is_closed())
+    }
+
+    fn geometry(&self) -> &Geometry {
+        self.geometry
+            .as_deref()
+            .expect("Meshes are loaded when the scene is prepared")
+    }
+}
+
+impl Hittable for Mesh {
+    fn hit(&self, ray: Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+        let Geometry { mesh, bvh } = self.geometry();
+        let (t, (triangle, u, v)) = bvh.hit(ray, t_min, t_max, |triangle, t_max| {
+            let [a, b, c] = mesh.vertices(triangle);
+            let (t, u, v) = hit_triangle(ray, a, b, c)?;
+            (t_min..=t_max)
+                .contains(&t)
+                .then_some((t, (triangle, u, v)))
+        })?;
+
+        // Which side was hit is up to the flat triangle, even when the shading is smooth
+        let [a, b, c] = mesh.vertices(triangle);
+        let outward_normal = (b - a).cross(c - a).unit_vector();
+        let mut record = HitRecord::facing(ray, t, outward_normal, self.material.material());
+        if let Some((tangent, bitangent)) = mesh.tangents(triangle) {
+            record = record.with_tangents(tangent, bitangent);
+        }
+        let weights = [1. - u - v, u, v];
+        if let Some(normal) = mesh.blend(&mesh.normals, triangle, weights) {
+            let normal = normal.unit_vector();
+            record = record.with_shading_normal(if record.is_front_face() {
+                normal
+            } else {
+                -normal
+            });
+        }
+        let (u, v) = mesh.blend_uv(triangle, weights).unwrap_or((u, v));
+        let mut tint = mesh
+            .blend(&mesh.colors, triangle, weights)
+            .unwrap_or(Color::new(1., 1., 1.));
+        if let Some(texture) = &self.texture_image {
+            tint *= texture.sample(u.rem_euclid(1.), v.rem_euclid(1.));
+        }
+        Some(record.with_uv(u, v).with_tint(tint))
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.geometry.as_ref()?.bvh.bounding_box()
+    }
+}
+
+impl ResolveMaterials for Mesh {
+    fn resolve_materials(&mut self, library: &MaterialLibrary) -> Result<()> {
+        self.material.resolve(library)
+    }
+}
+
+impl LoadFiles for Mesh {
+    fn load_files(&mut self, directory: &Path) -> Result<()> {
+        self.material.load_files(directory)?;
+        let mut mesh = TriangleMesh::load(&directory.join(&self.path))?.transformed(self.transform);
+        if let Some(displacement) = &self.displacement {
+            let heights = Image::load_raw(&directory.join(&displacement.map))?;
+            mesh = displacement
+                .apply(mesh, &heights)
+                .map_err(|e| anyhow!("Cannot displace {}: {}", self.path.display(), e))?;
+        }
+        self.geometry = Some(Arc::new(Geometry::new(mesh)));
+        if let Some(texture) = &self.texture {
+            self.texture_image = Some(Arc::new(Image::load(&directory.join(texture))?));
+        }
+        Ok(())
+    }
+}
+
+impl Validate for Mesh {
+    fn validate(&self, path: &str, problems: &mut Problems) {
+        if !format(&self.path).is_some_and(|format| FORMATS.contains(&format.as_str())) {
+            problems.error(
+                &join(path, "path"),
+                "Meshes can only be read from .ply and .stl files",
+            );
+        }
+        self.transform.validate(&join(path, "transform"), problems);
+        if let Some(displacement) = &self.displacement {
+            displacement.validate(&join(path, "displacement"), problems);
+        }
+        self.material.validate(&join(path, "material"), problems);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::{Lambertian, Material, SurfaceMaps};
+
+    /// A unit square in the xy plane, split into two triangles, red at its origin
+    fn square() -> Mesh {
+        let mesh = TriangleMesh {
+            positions: vec![
+                Vec3::new(0., 0., 0.),
+                Vec3::new(1., 0., 0.),
+                Vec3::new(1., 1., 0.),
+                Vec3::new(0., 1., 0.),
+            ],
+            colors: vec![
+                Color::new(1., 0., 0.),
+                Color::new(1., 1., 1.),
+                Color::new(1., 1., 1.),
+                Color::new(1., 1., 1.),
+            ],
+            uvs: vec![(0., 0.), (2., 0.), (2., 2.), (0., 2.)],
+            triangles: vec![[0, 1, 2], [0, 2, 3]],
+            ..TriangleMesh::default()
+        };
+        mesh.check().unwrap();
+        Mesh {
+            path: "square.ply".into(),
+            transform: Transform::default(),
+            texture: None,
+            displacement: None,
+            texture_image: None,
+            material: Material::Lambertian(Lambertian {
+                albedo: Vec3::new(0.5, 0.5, 0.5),
+                maps: SurfaceMaps::default(),
+            })
+            .into(),
+            geometry: Some(Arc::new(Geometry::new(mesh))),
+        }
+    }
+
+    #[test]
+    fn test_hit_interpolates_vertices() {
+        let square = square();
+        let ray = Ray::new(Vec3::new(0.25, 0.5, 1.), Vec3::new(0., 0., -1.));
+        let record = square.hit(ray, 0., f32::MAX).unwrap();
+        assert!((record.t() - 1.).abs() < 1e-5);
+        assert!(record.is_front_face());
+        assert_eq!(record.normal(), Vec3::new(0., 0., 1.));
+        let (tangent, bitangent, _) = record.shading_frame();
+        assert!((tangent - Vec3::new(1., 0., 0.)).length() < 1e-5);
+        assert!((bitangent - Vec3::new(0., 1., 0.)).length() < 1e-5);
+        let (u, v) = record.uv();
+        assert!((u - 0.5).abs() < 1e-5 && (v - 1.).abs() < 1e-5);
+        assert!((record.tint() - Color::new(1., 0.5, 0.5)).length() < 1e-5);
+
+        let miss = Ray::new(Vec3::new(1.5, 0.5, 1.), Vec3::new(0., 0., -1.));
+        assert!(square.hit(miss, 0., f32::MAX).is_none());
+    }
+
+    #[test]
+    fn test_vertex_normals_shade_but_flat_faces_decide_sides() {
+        let mut square = square();
+        let geometry = square.geometry.take().unwrap();
+        let mut mesh = Arc::try_unwrap(geometry).unwrap().mesh;
+        let tilted = Vec3::new(1., 0., 1.).unit_vector();
+        mesh.normals = vec![tilted; 4];
+        square.geometry = Some(Arc::new(Geometry::new(mesh)));
+
+        let ray = Ray::new(Vec3::new(0.25, 0.5, 1.), Vec3::new(0., 0., -1.));
+        let record = square.hit(ray, 0., f32::MAX).unwrap();
+        assert!(record.is_front_face());
+        assert_eq!(record.geometric_normal(), Vec3::new(0., 0., 1.));
+        assert!((record.normal() - tilted).length() < 1e-5);
+        // Lit through the shading normal but from behind the surface
+        assert!(!record.agrees_on_side(Vec3::new(1., 0., -0.1)));
+        assert!(record.agrees_on_side(Vec3::new(0., 0., 1.)));
+
+        let below = Ray::new(Vec3::new(0.25, 0.5, -1.), Vec3::new(0., 0., 1.));
+        let record = square.hit(below, 0., f32::MAX).unwrap();
+        assert!(!record.is_front_face());
+        assert_eq!(record.geometric_normal(), Vec3::new(0., 0., -1.));
+        assert!((record.normal() + tilted).length() < 1e-5);
+    }
+
+    #[test]
+    fn test_check_rejects_missing_vertices() {
+        let mesh = TriangleMesh {
+            positions: vec![Vec3::new(0., 0., 0.); 3],
+            triangles: vec![[0, 1, 3]],
+            ..TriangleMesh::default()
+        };
+        assert!(mesh.check().is_err());
+    }
+}
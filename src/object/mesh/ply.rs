@@ -0,0 +1,358 @@
+use std::convert::TryInto;
+
+use anyhow::{anyhow, Result};
+
+use crate::object::mesh::TriangleMesh;
+use crate::vec3::Vec3;
+
+/// How the values after the header are stored
+#[derive(Debug, PartialEq)]
+enum Format {
+    Ascii,
+    BinaryLittleEndian,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Scalar {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl Scalar {
+    fn parse(name: &str) -> Result<Self> {
+        Ok(match name {
+            "char" | "int8" => Scalar::I8,
+            "uchar" | "uint8" => Scalar::U8,
+            "short" | "int16" => Scalar::I16,
+            "ushort" | "uint16" => Scalar::U16,
+            "int" | "int32" => Scalar::I32,
+            "uint" | "uint32" => Scalar::U32,
+            "float" | "float32" => Scalar::F32,
+            "double" | "float64" => Scalar::F64,
+            _ => return Err(anyhow!("Unknown property type '{}'", name)),
+        })
+    }
+
+    fn size(self) -> usize {
+        match self {
+            Scalar::I8 | Scalar::U8 => 1,
+            Scalar::I16 | Scalar::U16 => 2,
+            Scalar::I32 | Scalar::U32 | Scalar::F32 => 4,
+            Scalar::F64 => 8,
+        }
+    }
+
+    fn read(self, bytes: &[u8]) -> f64 {
+        match self {
+            Scalar::I8 => bytes[0] as i8 as f64,
+            Scalar::U8 => bytes[0] as f64,
+            Scalar::I16 => i16::from_le_bytes([bytes[0], bytes[1]]) as f64,
+            Scalar::U16 => u16::from_le_bytes([bytes[0], bytes[1]]) as f64,
+            Scalar::I32 => i32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64,
+            Scalar::U32 => u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64,
+            Scalar::F32 => f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64,
+            Scalar::F64 => f64::from_le_bytes(bytes[..8].try_into().unwrap()),
+        }
+    }
+
+    /// The largest value of an integer color channel, which is full intensity
+    fn color_scale(self) -> f64 {
+        match self {
+            Scalar::U8 => 255.,
+            Scalar::U16 => 65535.,
+            _ => 1.,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Property {
+    Scalar {
+        name: String,
+        kind: Scalar,
+    },
+    List {
+        name: String,
+        count: Scalar,
+        item: Scalar,
+    },
+}
+
+#[derive(Debug, PartialEq)]
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+/// Read the header, returning the format, the elements and where the values start
+fn parse_header(bytes: &[u8]) -> Result<(Format, Vec<Element>, usize)> {
+    let mut format = None;
+    let mut elements: Vec<Element> = Vec::new();
+    let mut offset = 0;
+    for (number, line) in bytes.split(|&byte| byte == b'\n').enumerate() {
+        offset += line.len() + 1;
+        let line = std::str::from_utf8(line)
+            .map_err(|_| anyhow!("The header is not text"))?
+            .trim();
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words[..] {
+            ["ply"] if number == 0 => {}
+            _ if number == 0 => return Err(anyhow!("Not a PLY file")),
+            ["format", "ascii", _] => format = Some(Format::Ascii),
+            ["format", "binary_little_endian", _] => format = Some(Format::BinaryLittleEndian),
+            ["format", other, _] => return Err(anyhow!("Unsupported PLY format '{}'", other)),
+            ["element", name, count] => elements.push(Element {
+                name: name.to_string(),
+                count: count.parse()?,
+                properties: Vec::new(),
+            }),
+            ["property", "list", count, item, name] => {
+                let element = elements
+                    .last_mut()
+                    .ok_or_else(|| anyhow!("Property '{}' before any element", name))?;
+                element.properties.push(Property::List {
+                    name: name.to_string(),
+                    count: Scalar::parse(count)?,
+                    item: Scalar::parse(item)?,
+                });
+            }
+            ["property", kind, name] => {
+                let element = elements
+                    .last_mut()
+                    .ok_or_else(|| anyhow!("Property '{}' before any element", name))?;
+                element.properties.push(Property::Scalar {
+                    name: name.to_string(),
+                    kind: Scalar::parse(kind)?,
+                });
+            }
+            ["end_header"] => {
+                let format = format.ok_or_else(|| anyhow!("The header has no format"))?;
+                return Ok((format, elements, offset));
+            }
+            // Comments, object info and blank lines
+            _ => {}
+        }
+    }
+    Err(anyhow!("The header never ends"))
+}
+
+/// The values after the header, read one at a time
+enum Values<'a> {
+    Ascii(std::str::SplitAsciiWhitespace<'a>),
+    Binary(&'a [u8]),
+}
+
+impl Values<'_> {
+    fn next(&mut self, kind: Scalar) -> Result<f64> {
+        match self {
+            Values::Ascii(words) => {
+                let word = words.next().ok_or_else(|| anyhow!("The file ends early"))?;
+                word.parse()
+                    .map_err(|_| anyhow!("'{}' is not a number", word))
+            }
+            Values::Binary(bytes) => {
+                if bytes.len() < kind.size() {
+                    return Err(anyhow!("The file ends early"));
+                }
+                let (value, rest) = bytes.split_at(kind.size());
+                *bytes = rest;
+                Ok(kind.read(value))
+            }
+        }
+    }
+}
+
+/// Where each vertex property goes in the mesh
+#[derive(Copy, Clone, PartialEq)]
+enum Slot {
+    Position(usize),
+    Normal(usize),
+    Uv(usize),
+    Color(usize),
+    Unused,
+}
+
+impl Slot {
+    fn of(name: &str) -> Slot {
+        match name {
+            "x" => Slot::Position(0),
+            "y" => Slot::Position(1),
+            "z" => Slot::Position(2),
+            "nx" => Slot::Normal(0),
+            "ny" => Slot::Normal(1),
+            "nz" => Slot::Normal(2),
+            "u" | "s" | "texture_u" | "texture_s" => Slot::Uv(0),
+            "v" | "t" | "texture_v" | "texture_t" => Slot::Uv(1),
+            "red" | "r" => Slot::Color(0),
+            "green" | "g" => Slot::Color(1),
+            "blue" | "b" => Slot::Color(2),
+            _ => Slot::Unused,
+        }
+    }
+}
+
+/// Read a mesh from a PLY file in ASCII or binary little-endian, with the positions and any
+/// normals, surface coordinates and colors of its vertices. Polygons are split into fans of
+/// triangles.
+pub fn parse(bytes: &[u8]) -> Result<TriangleMesh> {
+    let (format, elements, offset) = parse_header(bytes)?;
+    let body = &bytes[offset.min(bytes.len())..];
+    let mut values = match format {
+        Format::Ascii => Values::Ascii(
+            std::str::from_utf8(body)
+                .map_err(|_| anyhow!("The values are not text"))?
+                .split_ascii_whitespace(),
+        ),
+        Format::BinaryLittleEndian => Values::Binary(body),
+    };
+
+    let mut mesh = TriangleMesh::default();
+    for element in &elements {
+        match element.name.as_str() {
+            "vertex" => read_vertices(element, &mut values, &mut mesh)?,
+            "face" => read_faces(element, &mut values, &mut mesh)?,
+            _ => {
+                for _ in 0..element.count {
+                    for property in &element.properties {
+                        read_property(property, &mut values)?;
+                    }
+                }
+            }
+        }
+    }
+    Ok(mesh)
+}
+
+fn read_property(property: &Property, values: &mut Values) -> Result<Vec<f64>> {
+    match property {
+        Property::Scalar { kind, .. } => Ok(vec![values.next(*kind)?]),
+        Property::List { count, item, .. } => {
+            let count = values.next(*count)? as usize;
+            (0..count).map(|_| values.next(*item)).collect()
+        }
+    }
+}
+
+fn read_vertices(element: &Element, values: &mut Values, mesh: &mut TriangleMesh) -> Result<()> {
+    let slots: Vec<Slot> = element
+        .properties
+        .iter()
+        .map(|property| match property {
+            Property::Scalar { name, .. } => Slot::of(name),
+            Property::List { .. } => Slot::Unused,
+        })
+        .collect();
+    let has = |wanted: &[Slot]| wanted.iter().all(|slot| slots.contains(slot));
+    let has_normals = has(&[Slot::Normal(0), Slot::Normal(1), Slot::Normal(2)]);
+    let has_uvs = has(&[Slot::Uv(0), Slot::Uv(1)]);
+    let has_colors = has(&[Slot::Color(0), Slot::Color(1), Slot::Color(2)]);
+
+    for _ in 0..element.count {
+        let (mut position, mut normal, mut uv, mut color) = ([0.; 3], [0.; 3], [0.; 2], [0.; 3]);
+        for (property, &slot) in element.properties.iter().zip(&slots) {
+            let value = read_property(property, values)?;
+            let value = value.first().copied().unwrap_or_default();
+            match (slot, property) {
+                (Slot::Position(i), _) => position[i] = value as f32,
+                (Slot::Normal(i), _) => normal[i] = value as f32,
+                (Slot::Uv(i), _) => uv[i] = value as f32,
+                (Slot::Color(i), Property::Scalar { kind, .. }) => {
+                    color[i] = (value / kind.color_scale()) as f32
+                }
+                _ => {}
+            }
+        }
+        mesh.positions
+            .push(Vec3::new(position[0], position[1], position[2]));
+        if has_normals {
+            mesh.normals
+                .push(Vec3::new(normal[0], normal[1], normal[2]));
+        }
+        if has_uvs {
+            mesh.uvs.push((uv[0], uv[1]));
+        }
+        if has_colors {
+            mesh.colors.push(Vec3::new(color[0], color[1], color[2]));
+        }
+    }
+    Ok(())
+}
+
+fn read_faces(element: &Element, values: &mut Values, mesh: &mut TriangleMesh) -> Result<()> {
+    for _ in 0..element.count {
+        for property in &element.properties {
+            let indices = read_property(property, values)?;
+            let is_vertices = matches!(
+                property,
+                Property::List { name, .. } if name == "vertex_indices" || name == "vertex_index"
+            );
+            if !is_vertices {
+                continue;
+            }
+            let indices: Vec<usize> = indices.into_iter().map(|i| i as usize).collect();
+            for i in 1..indices.len().saturating_sub(1) {
+                mesh.triangles
+                    .push([indices[0], indices[i], indices[i + 1]]);
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEADER: &str = "element vertex 4
+property float x
+property float y
+property float z
+property uchar red
+property uchar green
+property uchar blue
+element face 1
+property list uchar int vertex_indices
+end_header
+";
+
+    #[test]
+    fn test_ascii_quad_with_colors() {
+        let source = format!(
+            "ply\nformat ascii 1.0\ncomment a square\n{}{}",
+            HEADER, "0 0 0 255 0 0\n1 0 0 0 255 0\n1 1 0 0 0 255\n0 1 0 255 255 255\n4 0 1 2 3\n"
+        );
+        let mesh = parse(source.as_bytes()).unwrap();
+        assert_eq!(mesh.positions[2], Vec3::new(1., 1., 0.));
+        assert_eq!(mesh.colors[1], Vec3::new(0., 1., 0.));
+        assert!(mesh.normals.is_empty() && mesh.uvs.is_empty());
+        assert_eq!(mesh.triangles, vec![[0, 1, 2], [0, 2, 3]]);
+    }
+
+    #[test]
+    fn test_binary_matches_ascii() {
+        let mut bytes = format!("ply\nformat binary_little_endian 1.0\n{}", HEADER).into_bytes();
+        let vertices = [[0., 0., 0.], [1., 0., 0.], [1., 1., 0.], [0., 1., 0.]];
+        for (i, vertex) in vertices.iter().enumerate() {
+            for coordinate in vertex {
+                bytes.extend((*coordinate as f32).to_le_bytes());
+            }
+            bytes.extend([i as u8 * 60, 0, 255]);
+        }
+        bytes.push(4);
+        for index in [0i32, 1, 2, 3] {
+            bytes.extend(index.to_le_bytes());
+        }
+
+        let mesh = parse(&bytes).unwrap();
+        assert_eq!(mesh.positions[3], Vec3::new(0., 1., 0.));
+        assert_eq!(mesh.colors[3], Vec3::new(180. / 255., 0., 1.));
+        assert_eq!(mesh.triangles, vec![[0, 1, 2], [0, 2, 3]]);
+    }
+}
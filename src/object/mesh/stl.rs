@@ -0,0 +1,112 @@
+use std::convert::TryInto;
+
+use anyhow::{anyhow, Result};
+
+use crate::object::mesh::TriangleMesh;
+use crate::vec3::Vec3;
+
+/// The header of a binary file and its count of triangles
+const HEADER_SIZE: usize = 84;
+/// A normal, three vertices and an attribute byte count
+const TRIANGLE_SIZE: usize = 50;
+
+/// Read a mesh from an ASCII or binary STL file. Each triangle gets vertices of its own, and
+/// the facet normals are left out as they only repeat the triangles' own.
+pub fn parse(bytes: &[u8]) -> Result<TriangleMesh> {
+    if is_binary(bytes) {
+        Ok(parse_binary(bytes))
+    } else if bytes.starts_with(b"solid") {
+        parse_ascii(bytes)
+    } else {
+        Err(anyhow!("Not an STL file"))
+    }
+}
+
+/// Binary files may also start with "solid", so tell them apart by their length
+fn is_binary(bytes: &[u8]) -> bool {
+    bytes.len() >= HEADER_SIZE && {
+        let count = u32::from_le_bytes([bytes[80], bytes[81], bytes[82], bytes[83]]) as usize;
+        bytes.len() == HEADER_SIZE + count * TRIANGLE_SIZE
+    }
+}
+
+fn parse_binary(bytes: &[u8]) -> TriangleMesh {
+    let mut mesh = TriangleMesh::default();
+    for triangle in bytes[HEADER_SIZE..].chunks_exact(TRIANGLE_SIZE) {
+        let float = |i: usize| {
+            let offset = 12 + 4 * i;
+            f32::from_le_bytes(triangle[offset..offset + 4].try_into().unwrap())
+        };
+        push_triangle(
+            &mut mesh,
+            [0, 1, 2].map(|v| Vec3::new(float(3 * v), float(3 * v + 1), float(3 * v + 2))),
+        );
+    }
+    mesh
+}
+
+fn parse_ascii(bytes: &[u8]) -> Result<TriangleMesh> {
+    let text = std::str::from_utf8(bytes).map_err(|_| anyhow!("The file is not text"))?;
+    let mut words = text.split_ascii_whitespace();
+    let mut mesh = TriangleMesh::default();
+    let mut vertices = Vec::with_capacity(3);
+    while let Some(word) = words.next() {
+        match word {
+            "vertex" => {
+                let mut coordinate = || -> Result<f32> {
+                    let word = words.next().ok_or_else(|| anyhow!("The file ends early"))?;
+                    word.parse()
+                        .map_err(|_| anyhow!("'{}' is not a number", word))
+                };
+                vertices.push(Vec3::new(coordinate()?, coordinate()?, coordinate()?));
+            }
+            "endloop" => {
+                if vertices.len() != 3 {
+                    return Err(anyhow!("A facet has {} vertices", vertices.len()));
+                }
+                push_triangle(&mut mesh, [vertices[0], vertices[1], vertices[2]]);
+                vertices.clear();
+            }
+            _ => {}
+        }
+    }
+    Ok(mesh)
+}
+
+fn push_triangle(mesh: &mut TriangleMesh, vertices: [Vec3; 3]) {
+    let start = mesh.positions.len();
+    mesh.positions.extend(vertices);
+    mesh.triangles.push([start, start + 1, start + 2]);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ascii_and_binary_agree() {
+        let ascii = "solid part
+facet normal 0 0 1
+  outer loop
+    vertex 0 0 0
+    vertex 1 0 0
+    vertex 0 1 0
+  endloop
+endfacet
+endsolid part
+";
+        // A binary header may start with "solid" too
+        let mut binary = b"solid but binary".to_vec();
+        binary.resize(80, 0);
+        binary.extend(1u32.to_le_bytes());
+        for value in [0., 0., 1., 0., 0., 0., 1., 0., 0., 0., 1., 0.] {
+            binary.extend((value as f32).to_le_bytes());
+        }
+        binary.extend([0, 0]);
+
+        let mesh = parse(ascii.as_bytes()).unwrap();
+        assert_eq!(mesh, parse(&binary).unwrap());
+        assert_eq!(mesh.positions[1], Vec3::new(1., 0., 0.));
+        assert_eq!(mesh.triangles, vec![[0, 1, 2]]);
+    }
+}
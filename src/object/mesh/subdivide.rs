@@ -0,0 +1,256 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::object::mesh::TriangleMesh;
+use crate::vec3::{Point, Vec3};
+
+/// How a mesh is refined before it is displaced
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Clone, Copy)]
+pub enum Subdivision {
+    /// Split the triangles at the middles of their edges, keeping the surface as it is
+    #[default]
+    Uniform,
+    /// Split the triangles like uniform subdivision, smoothing the surface towards Loop's
+    /// limit surface, which rounds off corners and shrinks coarse meshes
+    Loop,
+}
+
+/// The triangles on either side of an edge between positions, by the position opposite it.
+/// Positions are known by their first vertex, so edges join across seams where vertices
+/// differ only in their attributes.
+type Edges = HashMap<(usize, usize), Vec<usize>>;
+
+fn edge(a: usize, b: usize) -> (usize, usize) {
+    (a.min(b), a.max(b))
+}
+
+impl TriangleMesh {
+    /// Merge vertices that agree on their position and every attribute, so triangles from
+    /// formats that give each triangle its own vertices share them, and the vertices made
+    /// when subdividing between them
+    #[must_use]
+    pub fn welded(self) -> Self {
+        let mut welded = TriangleMesh::default();
+        let mut indices = HashMap::new();
+        let remap: Vec<usize> = (0..self.positions.len())
+            .map(|i| {
+                let mut key: Vec<u32> = bits(self.positions[i]).to_vec();
+                if let Some(&normal) = self.normals.get(i) {
+                    key.extend(bits(normal));
+                }
+                if let Some(&(u, v)) = self.uvs.get(i) {
+                    key.extend([u.to_bits(), v.to_bits()]);
+                }
+                if let Some(&color) = self.colors.get(i) {
+                    key.extend(bits(color));
+                }
+                *indices.entry(key).or_insert_with(|| {
+                    welded.positions.push(self.positions[i]);
+                    welded.normals.extend(self.normals.get(i));
+                    welded.uvs.extend(self.uvs.get(i));
+                    welded.colors.extend(self.colors.get(i));
+                    welded.positions.len() - 1
+                })
+            })
+            .collect();
+        welded.triangles = self
+            .triangles
+            .iter()
+            .map(|triangle| triangle.map(|i| remap[i]))
+            .collect();
+        welded
+    }
+
+    /// Whether every edge between positions is shared by at least two triangles, so the mesh
+    /// encloses a solid
+    pub fn is_closed(&self) -> bool {
+        let mut counts: HashMap<([u32; 3], [u32; 3]), usize> = HashMap::new();
+        for triangle in &self.triangles {
+            let [a, b, c] = triangle.map(|i| bits(self.positions[i]));
+            for (a, b) in [(a, b), (b, c), (c, a)] {
+                *counts.entry((a.min(b), a.max(b))).or_insert(0) += 1;
+            }
+        }
+        counts.values().all(|&count| count >= 2)
+    }
+
+    /// The length of the longest edge of any triangle
+    pub fn longest_edge(&self) -> f32 {
+        (0..self.triangles.len())
+            .flat_map(|triangle| {
+                let [a, b, c] = self.vertices(triangle);
+                [(b - a).length(), (c - b).length(), (a - c).length()]
+            })
+            .fold(0., f32::max)
+    }
+
+    /// Split every triangle into four at the middles of its edges. Attributes are blended
+    /// along the edges, while Loop subdivision also moves the positions of old and new
+    /// vertices towards a smooth surface.
+    #[must_use]
+    pub fn subdivided(&self, scheme: Subdivision) -> Self {
+        let mut firsts = HashMap::new();
+        let same_position: Vec<usize> = (0..self.positions.len())
+            .map(|i| *firsts.entry(bits(self.positions[i])).or_insert(i))
+            .collect();
+        let mut edges = Edges::new();
+        for triangle in &self.triangles {
+            let [a, b, c] = triangle.map(|i| same_position[i]);
+            for (from, to, opposite) in [(a, b, c), (b, c, a), (c, a, b)] {
+                edges.entry(edge(from, to)).or_default().push(opposite);
+            }
+        }
+
+        let mut mesh = TriangleMesh {
+            positions: match scheme {
+                Subdivision::Uniform => self.positions.clone(),
+                Subdivision::Loop => {
+                    let smoothed = self.smoothed_positions(&edges);
+                    same_position.iter().map(|&i| smoothed[i]).collect()
+                }
+            },
+            normals: self.normals.clone(),
+            uvs: self.uvs.clone(),
+            colors: self.colors.clone(),
+            triangles: Vec::with_capacity(4 * self.triangles.len()),
+        };
+        let mut middles = HashMap::new();
+        let mut middle = |mesh: &mut TriangleMesh, a: usize, b: usize| {
+            *middles.entry(edge(a, b)).or_insert_with(|| {
+                let opposite = &edges[&edge(same_position[a], same_position[b])];
+                let position = match (scheme, opposite.as_slice()) {
+                    (Subdivision::Loop, &[c, d]) => {
+                        0.375 * (self.positions[a] + self.positions[b])
+                            + 0.125 * (self.positions[c] + self.positions[d])
+                    }
+                    _ => 0.5 * (self.positions[a] + self.positions[b]),
+                };
+                mesh.positions.push(position);
+                if !self.normals.is_empty() {
+                    let normal = self.normals[a] + self.normals[b];
+                    mesh.normals.push(if normal.is_near_zero() {
+                        self.normals[a]
+                    } else {
+                        normal.unit_vector()
+                    });
+                }
+                if !self.uvs.is_empty() {
+                    let (ua, va) = self.uvs[a];
+                    let (ub, vb) = self.uvs[b];
+                    mesh.uvs.push((0.5 * (ua + ub), 0.5 * (va + vb)));
+                }
+                if !self.colors.is_empty() {
+                    mesh.colors.push(0.5 * (self.colors[a] + self.colors[b]));
+                }
+                mesh.positions.len() - 1
+            })
+        };
+        for &[a, b, c] in &self.triangles {
+            let ab = middle(&mut mesh, a, b);
+            let bc = middle(&mut mesh, b, c);
+            let ca = middle(&mut mesh, c, a);
+            mesh.triangles
+                .extend([[a, ab, ca], [ab, b, bc], [ca, bc, c], [ab, bc, ca]]);
+        }
+        mesh
+    }
+
+    /// The old positions moved by Loop's rules, for the first vertex at each. Edges with other
+    /// than two triangles are creases, which vertices on them only follow, and corners of
+    /// creases stay put.
+    fn smoothed_positions(&self, edges: &Edges) -> Vec<Point> {
+        let mut neighbors = vec![Vec::new(); self.positions.len()];
+        let mut creases = vec![Vec::new(); self.positions.len()];
+        for (&(a, b), opposite) in edges {
+            neighbors[a].push(b);
+            neighbors[b].push(a);
+            if opposite.len() != 2 {
+                creases[a].push(b);
+                creases[b].push(a);
+            }
+        }
+        (0..self.positions.len())
+            .map(|i| {
+                let position = self.positions[i];
+                match (creases[i].as_slice(), neighbors[i].len()) {
+                    (_, 0) => position,
+                    (&[], count) => {
+                        let n = count as f32;
+                        let beta = if count == 3 { 3. / 16. } else { 3. / (8. * n) };
+                        let sum = neighbors[i]
+                            .iter()
+                            .fold(Vec3::new(0., 0., 0.), |sum, &j| sum + self.positions[j]);
+                        (1. - n * beta) * position + beta * sum
+                    }
+                    (&[a, b], _) => {
+                        0.75 * position + 0.125 * (self.positions[a] + self.positions[b])
+                    }
+                    _ => position,
+                }
+            })
+            .collect()
+    }
+}
+
+/// The exact value of a vector, to find the vertices sharing a position
+pub(super) fn bits(v: Vec3) -> [u32; 3] {
+    [v.x().to_bits(), v.y().to_bits(), v.z().to_bits()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A regular octahedron around the origin, with every triangle's vertices its own
+    fn octahedron() -> TriangleMesh {
+        let axes = [
+            Vec3::new(1., 0., 0.),
+            Vec3::new(0., 1., 0.),
+            Vec3::new(-1., 0., 0.),
+            Vec3::new(0., -1., 0.),
+        ];
+        let mut mesh = TriangleMesh::default();
+        for i in 0..4 {
+            for pole in [Vec3::new(0., 0., 1.), Vec3::new(0., 0., -1.)] {
+                let start = mesh.positions.len();
+                mesh.positions.extend([axes[i], axes[(i + 1) % 4], pole]);
+                mesh.triangles.push([start, start + 1, start + 2]);
+            }
+        }
+        mesh
+    }
+
+    #[test]
+    fn test_uniform_subdivision_halves_edges() {
+        let mesh = octahedron().welded();
+        assert_eq!(mesh.positions.len(), 6);
+
+        let subdivided = mesh.subdivided(Subdivision::Uniform);
+        assert_eq!(subdivided.triangles.len(), 32);
+        // Each of the 12 edges gets one new vertex, shared by both its triangles
+        assert_eq!(subdivided.positions.len(), 18);
+        assert!((subdivided.longest_edge() - mesh.longest_edge() / 2.).abs() < 1e-5);
+        subdivided.check().unwrap();
+    }
+
+    #[test]
+    fn test_loop_subdivision_rounds_closed_meshes() {
+        let mesh = octahedron().welded();
+        let subdivided = mesh.subdivided(Subdivision::Loop);
+        // Both the corners and the middles of the edges are pulled towards the center
+        let lengths: Vec<f32> = subdivided.positions.iter().map(|p| p.length()).collect();
+        assert!(lengths[..6].iter().all(|&l| (l - 0.625).abs() < 1e-5));
+        let middle = 0.375 * 2f32.sqrt();
+        assert!(lengths[6..].iter().all(|&l| (l - middle).abs() < 1e-5));
+        subdivided.check().unwrap();
+
+        // Triangles that only share positions, as along seams, are smoothed together too
+        let unwelded = octahedron().subdivided(Subdivision::Loop);
+        assert_eq!(unwelded.positions.len(), 48);
+        assert!(unwelded.positions[..24]
+            .iter()
+            .all(|p| (p.length() - 0.625).abs() < 1e-5));
+        assert!(octahedron().is_closed() && unwelded.is_closed());
+    }
+}
@@ -1,74 +1,547 @@
+use std::path::Path;
+
+use anyhow::Result;
 use serde::{Deserialize, Serialize};
 
 use enum_dispatch::enum_dispatch;
 
-use crate::material::Material;
+use crate::aabb::Aabb;
+use crate::material::{Material, MaterialLibrary};
 use crate::primitive::ray::Ray;
-use crate::primitive::vec3::{Point, Vec3};
+use crate::primitive::vec3::{Color, Point, Vec3};
+use crate::validate::{join, Problems, Validate};
+
+/// The most surface crossings collected along a ray for constructive solid geometry
+const MAX_CROSSINGS: usize = 32;
+
+/// How far past a crossing the search for the next one starts
+const CROSSING_EPSILON: f32 = 1e-4;
 
+mod capsule;
+mod cone;
+mod constant_medium;
+mod csg;
+mod cuboid;
+mod cylinder;
+mod disk;
+mod grid;
+mod heightfield;
+mod instance;
+mod mesh;
+mod moving_sphere;
+mod named;
+mod plane;
+mod quad;
+mod sdf;
 mod sphere;
+mod torus;
+mod transform;
+mod volume;
+pub use capsule::Capsule;
+pub use cone::Cone;
+pub use constant_medium::ConstantMedium;
+pub use csg::{Difference, Intersection, Union};
+pub use cuboid::Cuboid;
+pub use cylinder::Cylinder;
+pub use disk::Disk;
+pub use grid::{Grid, GridSource, Noise};
+pub use heightfield::{HeightSource, Heightfield};
+pub use instance::Instance;
+pub use mesh::{Bvh, Displacement, Geometry, Mesh, Subdivision, TriangleMesh};
+pub use moving_sphere::MovingSphere;
+pub use named::Named;
+pub use plane::Plane;
+pub use quad::Quad;
+pub use sdf::{Sdf, SdfShape};
 pub use sphere::Sphere;
+pub use torus::Torus;
+pub use transform::Transform;
+pub use volume::{Emission, Volume};
+
+/// Shading normals may lean no closer to the geometric surface than this cosine, so the
+/// shading surface never turns away from directions the geometry faces
+const MIN_SHADING_COSINE: f32 = 0.05;
 
 #[derive(Debug, Copy, Clone, PartialEq)]
-pub struct HitRecord {
+pub struct HitRecord<'a> {
     point: Point,
+    /// The normal used for shading, e.g. smoothed between vertices or bent by a normal map
     normal: Vec3,
+    /// The normal of the surface that was actually hit, which decides its sides
+    geometric_normal: Vec3,
+    /// Unit directions along the surface, following u and v where the object has them, that
+    /// make up the shading frame with the normal
+    tangent: Vec3,
+    bitangent: Vec3,
     t: f32,
     is_front_face: bool,
-    material: Material,
+    material: &'a Material,
+    /// Surface coordinates of the hit, in [0, 1] on bounded surfaces
+    uv: (f32, f32),
+    /// Light given off at the hit, e.g. by glowing volumes
+    emitted: Color,
+    /// Multiplies the light the material scatters, e.g. by the colors of a mesh's vertices
+    tint: Color,
 }
 
-impl HitRecord {
+impl<'a> HitRecord<'a> {
     pub fn new(
         point: Point,
         normal: Vec3,
         t: f32,
         is_front_face: bool,
-        material: Material,
+        material: &'a Material,
     ) -> Self {
+        let (tangent, bitangent) = tangents(normal);
         Self {
             point,
             normal,
+            geometric_normal: normal,
+            tangent,
+            bitangent,
             t,
             is_front_face,
             material,
+            uv: (0., 0.),
+            emitted: Color::new(0., 0., 0.),
+            tint: Color::new(1., 1., 1.),
         }
     }
 
+    /// A hit on a surface with the outward normal, turning the normal against the ray
+    pub fn facing(ray: Ray, t: f32, outward_normal: Vec3, material: &'a Material) -> Self {
+        let is_front_face = ray.direction().dot(outward_normal) < 0.;
+        let normal = if is_front_face {
+            outward_normal
+        } else {
+            -outward_normal
+        };
+        Self::new(ray.at(t), normal, t, is_front_face, material)
+    }
+
+    #[must_use]
+    pub fn with_uv(self, u: f32, v: f32) -> Self {
+        Self { uv: (u, v), ..self }
+    }
+
+    /// Shade with another normal on the side of the surface that was hit, leaning it towards
+    /// the geometric normal where it would face away from directions the surface faces
+    #[must_use]
+    pub fn with_shading_normal(self, normal: Vec3) -> Self {
+        let cosine = normal.dot(self.geometric_normal);
+        let normal = if cosine < MIN_SHADING_COSINE {
+            (normal + (MIN_SHADING_COSINE - cosine) * self.geometric_normal).unit_vector()
+        } else {
+            normal
+        };
+        Self { normal, ..self }.with_tangents(self.tangent, self.bitangent)
+    }
+
+    /// Align the shading frame with the directions of increasing u and v, which are made
+    /// perpendicular to the shading normal. Degenerate directions keep the frame as it is.
+    #[must_use]
+    pub fn with_tangents(self, tangent: Vec3, bitangent: Vec3) -> Self {
+        let tangent = tangent - self.normal.dot(tangent) * self.normal;
+        if tangent.is_near_zero() {
+            return self;
+        }
+        let tangent = tangent.unit_vector();
+        let side = self.normal.cross(tangent);
+        // Keep the handedness of the bitangent, which flips with mirrored coordinates
+        let bitangent = if side.dot(bitangent) < 0. {
+            -side
+        } else {
+            side
+        };
+        Self {
+            tangent,
+            bitangent,
+            ..self
+        }
+    }
+
+    /// The same hit in another space, e.g. moved out of an instance, given its point there
+    /// and how normals and directions along the surface move
+    #[must_use]
+    pub fn moved(
+        self,
+        point: Point,
+        normal: impl Fn(Vec3) -> Vec3,
+        vector: impl Fn(Vec3) -> Vec3,
+    ) -> Self {
+        Self {
+            point,
+            normal: normal(self.normal).unit_vector(),
+            geometric_normal: normal(self.geometric_normal).unit_vector(),
+            ..self
+        }
+        .with_tangents(vector(self.tangent), vector(self.bitangent))
+    }
+
     pub fn point(self) -> Point {
         self.point
     }
 
+    /// The shading normal, on the side of the surface that was hit
     pub fn normal(self) -> Vec3 {
         self.normal
     }
 
+    /// The normal of the surface itself, on the side that was hit
+    pub fn geometric_normal(self) -> Vec3 {
+        self.geometric_normal
+    }
+
+    /// The tangent, bitangent and normal to shade with
+    pub fn shading_frame(self) -> (Vec3, Vec3, Vec3) {
+        (self.tangent, self.bitangent, self.normal)
+    }
+
+    /// Whether the direction leaves on the same side of the geometric surface as of the
+    /// shading one. Light may only arrive or scatter along such directions, or it would leak
+    /// through a surface bent by smoothing or mapping.
+    pub fn agrees_on_side(self, direction: Vec3) -> bool {
+        self.normal == self.geometric_normal
+            || direction.dot(self.normal) * direction.dot(self.geometric_normal) > 0.
+    }
+
     pub fn t(self) -> f32 {
         self.t
     }
 
-    pub fn material(self) -> Material {
+    pub fn material(self) -> &'a Material {
         self.material
     }
 
     pub fn is_front_face(self) -> bool {
         self.is_front_face
     }
+
+    pub fn uv(self) -> (f32, f32) {
+        self.uv
+    }
+
+    #[must_use]
+    pub fn with_emission(self, emitted: Color) -> Self {
+        Self { emitted, ..self }
+    }
+
+    pub fn emitted(self) -> Color {
+        self.emitted
+    }
+
+    #[must_use]
+    pub fn with_tint(self, tint: Color) -> Self {
+        Self { tint, ..self }
+    }
+
+    pub fn tint(self) -> Color {
+        self.tint
+    }
+
+    /// The same hit, entering the surface or leaving it
+    #[must_use]
+    pub fn with_front_face(self, is_front_face: bool) -> Self {
+        Self {
+            is_front_face,
+            ..self
+        }
+    }
+}
+
+/// A stretch of a ray inside a solid, from where it enters to where it exits. A missing end
+/// means the ray stays inside beyond it, e.g. when it starts inside.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Span<'a> {
+    enter: Option<HitRecord<'a>>,
+    exit: Option<HitRecord<'a>>,
+}
+
+impl<'a> Span<'a> {
+    pub fn new(enter: Option<HitRecord<'a>>, exit: Option<HitRecord<'a>>) -> Self {
+        Self { enter, exit }
+    }
+
+    pub fn enter(self) -> Option<HitRecord<'a>> {
+        self.enter
+    }
+
+    pub fn exit(self) -> Option<HitRecord<'a>> {
+        self.exit
+    }
+
+    /// Both ends moved by the function, e.g. into another space
+    #[must_use]
+    pub fn map(self, f: impl Fn(HitRecord<'a>) -> HitRecord<'a>) -> Self {
+        Self {
+            enter: self.enter.map(&f),
+            exit: self.exit.map(&f),
+        }
+    }
 }
 
 #[enum_dispatch]
 pub trait Hittable {
-    fn hit(&self, ray: Ray, t_min: f32, t_max: f32) -> Option<HitRecord>;
+    /// Intersect the object as it is at the time of the ray
+    fn hit(&self, ray: Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>>;
+
+    /// A box containing the object over its whole motion, or None if it is unbounded
+    fn bounding_box(&self) -> Option<Aabb>;
+
+    /// Where the ray is inside the object from t = 0 on, in order, for constructive solid
+    /// geometry. By default found from where the ray crosses the surface, which only bounds a
+    /// solid for closed objects.
+    fn spans(&self, ray: Ray) -> Vec<Span<'_>> {
+        let mut crossings = Vec::new();
+        let mut t_min = 0.;
+        while let Some(record) = self.hit(ray, t_min, f32::MAX) {
+            crossings.push(record);
+            if crossings.len() == MAX_CROSSINGS {
+                break;
+            }
+            t_min = record.t() + CROSSING_EPSILON;
+        }
+        spans_between(crossings)
+    }
+
+    /// Sample where the ray collides with a medium in the object between the bounds, as
+    /// fog or smoke. Media have no surface, so `hit` passes through them and the integrator
+    /// samples them with this instead.
+    fn collide(&self, _ray: Ray, _t_min: f32, _t_max: f32) -> Option<HitRecord<'_>> {
+        None
+    }
+
+    /// The fraction of light passing through the media in the object between the bounds
+    fn transmittance(&self, _ray: Ray, _t_min: f32, _t_max: f32) -> f32 {
+        1.
+    }
 }
 
-#[enum_dispatch(Hittable)]
+/// Pair up crossings in order along a ray into the spans between entering and exiting
+fn spans_between(crossings: Vec<HitRecord>) -> Vec<Span> {
+    let mut spans = Vec::new();
+    let mut enter = None;
+    let mut inside = false;
+    for record in crossings {
+        if record.is_front_face() {
+            if !inside {
+                enter = Some(record);
+                inside = true;
+            }
+        } else if inside || spans.is_empty() {
+            // Exiting before any entry means the ray started inside
+            spans.push(Span::new(enter.take(), Some(record)));
+            inside = false;
+        }
+    }
+    if inside {
+        spans.push(Span::new(enter, None));
+    }
+    spans
+}
+
+#[enum_dispatch]
+pub trait ResolveMaterials {
+    /// Replace references to named materials with the materials from the library
+    fn resolve_materials(&mut self, library: &MaterialLibrary) -> Result<()>;
+}
+
+#[enum_dispatch]
+pub trait LoadFiles {
+    /// Load the files the object and its inline materials refer to, relative to the directory
+    /// of the scene
+    fn load_files(&mut self, directory: &Path) -> Result<()>;
+}
+
+#[enum_dispatch(Hittable, ResolveMaterials, LoadFiles)]
 #[derive(Debug, PartialEq, Serialize, Deserialize)]
 pub enum Object {
     Sphere,
+    MovingSphere,
+    Instance,
+    Named,
+    Plane,
+    Quad,
+    Disk,
+    Box(Cuboid),
+    Cylinder,
+    Cone,
+    Torus,
+    Capsule,
+    Union,
+    Intersection,
+    Difference,
+    ConstantMedium,
+    Volume,
+    Sdf,
+    Heightfield,
+    Mesh,
+}
+
+impl Object {
+    /// The names of this object and the objects it contains
+    pub fn names(&self) -> Vec<&str> {
+        match self {
+            Object::Named(named) => {
+                let mut names = named.object().names();
+                names.insert(0, named.name());
+                names
+            }
+            Object::Instance(instance) => instance.object().names(),
+            Object::ConstantMedium(medium) => medium.boundary().names(),
+            Object::Union(union) => union.objects().iter().flat_map(Object::names).collect(),
+            Object::Intersection(intersection) => intersection
+                .objects()
+                .iter()
+                .flat_map(Object::names)
+                .collect(),
+            Object::Difference(difference) => difference
+                .objects()
+                .iter()
+                .flat_map(Object::names)
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// The object with the name, this one or one inside it, with the transforms of the
+    /// instances around it at the time, from the innermost out
+    pub fn find(&self, name: &str, time: f32) -> Option<(&Object, Vec<Transform>)> {
+        let objects = match self {
+            Object::Named(named) if named.name() == name => return Some((self, Vec::new())),
+            Object::Named(named) => return named.object().find(name, time),
+            Object::Instance(instance) => {
+                let (object, mut transforms) = instance.object().find(name, time)?;
+                transforms.push(instance.transform(time));
+                return Some((object, transforms));
+            }
+            Object::ConstantMedium(medium) => return medium.boundary().find(name, time),
+            Object::Union(union) => union.objects(),
+            Object::Intersection(intersection) => intersection.objects(),
+            Object::Difference(difference) => difference.objects(),
+            _ => return None,
+        };
+        objects.iter().find_map(|object| object.find(name, time))
+    }
+}
+
+impl Validate for Object {
+    fn validate(&self, path: &str, problems: &mut Problems) {
+        match self {
+            Object::Sphere(sphere) => sphere.validate(&join(path, "Sphere"), problems),
+            Object::MovingSphere(sphere) => sphere.validate(&join(path, "MovingSphere"), problems),
+            Object::Instance(instance) => instance.validate(&join(path, "Instance"), problems),
+            Object::Named(named) => named.validate(&join(path, "Named"), problems),
+            Object::Plane(plane) => plane.validate(&join(path, "Plane"), problems),
+            Object::Quad(quad) => quad.validate(&join(path, "Quad"), problems),
+            Object::Disk(disk) => disk.validate(&join(path, "Disk"), problems),
+            Object::Box(cuboid) => cuboid.validate(&join(path, "Box"), problems),
+            Object::Cylinder(cylinder) => cylinder.validate(&join(path, "Cylinder"), problems),
+            Object::Cone(cone) => cone.validate(&join(path, "Cone"), problems),
+            Object::Torus(torus) => torus.validate(&join(path, "Torus"), problems),
+            Object::Capsule(capsule) => capsule.validate(&join(path, "Capsule"), problems),
+            Object::Union(union) => union.validate(&join(path, "Union"), problems),
+            Object::Intersection(intersection) => {
+                intersection.validate(&join(path, "Intersection"), problems)
+            }
+            Object::Difference(difference) => {
+                difference.validate(&join(path, "Difference"), problems)
+            }
+            Object::ConstantMedium(medium) => {
+                medium.validate(&join(path, "ConstantMedium"), problems)
+            }
+            Object::Volume(volume) => volume.validate(&join(path, "Volume"), problems),
+            Object::Sdf(sdf) => sdf.validate(&join(path, "Sdf"), problems),
+            Object::Heightfield(heightfield) => {
+                heightfield.validate(&join(path, "Heightfield"), problems)
+            }
+            Object::Mesh(mesh) => mesh.validate(&join(path, "Mesh"), problems),
+        }
+    }
+}
+
+/// Axes along a plane with the normal, used for surface coordinates on flat objects
+fn tangents(normal: Vec3) -> (Vec3, Vec3) {
+    let helper = if normal.x().abs() > 0.9 {
+        Vec3::new(0., 1., 0.)
+    } else {
+        Vec3::new(1., 0., 0.)
+    };
+    let tangent = helper.cross(normal).unit_vector();
+    (tangent, normal.cross(tangent).unit_vector())
+}
+
+/// Orthonormal axes around an object's axis, which becomes the local z axis
+#[derive(Debug, Clone, Copy)]
+struct Frame {
+    origin: Point,
+    x: Vec3,
+    y: Vec3,
+    z: Vec3,
+}
+
+impl Frame {
+    fn new(origin: Point, axis: Vec3) -> Self {
+        let z = axis.unit_vector();
+        let (x, y) = tangents(z);
+        Self { origin, x, y, z }
+    }
+
+    /// The ray's origin and direction in local coordinates, where t stays the same
+    fn ray_to_local(&self, ray: Ray) -> (Vec3, Vec3) {
+        let local = |v: Vec3| Vec3::new(v.dot(self.x), v.dot(self.y), v.dot(self.z));
+        (local(ray.origin() - self.origin), local(ray.direction()))
+    }
+
+    fn vector_to_world(&self, v: Vec3) -> Vec3 {
+        v.x() * self.x + v.y() * self.y + v.z() * self.z
+    }
+}
+
+/// The t and barycentric coordinates of the ray's hit on a triangle, by Möller–Trumbore
+fn hit_triangle(ray: Ray, a: Vec3, b: Vec3, c: Vec3) -> Option<(f32, f32, f32)> {
+    let edge1 = b - a;
+    let edge2 = c - a;
+    let p = ray.direction().cross(edge2);
+    let determinant = edge1.dot(p);
+    if determinant.abs() < 1e-12 {
+        return None;
+    }
+    let inverse = 1. / determinant;
+    let offset = ray.origin() - a;
+    let u = offset.dot(p) * inverse;
+    if !(0. ..=1.).contains(&u) {
+        return None;
+    }
+    let q = offset.cross(edge1);
+    let v = ray.direction().dot(q) * inverse;
+    if v < 0. || u + v > 1. {
+        return None;
+    }
+    Some((edge2.dot(q) * inverse, u, v))
+}
+
+/// The angle of the local point around the z axis, as a surface coordinate in [0, 1]
+fn angle_around_axis(point: Vec3) -> f32 {
+    (point.y().atan2(point.x()) + std::f32::consts::PI) / (2. * std::f32::consts::PI)
+}
+
+/// How far along its motion from `start` to `end` an object is at the time, clamped to [0, 1]
+fn motion_fraction(time: f32, start: f32, end: f32) -> f32 {
+    ((time - start) / (end - start)).clamp(0., 1.)
+}
+
+fn validate_motion(start: f32, end: f32, path: &str, problems: &mut Problems) {
+    if end <= start {
+        problems.error(
+            &join(path, "time_end"),
+            "The motion must end after it starts",
+        );
+    }
 }
 
 impl Hittable for Vec<Object> {
-    fn hit(&self, ray: Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+    fn hit(&self, ray: Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
         let mut closest_hit = None;
         let mut closest_so_far = t_max;
 
@@ -81,10 +554,22 @@ impl Hittable for Vec<Object> {
         }
         closest_hit
     }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.as_slice().bounding_box()
+    }
+
+    fn collide(&self, ray: Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+        self.as_slice().collide(ray, t_min, t_max)
+    }
+
+    fn transmittance(&self, ray: Ray, t_min: f32, t_max: f32) -> f32 {
+        self.as_slice().transmittance(ray, t_min, t_max)
+    }
 }
 
 impl Hittable for [Object] {
-    fn hit(&self, ray: Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+    fn hit(&self, ray: Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
         let mut closest_hit = None;
         let mut closest_so_far = t_max;
 
@@ -97,4 +582,30 @@ impl Hittable for [Object] {
         }
         closest_hit
     }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let mut boxes = self.iter().map(Hittable::bounding_box);
+        let first = boxes.next()??;
+        boxes.try_fold(first, |aabb, other| Some(aabb.surrounding(other?)))
+    }
+
+    /// The nearest collision, which is where the first of the media collides when each is
+    /// sampled on its own
+    fn collide(&self, ray: Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+        let mut closest = None;
+        let mut closest_so_far = t_max;
+        for object in self {
+            if let Some(record) = object.collide(ray, t_min, closest_so_far) {
+                closest = Some(record);
+                closest_so_far = record.t;
+            }
+        }
+        closest
+    }
+
+    fn transmittance(&self, ray: Ray, t_min: f32, t_max: f32) -> f32 {
+        self.iter()
+            .map(|object| object.transmittance(ray, t_min, t_max))
+            .product()
+    }
 }
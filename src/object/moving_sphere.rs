@@ -0,0 +1,88 @@
+use std::path::Path;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::aabb::Aabb;
+use crate::material::{MaterialLibrary, MaterialRef};
+use crate::object::sphere::{hit_sphere, sphere_box, validate_radius};
+use crate::object::{
+    motion_fraction, validate_motion, HitRecord, Hittable, LoadFiles, ResolveMaterials,
+};
+use crate::ray::Ray;
+use crate::validate::{join, Problems, Validate};
+use crate::vec3::Point;
+
+/// A sphere moving in a straight line from `center_start` at `time_start` to `center_end` at
+/// `time_end`, resting at either end outside of that interval
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct MovingSphere {
+    center_start: Point,
+    center_end: Point,
+    #[serde(default)]
+    time_start: f32,
+    #[serde(default = "default_time_end")]
+    time_end: f32,
+    radius: f32,
+    material: MaterialRef,
+}
+
+fn default_time_end() -> f32 {
+    1.
+}
+
+impl MovingSphere {
+    pub fn new<M: Into<MaterialRef>>(
+        (center_start, time_start): (Point, f32),
+        (center_end, time_end): (Point, f32),
+        radius: f32,
+        material: M,
+    ) -> Self {
+        Self {
+            center_start,
+            center_end,
+            time_start,
+            time_end,
+            radius,
+            material: material.into(),
+        }
+    }
+
+    pub fn center(&self, time: f32) -> Point {
+        let t = motion_fraction(time, self.time_start, self.time_end);
+        self.center_start.lerp(self.center_end, t)
+    }
+}
+
+impl Hittable for MovingSphere {
+    fn hit(&self, ray: Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+        let center = self.center(ray.time());
+        hit_sphere(center, self.radius, &self.material, ray, t_min, t_max)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let start = sphere_box(self.center_start, self.radius);
+        let end = sphere_box(self.center_end, self.radius);
+        Some(start.surrounding(end))
+    }
+}
+
+impl ResolveMaterials for MovingSphere {
+    fn resolve_materials(&mut self, library: &MaterialLibrary) -> Result<()> {
+        self.material.resolve(library)
+    }
+}
+
+impl LoadFiles for MovingSphere {
+    fn load_files(&mut self, directory: &Path) -> Result<()> {
+        self.material.load_files(directory)
+    }
+}
+
+impl Validate for MovingSphere {
+    fn validate(&self, path: &str, problems: &mut Problems) {
+        validate_radius(self.radius, &self.material, path, problems);
+        validate_motion(self.time_start, self.time_end, path, problems);
+        self.material.validate(&join(path, "material"), problems);
+    }
+}
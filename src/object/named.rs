@@ -0,0 +1,77 @@
+use std::path::Path;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::aabb::Aabb;
+use crate::material::MaterialLibrary;
+use crate::object::{HitRecord, Hittable, LoadFiles, Object, ResolveMaterials, Span};
+use crate::ray::Ray;
+use crate::validate::{join, Problems, Validate};
+
+/// An object that other parts of the scene can refer to by name, e.g. to focus the camera on it
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct Named {
+    name: String,
+    object: Box<Object>,
+}
+
+impl Named {
+    pub fn new<S: Into<String>>(name: S, object: Object) -> Self {
+        Self {
+            name: name.into(),
+            object: Box::new(object),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn object(&self) -> &Object {
+        &self.object
+    }
+}
+
+impl Hittable for Named {
+    fn hit(&self, ray: Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+        self.object.hit(ray, t_min, t_max)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.object.bounding_box()
+    }
+
+    fn spans(&self, ray: Ray) -> Vec<Span<'_>> {
+        self.object.spans(ray)
+    }
+
+    fn collide(&self, ray: Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+        self.object.collide(ray, t_min, t_max)
+    }
+
+    fn transmittance(&self, ray: Ray, t_min: f32, t_max: f32) -> f32 {
+        self.object.transmittance(ray, t_min, t_max)
+    }
+}
+
+impl ResolveMaterials for Named {
+    fn resolve_materials(&mut self, library: &MaterialLibrary) -> Result<()> {
+        self.object.resolve_materials(library)
+    }
+}
+
+impl LoadFiles for Named {
+    fn load_files(&mut self, directory: &Path) -> Result<()> {
+        self.object.load_files(directory)
+    }
+}
+
+impl Validate for Named {
+    fn validate(&self, path: &str, problems: &mut Problems) {
+        if self.name.is_empty() {
+            problems.error(&join(path, "name"), "The name must not be empty");
+        }
+        self.object.validate(&join(path, "object"), problems);
+    }
+}
@@ -0,0 +1,123 @@
+use std::path::Path;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::aabb::Aabb;
+use crate::material::{MaterialLibrary, MaterialRef};
+use crate::object::{tangents, HitRecord, Hittable, LoadFiles, ResolveMaterials, Span};
+use crate::ray::Ray;
+use crate::validate::{join, Problems, Validate};
+use crate::vec3::{Point, Vec3};
+
+/// An infinite plane through `point`, facing along `normal`
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct Plane {
+    point: Point,
+    normal: Vec3,
+    material: MaterialRef,
+    /// The size of a tile of the surface coordinates, which repeat across the plane
+    #[serde(default = "default_uv_scale")]
+    uv_scale: f32,
+}
+
+fn default_uv_scale() -> f32 {
+    1.
+}
+
+impl Plane {
+    pub fn new<M: Into<MaterialRef>>(point: Point, normal: Vec3, material: M) -> Self {
+        Self {
+            point,
+            normal,
+            material: material.into(),
+            uv_scale: default_uv_scale(),
+        }
+    }
+}
+
+impl Hittable for Plane {
+    fn hit(&self, ray: Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+        let normal = self.normal.unit_vector();
+        let denominator = normal.dot(ray.direction());
+        if denominator.abs() < 1e-8 {
+            return None;
+        }
+        let t = (self.point - ray.origin()).dot(normal) / denominator;
+        if t < t_min || t_max < t {
+            return None;
+        }
+
+        let (tangent, bitangent) = tangents(normal);
+        let offset = ray.at(t) - self.point;
+        let u = (offset.dot(tangent) / self.uv_scale).rem_euclid(1.);
+        let v = (offset.dot(bitangent) / self.uv_scale).rem_euclid(1.);
+        let record = HitRecord::facing(ray, t, normal, self.material.material());
+        Some(record.with_tangents(tangent, bitangent).with_uv(u, v))
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        None
+    }
+
+    /// The plane bounds the half space behind it
+    fn spans(&self, ray: Ray) -> Vec<Span<'_>> {
+        match self.hit(ray, 0., f32::MAX) {
+            Some(record) if record.is_front_face() => vec![Span::new(Some(record), None)],
+            Some(record) => vec![Span::new(None, Some(record))],
+            None if (ray.origin() - self.point).dot(self.normal) < 0. => {
+                vec![Span::new(None, None)]
+            }
+            None => Vec::new(),
+        }
+    }
+}
+
+impl ResolveMaterials for Plane {
+    fn resolve_materials(&mut self, library: &MaterialLibrary) -> Result<()> {
+        self.material.resolve(library)
+    }
+}
+
+impl LoadFiles for Plane {
+    fn load_files(&mut self, directory: &Path) -> Result<()> {
+        self.material.load_files(directory)
+    }
+}
+
+impl Validate for Plane {
+    fn validate(&self, path: &str, problems: &mut Problems) {
+        if self.normal.length_squared() == 0. {
+            problems.error(&join(path, "normal"), "The normal must not be zero");
+        }
+        if self.uv_scale <= 0. {
+            problems.error(&join(path, "uv_scale"), "The uv scale must be positive");
+        }
+        self.material.validate(&join(path, "material"), problems);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::{Lambertian, Material, SurfaceMaps};
+
+    #[test]
+    fn test_frame_follows_uv_on_both_sides() {
+        let material = Material::Lambertian(Lambertian {
+            albedo: Vec3::new(0.5, 0.5, 0.5),
+            maps: SurfaceMaps::default(),
+        });
+        let plane = Plane::new(Vec3::new(0., 0., 0.), Vec3::new(0., 0., 1.), material);
+        let (u_axis, v_axis) = tangents(Vec3::new(0., 0., 1.));
+
+        for z in [1., -1.] {
+            let ray = Ray::new(Vec3::new(0.2, 0.3, z), Vec3::new(0., 0., -z));
+            let record = plane.hit(ray, 0., f32::MAX).unwrap();
+            let (tangent, bitangent, normal) = record.shading_frame();
+            assert!((normal.z() - z).abs() < 1e-5);
+            assert!((tangent - u_axis).length() < 1e-5);
+            assert!((bitangent - v_axis).length() < 1e-5);
+        }
+    }
+}
@@ -0,0 +1,120 @@
+use std::path::Path;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::aabb::Aabb;
+use crate::material::{MaterialLibrary, MaterialRef};
+use crate::object::{HitRecord, Hittable, LoadFiles, ResolveMaterials};
+use crate::ray::Ray;
+use crate::validate::{join, Problems, Validate};
+use crate::vec3::{Point, Vec3};
+
+/// A parallelogram with a corner at `corner` and sides along `u` and `v`, facing along u × v
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct Quad {
+    corner: Point,
+    u: Vec3,
+    v: Vec3,
+    material: MaterialRef,
+}
+
+impl Quad {
+    pub fn new<M: Into<MaterialRef>>(corner: Point, u: Vec3, v: Vec3, material: M) -> Self {
+        Self {
+            corner,
+            u,
+            v,
+            material: material.into(),
+        }
+    }
+}
+
+impl Hittable for Quad {
+    fn hit(&self, ray: Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+        let n = self.u.cross(self.v);
+        let normal = n.unit_vector();
+        let denominator = normal.dot(ray.direction());
+        if denominator.abs() < 1e-8 {
+            return None;
+        }
+        let t = (self.corner - ray.origin()).dot(normal) / denominator;
+        if t < t_min || t_max < t {
+            return None;
+        }
+
+        // Express the hit in the sides of the quad, which go from 0 to 1 inside it
+        let w = n / n.dot(n);
+        let offset = ray.at(t) - self.corner;
+        let alpha = w.dot(offset.cross(self.v));
+        let beta = w.dot(self.u.cross(offset));
+        if !(0. ..=1.).contains(&alpha) || !(0. ..=1.).contains(&beta) {
+            return None;
+        }
+
+        let record = HitRecord::facing(ray, t, normal, self.material.material());
+        Some(record.with_tangents(self.u, self.v).with_uv(alpha, beta))
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let corners = [
+            self.corner,
+            self.corner + self.u,
+            self.corner + self.v,
+            self.corner + self.u + self.v,
+        ];
+        // Pad flat boxes, so rays along the plane still hit them
+        Aabb::from_points(corners).map(|aabb| aabb.padded(1e-4))
+    }
+}
+
+impl ResolveMaterials for Quad {
+    fn resolve_materials(&mut self, library: &MaterialLibrary) -> Result<()> {
+        self.material.resolve(library)
+    }
+}
+
+impl LoadFiles for Quad {
+    fn load_files(&mut self, directory: &Path) -> Result<()> {
+        self.material.load_files(directory)
+    }
+}
+
+impl Validate for Quad {
+    fn validate(&self, path: &str, problems: &mut Problems) {
+        if self.u.cross(self.v).length_squared() == 0. {
+            problems.error(path, "The sides u and v must be non-zero and not parallel");
+        }
+        self.material.validate(&join(path, "material"), problems);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::{Lambertian, Material, SurfaceMaps};
+
+    #[test]
+    fn test_hit_uv() {
+        let material = Material::Lambertian(Lambertian {
+            albedo: Vec3::new(0.5, 0.5, 0.5),
+            maps: SurfaceMaps::default(),
+        });
+        let quad = Quad::new(
+            Vec3::new(0., 0., 0.),
+            Vec3::new(2., 0., 0.),
+            Vec3::new(0., 4., 0.),
+            material,
+        );
+
+        let ray = Ray::new(Vec3::new(0.5, 3., 1.), Vec3::new(0., 0., -1.));
+        let record = quad.hit(ray, 0., f32::MAX).unwrap();
+        assert!((record.t() - 1.).abs() < 1e-5);
+        assert!((record.uv().0 - 0.25).abs() < 1e-5);
+        assert!((record.uv().1 - 0.75).abs() < 1e-5);
+        assert!(record.is_front_face());
+
+        let outside = Ray::new(Vec3::new(2.5, 3., 1.), Vec3::new(0., 0., -1.));
+        assert!(quad.hit(outside, 0., f32::MAX).is_none());
+    }
+}
@@ -0,0 +1,413 @@
+use std::path::Path;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::aabb::Aabb;
+use crate::material::{MaterialLibrary, MaterialRef};
+use crate::object::{HitRecord, Hittable, LoadFiles, ResolveMaterials};
+use crate::ray::Ray;
+use crate::validate::{join, Problems, Validate};
+use crate::vec3::{Point, Vec3};
+
+/// The most steps sphere tracing takes along a ray before giving up
+const MAX_STEPS: usize = 512;
+
+/// How close to the surface counts as hitting it
+const SURFACE_DISTANCE: f32 = 1e-4;
+
+/// A shape given by its signed distance function, negative inside, intersected by sphere
+/// tracing: stepping along the ray by the distance to the nearest surface
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct Sdf {
+    shape: SdfShape,
+    /// Two opposite corners of a box around the shape, needed for repeated shapes
+    bounds: Option<[Point; 2]>,
+    /// The fraction of the distance to step, below 1 for shapes like twists whose distances
+    /// overestimate and would step past the surface
+    #[serde(default = "default_step_scale")]
+    step_scale: f32,
+    material: MaterialRef,
+}
+
+fn default_step_scale() -> f32 {
+    1.
+}
+
+/// A node of a distance function. Primitives are centered on the origin.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub enum SdfShape {
+    Sphere {
+        radius: f32,
+    },
+    /// A box reaching `size` from the center along each axis
+    Box {
+        size: Vec3,
+    },
+    /// A box whose edges and corners are rounded off with the radius
+    RoundedBox {
+        size: Vec3,
+        radius: f32,
+    },
+    /// A ring around the y axis
+    Torus {
+        major_radius: f32,
+        minor_radius: f32,
+    },
+    Translate {
+        offset: Vec3,
+        shape: Box<SdfShape>,
+    },
+    Union {
+        shapes: Vec<SdfShape>,
+    },
+    /// A union blending the shapes together within about `smoothness` of where they meet
+    SmoothUnion {
+        shapes: Vec<SdfShape>,
+        smoothness: f32,
+    },
+    /// The first shape with the others cut away
+    Subtract {
+        shapes: Vec<SdfShape>,
+    },
+    Intersect {
+        shapes: Vec<SdfShape>,
+    },
+    /// Copies of the shape every `period` along each axis, or not along axes with a period of 0
+    Repeat {
+        period: Vec3,
+        shape: Box<SdfShape>,
+    },
+    /// The shape turned around the y axis by `rate` radians per unit of height
+    Twist {
+        rate: f32,
+        shape: Box<SdfShape>,
+    },
+}
+
+impl SdfShape {
+    /// The signed distance from the point to the surface, negative inside
+    pub fn distance(&self, p: Vec3) -> f32 {
+        match self {
+            SdfShape::Sphere { radius } => p.length() - radius,
+            SdfShape::Box { size } => box_distance(p, *size),
+            SdfShape::RoundedBox { size, radius } => {
+                box_distance(p, *size - Vec3::new(*radius, *radius, *radius)) - radius
+            }
+            SdfShape::Torus {
+                major_radius,
+                minor_radius,
+            } => {
+                let ring = (p.x() * p.x() + p.z() * p.z()).sqrt() - major_radius;
+                (ring * ring + p.y() * p.y()).sqrt() - minor_radius
+            }
+            SdfShape::Translate { offset, shape } => shape.distance(p - *offset),
+            SdfShape::Union { shapes } => fold(shapes, p, f32::min),
+            SdfShape::SmoothUnion { shapes, smoothness } => {
+                let k = smoothness.max(1e-6);
+                fold(shapes, p, |a, b| {
+                    let h = (0.5 + 0.5 * (b - a) / k).clamp(0., 1.);
+                    b + (a - b) * h - k * h * (1. - h)
+                })
+            }
+            SdfShape::Subtract { shapes } => fold(shapes, p, |a, b| a.max(-b)),
+            SdfShape::Intersect { shapes } => fold(shapes, p, f32::max),
+            SdfShape::Repeat { period, shape } => {
+                let wrap = |x: f32, period: f32| {
+                    if period > 0. {
+                        x - period * (x / period).round()
+                    } else {
+                        x
+                    }
+                };
+                shape.distance(Vec3::new(
+                    wrap(p.x(), period.x()),
+                    wrap(p.y(), period.y()),
+                    wrap(p.z(), period.z()),
+                ))
+            }
+            SdfShape::Twist { rate, shape } => {
+                let (sin, cos) = (rate * p.y()).sin_cos();
+                shape.distance(Vec3::new(
+                    cos * p.x() - sin * p.z(),
+                    p.y(),
+                    sin * p.x() + cos * p.z(),
+                ))
+            }
+        }
+    }
+
+    /// A box around the shape, or None if it is unbounded
+    pub fn bounds(&self) -> Option<Aabb> {
+        let around = |extent: Vec3| Aabb::new(-extent, extent);
+        match self {
+            SdfShape::Sphere { radius } => Some(around(Vec3::new(*radius, *radius, *radius))),
+            SdfShape::Box { size } | SdfShape::RoundedBox { size, .. } => Some(around(*size)),
+            SdfShape::Torus {
+                major_radius,
+                minor_radius,
+            } => {
+                let reach = major_radius + minor_radius;
+                Some(around(Vec3::new(reach, *minor_radius, reach)))
+            }
+            SdfShape::Translate { offset, shape } => {
+                let aabb = shape.bounds()?;
+                Some(Aabb::new(aabb.min() + *offset, aabb.max() + *offset))
+            }
+            SdfShape::Union { shapes } => union_bounds(shapes),
+            SdfShape::SmoothUnion { shapes, smoothness } => {
+                union_bounds(shapes).map(|aabb| aabb.padded(*smoothness))
+            }
+            SdfShape::Subtract { shapes } => shapes.first()?.bounds(),
+            SdfShape::Intersect { shapes } => shapes
+                .iter()
+                .filter_map(SdfShape::bounds)
+                .reduce(Aabb::intersection),
+            SdfShape::Repeat { .. } => None,
+            SdfShape::Twist { shape, .. } => {
+                // Twisting sweeps the shape around the y axis
+                let aabb = shape.bounds()?;
+                let reach = aabb
+                    .corners()
+                    .iter()
+                    .map(|corner| (corner.x() * corner.x() + corner.z() * corner.z()).sqrt())
+                    .fold(0., f32::max);
+                Some(Aabb::new(
+                    Vec3::new(-reach, aabb.min().y(), -reach),
+                    Vec3::new(reach, aabb.max().y(), reach),
+                ))
+            }
+        }
+    }
+}
+
+impl Validate for SdfShape {
+    fn validate(&self, path: &str, problems: &mut Problems) {
+        let positive = |value: f32, field: &str, problems: &mut Problems| {
+            if value <= 0. {
+                problems.error(&join(path, field), "The value must be positive");
+            }
+        };
+        let children = |shapes: &[SdfShape], variant: &str, problems: &mut Problems| {
+            let path = join(&join(path, variant), "shapes");
+            if shapes.is_empty() {
+                problems.error(&path, "At least one shape is needed");
+            }
+            for (index, shape) in shapes.iter().enumerate() {
+                shape.validate(&join(&path, &index.to_string()), problems);
+            }
+        };
+        match self {
+            SdfShape::Sphere { radius } => positive(*radius, "Sphere.radius", problems),
+            SdfShape::Box { size } => {
+                positive(size.x().min(size.y()).min(size.z()), "Box.size", problems)
+            }
+            SdfShape::RoundedBox { size, radius } => {
+                positive(*radius, "RoundedBox.radius", problems);
+                if size.x().min(size.y()).min(size.z()) < *radius {
+                    problems.error(
+                        &join(path, "RoundedBox.size"),
+                        "The size must be at least the radius along each axis",
+                    );
+                }
+            }
+            SdfShape::Torus {
+                major_radius,
+                minor_radius,
+            } => {
+                positive(*major_radius, "Torus.major_radius", problems);
+                positive(*minor_radius, "Torus.minor_radius", problems);
+            }
+            SdfShape::Translate { shape, .. } => {
+                shape.validate(&join(path, "Translate.shape"), problems)
+            }
+            SdfShape::Union { shapes } => children(shapes, "Union", problems),
+            SdfShape::SmoothUnion { shapes, smoothness } => {
+                positive(*smoothness, "SmoothUnion.smoothness", problems);
+                children(shapes, "SmoothUnion", problems);
+            }
+            SdfShape::Subtract { shapes } => children(shapes, "Subtract", problems),
+            SdfShape::Intersect { shapes } => children(shapes, "Intersect", problems),
+            SdfShape::Repeat { period, shape } => {
+                if period.x() < 0. || period.y() < 0. || period.z() < 0. {
+                    problems.error(
+                        &join(path, "Repeat.period"),
+                        "The period must not be negative",
+                    );
+                }
+                shape.validate(&join(path, "Repeat.shape"), problems);
+            }
+            SdfShape::Twist { shape, .. } => shape.validate(&join(path, "Twist.shape"), problems),
+        }
+    }
+}
+
+/// The distance to a box reaching `size` from the origin along each axis
+fn box_distance(p: Vec3, size: Vec3) -> f32 {
+    let q = Vec3::new(p.x().abs(), p.y().abs(), p.z().abs()) - size;
+    let outside = q.max(Vec3::new(0., 0., 0.)).length();
+    let inside = q.x().max(q.y()).max(q.z()).min(0.);
+    outside + inside
+}
+
+/// Combine the distances to the shapes pairwise, in order
+fn fold(shapes: &[SdfShape], p: Vec3, combine: impl Fn(f32, f32) -> f32) -> f32 {
+    shapes
+        .iter()
+        .map(|shape| shape.distance(p))
+        .reduce(combine)
+        .unwrap_or(f32::MAX)
+}
+
+fn union_bounds(shapes: &[SdfShape]) -> Option<Aabb> {
+    let mut boxes = shapes.iter().map(SdfShape::bounds);
+    let first = boxes.next()??;
+    boxes.try_fold(first, |aabb, other| Some(aabb.surrounding(other?)))
+}
+
+impl Sdf {
+    pub fn new<M: Into<MaterialRef>>(shape: SdfShape, material: M) -> Self {
+        Self {
+            shape,
+            bounds: None,
+            step_scale: default_step_scale(),
+            material: material.into(),
+        }
+    }
+
+    fn aabb(&self) -> Option<Aabb> {
+        match self.bounds {
+            Some([a, b]) => Some(Aabb::new(a, b)),
+            None => self.shape.bounds(),
+        }
+    }
+
+    /// The outward normal from the gradient of the distance, by the tetrahedron technique
+    fn normal(&self, p: Point) -> Vec3 {
+        let h = 1e-3;
+        [
+            Vec3::new(1., -1., -1.),
+            Vec3::new(-1., -1., 1.),
+            Vec3::new(-1., 1., -1.),
+            Vec3::new(1., 1., 1.),
+        ]
+        .iter()
+        .fold(Vec3::new(0., 0., 0.), |gradient, &k| {
+            gradient + k * self.shape.distance(p + k * h)
+        })
+        .unit_vector()
+    }
+}
+
+impl Hittable for Sdf {
+    fn hit(&self, ray: Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+        let (mut t, t_end) = self.aabb()?.clip(ray, t_min, t_max)?;
+        let speed = ray.direction().length();
+
+        // March towards the surface from whichever side the ray starts on
+        let side = self.shape.distance(ray.at(t)).signum();
+        for step in 0..MAX_STEPS {
+            let distance = side * self.shape.distance(ray.at(t));
+            if distance < SURFACE_DISTANCE && step > 0 {
+                let outward_normal = self.normal(ray.at(t));
+                let material = self.material.material();
+                return Some(HitRecord::facing(ray, t, outward_normal, material));
+            }
+            t += distance.max(SURFACE_DISTANCE) * self.step_scale / speed;
+            if t > t_end {
+                return None;
+            }
+        }
+        None
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.aabb()
+    }
+}
+
+impl ResolveMaterials for Sdf {
+    fn resolve_materials(&mut self, library: &MaterialLibrary) -> Result<()> {
+        self.material.resolve(library)
+    }
+}
+
+impl LoadFiles for Sdf {
+    fn load_files(&mut self, directory: &Path) -> Result<()> {
+        self.material.load_files(directory)
+    }
+}
+
+impl Validate for Sdf {
+    fn validate(&self, path: &str, problems: &mut Problems) {
+        self.shape.validate(&join(path, "shape"), problems);
+        if self.bounds.is_none() && self.shape.bounds().is_none() {
+            problems.error(
+                &join(path, "bounds"),
+                "Repeated shapes are unbounded, so the bounds must be given",
+            );
+        }
+        if self.step_scale <= 0. || self.step_scale > 1. {
+            problems.error(
+                &join(path, "step_scale"),
+                "The step scale must be in (0, 1]",
+            );
+        }
+        self.material.validate(&join(path, "material"), problems);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::{Lambertian, Material, SurfaceMaps};
+
+    fn grey() -> Material {
+        Material::Lambertian(Lambertian {
+            albedo: Vec3::new(0.5, 0.5, 0.5),
+            maps: SurfaceMaps::default(),
+        })
+    }
+
+    #[test]
+    fn test_distances() {
+        let rounded = SdfShape::RoundedBox {
+            size: Vec3::new(1., 1., 1.),
+            radius: 0.25,
+        };
+        assert!((rounded.distance(Vec3::new(2., 0., 0.)) - 1.).abs() < 1e-6);
+        let corner = 0.75 + 0.25 / 3f32.sqrt();
+        assert!(rounded.distance(Vec3::new(corner, corner, corner)).abs() < 1e-6);
+
+        let repeated = SdfShape::Repeat {
+            period: Vec3::new(4., 0., 0.),
+            shape: Box::new(SdfShape::Sphere { radius: 1. }),
+        };
+        assert!((repeated.distance(Vec3::new(8., 0., 0.)) + 1.).abs() < 1e-6);
+        assert!((repeated.distance(Vec3::new(8., 3., 0.)) - 2.).abs() < 1e-6);
+        assert!(repeated.bounds().is_none());
+    }
+
+    #[test]
+    fn test_sphere_tracing() {
+        let shape = SdfShape::Subtract {
+            shapes: vec![
+                SdfShape::Box {
+                    size: Vec3::new(1., 1., 1.),
+                },
+                SdfShape::Sphere { radius: 1.2 },
+            ],
+        };
+        let sdf = Sdf::new(shape, grey());
+
+        // The sphere hollows out the middle of the faces, leaving the corners
+        let ray = Ray::new(Vec3::new(0., 0., 5.), Vec3::new(0., 0., -1.));
+        assert!(sdf.hit(ray, 0.001, f32::MAX).is_none());
+
+        let ray = Ray::new(Vec3::new(0.9, 0.9, 5.), Vec3::new(0., 0., -1.));
+        let record = sdf.hit(ray, 0.001, f32::MAX).unwrap();
+        assert!((record.t() - 4.).abs() < 1e-3);
+        assert!((record.normal() - Vec3::new(0., 0., 1.)).length() < 1e-2);
+        assert!(record.is_front_face());
+    }
+}
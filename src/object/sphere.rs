@@ -1,88 +1,150 @@
+use std::f32::consts::PI;
+use std::path::Path;
+
+use anyhow::Result;
 use serde::{Deserialize, Serialize};
 
-use crate::material::Material;
-use crate::object::{HitRecord, Hittable};
+use crate::aabb::Aabb;
+use crate::material::{Material, MaterialLibrary, MaterialRef};
+use crate::object::{HitRecord, Hittable, LoadFiles, ResolveMaterials};
 use crate::ray::Ray;
+use crate::validate::{join, Problems, Validate};
 use crate::vec3::{Point, Vec3};
 
 #[derive(Debug, PartialEq, Serialize, Deserialize)]
 pub struct Sphere {
     center: Point,
     radius: f32,
-    material: Material,
+    material: MaterialRef,
 }
 
 impl Sphere {
-    pub fn new(center: Point, radius: f32, material: Material) -> Self {
+    pub fn new<M: Into<MaterialRef>>(center: Point, radius: f32, material: M) -> Self {
         Self {
             center,
             radius,
-            material,
+            material: material.into(),
         }
     }
 }
 
 impl Hittable for Sphere {
-    fn hit(&self, ray: Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
-        // Sphere equation:
-        // x² + y² + z² = (x - Cx)² + (y - Cy)² + (z - Cz)²
-        //                 = (P - C) · (P - C)
-        // where P is a point and C is the center of the sphere
-        //
-        // If P is on the sphere, (P - C) · (P - C) = R²
-        //
-        // As P is a point along the ray P(t) where some arbitrary t,
-        // and P(t) = A + tv where A is the origin and v is the ray direction
-        // the LHS of the equation expands to at² + bt + c where
-        // a = (v • v), b = (A - C) • 2v, c = (A - C) • (A - C) - r^2
-        //
-        // Let OC = A - C and h = OC · v
-        // Given that b = 2(OC · v), the quadratic formula
-        // t = (-b ± sqrt(b^2 - 4ac)) / 2a can be simplified to
-        // t = (-h ± sqrt(h^2 - ac)) / a
-
-        let oc = ray.origin() - self.center;
-        // a = v • v = ∥v∥²
-        let a = ray.direction().length_squared();
-        // h = OC • v
-        let h = oc.dot(ray.direction());
-        // c = OC • OC - r² = ∥OC∥² - r²
-        let c = oc.length_squared() - self.radius * self.radius;
-
-        let discriminant = h * h - a * c;
-        if discriminant < 0. {
-            return None;
-        }
+    fn hit(&self, ray: Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+        hit_sphere(self.center, self.radius, &self.material, ray, t_min, t_max)
+    }
 
-        // Real roots, ie. intersection of sphere
-        let sqrt_d = discriminant.sqrt();
-        // Obtain a root using quadratic formula
-        let mut t = (-h - sqrt_d) / a;
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(sphere_box(self.center, self.radius))
+    }
+}
+
+impl ResolveMaterials for Sphere {
+    fn resolve_materials(&mut self, library: &MaterialLibrary) -> Result<()> {
+        self.material.resolve(library)
+    }
+}
+
+impl LoadFiles for Sphere {
+    fn load_files(&mut self, directory: &Path) -> Result<()> {
+        self.material.load_files(directory)
+    }
+}
+
+impl Validate for Sphere {
+    fn validate(&self, path: &str, problems: &mut Problems) {
+        validate_radius(self.radius, &self.material, path, problems);
+        self.material.validate(&join(path, "material"), problems);
+    }
+}
+
+/// Intersect a sphere, shared by the static and moving spheres
+pub(super) fn hit_sphere(
+    center: Point,
+    radius: f32,
+    material: &MaterialRef,
+    ray: Ray,
+    t_min: f32,
+    t_max: f32,
+) -> Option<HitRecord<'_>> {
+    // Sphere equation:
+    // x² + y² + z² = (x - Cx)² + (y - Cy)² + (z - Cz)²
+    //                 = (P - C) · (P - C)
+    // where P is a point and C is the center of the sphere
+    //
+    // If P is on the sphere, (P - C) · (P - C) = R²
+    //
+    // As P is a point along the ray P(t) where some arbitrary t,
+    // and P(t) = A + tv where A is the origin and v is the ray direction
+    // the LHS of the equation expands to at² + bt + c where
+    // a = (v • v), b = (A - C) • 2v, c = (A - C) • (A - C) - r^2
+    //
+    // Let OC = A - C and h = OC · v
+    // Given that b = 2(OC · v), the quadratic formula
+    // t = (-b ± sqrt(b^2 - 4ac)) / 2a can be simplified to
+    // t = (-h ± sqrt(h^2 - ac)) / a
+
+    let oc = ray.origin() - center;
+    // a = v • v = ∥v∥²
+    let a = ray.direction().length_squared();
+    // h = OC • v
+    let h = oc.dot(ray.direction());
+    // c = OC • OC - r² = ∥OC∥² - r²
+    let c = oc.length_squared() - radius * radius;
+
+    let discriminant = h * h - a * c;
+    if discriminant < 0. {
+        return None;
+    }
+
+    // Real roots, ie. intersection of sphere
+    let sqrt_d = discriminant.sqrt();
+    // Obtain a root using quadratic formula
+    let mut t = (-h - sqrt_d) / a;
+    if t < t_min || t_max < t {
+        t = (-h + sqrt_d) / a;
         if t < t_min || t_max < t {
-            t = (-h + sqrt_d) / a;
-            if t < t_min || t_max < t {
-                return None;
-            }
+            return None;
         }
-
-        let point = ray.at(t);
-        let outward_normal = (point - self.center) / self.radius;
-        let front_face = is_front_face(&ray, &outward_normal);
-        let outward_normal = if front_face {
-            outward_normal
-        } else {
-            -outward_normal
-        };
-        Some(HitRecord::new(
-            point,
-            outward_normal,
-            t,
-            front_face,
-            self.material,
-        ))
     }
+
+    let outward_normal = (ray.at(t) - center) / radius;
+    // Longitude and latitude, starting at -x and the south pole
+    let u = ((-outward_normal.z()).atan2(outward_normal.x()) + PI) / (2. * PI);
+    let v = (-outward_normal.y()).clamp(-1., 1.).acos() / PI;
+    // u grows around the y axis and v from the south pole to the north
+    let tangent = Vec3::new(outward_normal.z(), 0., -outward_normal.x());
+    let record = HitRecord::facing(ray, t, outward_normal, material.material());
+    Some(
+        record
+            .with_tangents(tangent, Vec3::new(0., 1., 0.))
+            .with_uv(u, v),
+    )
 }
 
-fn is_front_face(ray: &Ray, outward_normal: &Vec3) -> bool {
-    ray.direction().dot(*outward_normal) < 0.
+/// The box around a sphere, which may have a negative radius
+pub(super) fn sphere_box(center: Point, radius: f32) -> Aabb {
+    let radius = Vec3::new(radius, radius, radius);
+    Aabb::new(center - radius, center + radius)
+}
+
+pub(super) fn validate_radius(
+    radius: f32,
+    material: &MaterialRef,
+    path: &str,
+    problems: &mut Problems,
+) {
+    // Named materials are looked up in the library, unknown names are reported on their own
+    let material = match material {
+        MaterialRef::Inline(material) => Some(material),
+        MaterialRef::Named(name) => problems.material(name),
+    };
+    let is_solid = material.is_some_and(|material| !matches!(material, Material::Dielectric(_)));
+    if radius == 0. {
+        problems.error(&join(path, "radius"), "The radius must not be zero");
+    } else if radius < 0. && is_solid {
+        problems.warning(
+            &join(path, "radius"),
+            "A negative radius turns the sphere inside out, which is only useful for hollow dielectric shells",
+        );
+    }
 }
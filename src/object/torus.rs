@@ -0,0 +1,184 @@
+use std::path::Path;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::aabb::Aabb;
+use crate::material::{MaterialLibrary, MaterialRef};
+use crate::object::disk::disk_box;
+use crate::object::{angle_around_axis, Frame, HitRecord, Hittable, LoadFiles, ResolveMaterials};
+use crate::primitive::roots;
+use crate::ray::Ray;
+use crate::validate::{join, Problems, Validate};
+use crate::vec3::{Point, Vec3};
+
+/// A ring around `center` in the plane facing along `axis`. The tube of radius
+/// `minor_radius` follows a circle of radius `major_radius`.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct Torus {
+    center: Point,
+    axis: Vec3,
+    major_radius: f32,
+    minor_radius: f32,
+    material: MaterialRef,
+}
+
+impl Torus {
+    pub fn new<M: Into<MaterialRef>>(
+        center: Point,
+        axis: Vec3,
+        major_radius: f32,
+        minor_radius: f32,
+        material: M,
+    ) -> Self {
+        Self {
+            center,
+            axis,
+            major_radius,
+            minor_radius,
+            material: material.into(),
+        }
+    }
+}
+
+impl Hittable for Torus {
+    fn hit(&self, ray: Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+        let frame = Frame::new(self.center, self.axis);
+        let (o, d) = frame.ray_to_local(ray);
+        let speed = d.length();
+        let d = d / speed;
+        let (major, minor) = (self.major_radius as f64, self.minor_radius as f64);
+
+        // Skip to just before the bounding sphere, keeping the quartic well conditioned
+        let closest_approach = -o.dot(d);
+        let miss_distance = (o + closest_approach * d).length();
+        let bound = self.major_radius + self.minor_radius;
+        if miss_distance > bound {
+            return None;
+        }
+        let skip = (closest_approach - bound).max(0.);
+        let o = o + skip * d;
+
+        // (|p|² + R² - r²)² = 4R² (x² + y²) along the ray, with |d| = 1
+        let (ox, oy, oz) = (o.x() as f64, o.y() as f64, o.z() as f64);
+        let (dx, dy, dz) = (d.x() as f64, d.y() as f64, d.z() as f64);
+        let g = ox * ox + oy * oy + oz * oz + major * major - minor * minor;
+        let h = ox * dx + oy * dy + oz * dz;
+        let four_r2 = 4. * major * major;
+        let roots = roots::quartic(
+            4. * h,
+            4. * h * h + 2. * g - four_r2 * (dx * dx + dy * dy),
+            4. * h * g - 2. * four_r2 * (ox * dx + oy * dy),
+            g * g - four_r2 * (ox * ox + oy * oy),
+        );
+
+        let t = roots
+            .iter()
+            .map(|&distance| (skip + distance as f32) / speed)
+            .filter(|t| (t_min..=t_max).contains(t))
+            .min_by(f32::total_cmp)?;
+
+        // The normal points away from the nearest point on the center circle
+        let p = o + (t * speed - skip) * d;
+        let distance = (p.x() * p.x() + p.y() * p.y()).sqrt();
+        let ring = if distance > 0. {
+            Vec3::new(p.x(), p.y(), 0.) * (self.major_radius / distance)
+        } else {
+            Vec3::new(0., 0., 0.)
+        };
+        let normal = p - ring;
+
+        // Around the ring, then around the tube
+        let u = angle_around_axis(p);
+        let v = angle_around_axis(Vec3::new(distance - self.major_radius, p.z(), 0.));
+        let outward_normal = frame.vector_to_world(normal).unit_vector();
+        Some(HitRecord::facing(ray, t, outward_normal, self.material.material()).with_uv(u, v))
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let ring = disk_box(self.center, self.axis, self.major_radius);
+        Some(ring.padded(self.minor_radius))
+    }
+}
+
+impl ResolveMaterials for Torus {
+    fn resolve_materials(&mut self, library: &MaterialLibrary) -> Result<()> {
+        self.material.resolve(library)
+    }
+}
+
+impl LoadFiles for Torus {
+    fn load_files(&mut self, directory: &Path) -> Result<()> {
+        self.material.load_files(directory)
+    }
+}
+
+impl Validate for Torus {
+    fn validate(&self, path: &str, problems: &mut Problems) {
+        if self.axis.length_squared() == 0. {
+            problems.error(&join(path, "axis"), "The axis must not be zero");
+        }
+        if self.major_radius <= 0. {
+            problems.error(&join(path, "major_radius"), "The radius must be positive");
+        }
+        if self.minor_radius <= 0. {
+            problems.error(&join(path, "minor_radius"), "The radius must be positive");
+        } else if self.minor_radius > self.major_radius {
+            problems.warning(
+                &join(path, "minor_radius"),
+                "A tube wider than the ring intersects itself around the center",
+            );
+        }
+        self.material.validate(&join(path, "material"), problems);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::{Lambertian, Material, SurfaceMaps};
+
+    fn ring() -> Torus {
+        let material = Material::Lambertian(Lambertian {
+            albedo: Vec3::new(0.5, 0.5, 0.5),
+            maps: SurfaceMaps::default(),
+        });
+        Torus::new(
+            Vec3::new(0., 0., 0.),
+            Vec3::new(0., 1., 0.),
+            2.,
+            0.5,
+            material,
+        )
+    }
+
+    #[test]
+    fn test_hit_through_hole() {
+        // Along the axis, the ray passes through the hole
+        let ring = ring();
+        let ray = Ray::new(Vec3::new(0., 10., 0.), Vec3::new(0., -1., 0.));
+        assert!(ring.hit(ray, 0., f32::MAX).is_none());
+
+        // From far away along x, the outer edge of the tube comes first
+        let ray = Ray::new(Vec3::new(100., 0., 0.), Vec3::new(-1., 0., 0.));
+        let record = ring.hit(ray, 0., f32::MAX).unwrap();
+        assert!((record.t() - 97.5).abs() < 1e-3);
+        assert!((record.normal() - Vec3::new(1., 0., 0.)).length() < 1e-4);
+
+        // Starting inside the hole, the inner edge of the tube
+        let ray = Ray::new(Vec3::new(0., 0., 0.), Vec3::new(1., 0., 0.));
+        let record = ring.hit(ray, 0., f32::MAX).unwrap();
+        assert!((record.t() - 1.5).abs() < 1e-4);
+        assert!((record.normal() - Vec3::new(-1., 0., 0.)).length() < 1e-4);
+    }
+
+    #[test]
+    fn test_hit_from_above() {
+        let ring = ring();
+        let ray = Ray::new(Vec3::new(0., 3., 2.), Vec3::new(0., -2., 0.));
+        let record = ring.hit(ray, 0., f32::MAX).unwrap();
+        assert!((record.t() - 1.25).abs() < 1e-4);
+        assert!((record.point() - Vec3::new(0., 0.5, 2.)).length() < 1e-4);
+        assert!((record.normal() - Vec3::new(0., 1., 0.)).length() < 1e-4);
+    }
+}
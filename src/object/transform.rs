@@ -0,0 +1,168 @@
+use serde::{Deserialize, Serialize};
+
+use crate::aabb::Aabb;
+use crate::validate::{join, Problems, Validate};
+use crate::vec3::{Point, Vec3};
+
+/// A scale, then a rotation, then a translation
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy)]
+pub struct Transform {
+    #[serde(default = "default_translate")]
+    pub translate: Vec3,
+    /// Rotation in degrees around the x, then the y, then the z axis
+    #[serde(default = "default_rotate")]
+    pub rotate: Vec3,
+    #[serde(default = "default_scale")]
+    pub scale: Vec3,
+}
+
+fn default_translate() -> Vec3 {
+    Vec3::new(0., 0., 0.)
+}
+
+fn default_rotate() -> Vec3 {
+    Vec3::new(0., 0., 0.)
+}
+
+fn default_scale() -> Vec3 {
+    Vec3::new(1., 1., 1.)
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self {
+            translate: default_translate(),
+            rotate: default_rotate(),
+            scale: default_scale(),
+        }
+    }
+}
+
+impl Transform {
+    /// Interpolate each of the translation, rotation angles and scale
+    #[must_use]
+    pub fn lerp(self, other: Transform, t: f32) -> Transform {
+        Transform {
+            translate: self.translate.lerp(other.translate, t),
+            rotate: self.rotate.lerp(other.rotate, t),
+            scale: self.scale.lerp(other.scale, t),
+        }
+    }
+
+    /// Move a point from object space into world space
+    pub fn point(self, point: Point) -> Point {
+        self.vector(point) + self.translate
+    }
+
+    /// Move a direction from object space into world space
+    pub fn vector(self, vector: Vec3) -> Vec3 {
+        self.rotated(vector * self.scale)
+    }
+
+    /// Move a point from world space into object space
+    pub fn inverse_point(self, point: Point) -> Point {
+        self.inverse_vector(point - self.translate)
+    }
+
+    /// Move a direction from world space into object space
+    pub fn inverse_vector(self, vector: Vec3) -> Vec3 {
+        self.unrotated(vector) * self.inverse_scale()
+    }
+
+    /// Move a surface normal from object space into world space, which needs the inverse
+    /// transpose so normals stay perpendicular under non-uniform scaling. The result is not
+    /// normalized.
+    pub fn normal(self, normal: Vec3) -> Vec3 {
+        self.rotated(normal * self.inverse_scale())
+    }
+
+    /// The world space box containing the object space box
+    pub fn bounding_box(self, aabb: Aabb) -> Aabb {
+        Aabb::from_points(aabb.corners().map(|corner| self.point(corner))).unwrap()
+    }
+
+    fn inverse_scale(self) -> Vec3 {
+        Vec3::new(
+            1. / self.scale.x(),
+            1. / self.scale.y(),
+            1. / self.scale.z(),
+        )
+    }
+
+    fn rotated(self, vector: Vec3) -> Vec3 {
+        let vector = rotate_x(vector, self.rotate.x());
+        let vector = rotate_y(vector, self.rotate.y());
+        rotate_z(vector, self.rotate.z())
+    }
+
+    fn unrotated(self, vector: Vec3) -> Vec3 {
+        let vector = rotate_z(vector, -self.rotate.z());
+        let vector = rotate_y(vector, -self.rotate.y());
+        rotate_x(vector, -self.rotate.x())
+    }
+}
+
+fn rotate_x(v: Vec3, degrees: f32) -> Vec3 {
+    let (sin, cos) = degrees.to_radians().sin_cos();
+    Vec3::new(v.x(), cos * v.y() - sin * v.z(), sin * v.y() + cos * v.z())
+}
+
+fn rotate_y(v: Vec3, degrees: f32) -> Vec3 {
+    let (sin, cos) = degrees.to_radians().sin_cos();
+    Vec3::new(cos * v.x() + sin * v.z(), v.y(), cos * v.z() - sin * v.x())
+}
+
+fn rotate_z(v: Vec3, degrees: f32) -> Vec3 {
+    let (sin, cos) = degrees.to_radians().sin_cos();
+    Vec3::new(cos * v.x() - sin * v.y(), sin * v.x() + cos * v.y(), v.z())
+}
+
+impl Validate for Transform {
+    fn validate(&self, path: &str, problems: &mut Problems) {
+        if [self.scale.x(), self.scale.y(), self.scale.z()].contains(&0.) {
+            problems.error(
+                &join(path, "scale"),
+                "Scaling by zero flattens the object, so it cannot be hit",
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_near(a: Vec3, b: Vec3) {
+        assert!((a - b).length() < 1e-5, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn test_inverse_undoes_transform() {
+        let transform = Transform {
+            translate: Vec3::new(1., 2., 3.),
+            rotate: Vec3::new(30., -45., 60.),
+            scale: Vec3::new(2., 0.5, 3.),
+        };
+        let point = Vec3::new(0.3, -0.7, 1.1);
+        assert_near(transform.inverse_point(transform.point(point)), point);
+        assert_near(transform.point(transform.inverse_point(point)), point);
+    }
+
+    #[test]
+    fn test_normal_stays_perpendicular() {
+        let transform = Transform {
+            rotate: Vec3::new(0., 0., 90.),
+            scale: Vec3::new(4., 1., 1.),
+            ..Transform::default()
+        };
+        // A surface along x and z, tilted by the non-uniform scale
+        let tangent = Vec3::new(1., 1., 0.);
+        let normal = Vec3::new(1., -1., 0.);
+        let world_tangent = transform.vector(tangent);
+        assert!(world_tangent.dot(transform.normal(normal)).abs() < 1e-5);
+        assert_near(
+            transform.vector(Vec3::new(1., 0., 0.)),
+            Vec3::new(0., 4., 0.),
+        );
+    }
+}
@@ -0,0 +1,284 @@
+use std::path::Path;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::aabb::Aabb;
+use crate::material::{MaterialLibrary, MaterialRef};
+use crate::object::grid::GridSource;
+use crate::object::{HitRecord, Hittable, LoadFiles, ResolveMaterials, Span};
+use crate::primitive::random;
+use crate::ray::Ray;
+use crate::validate::{join, Problems, Validate};
+use crate::vec3::{Color, Point, Vec3};
+
+/// A medium whose density varies, given by a grid filling the box from `min` to `max`, e.g.
+/// clouds and explosions. Place and turn it with an instance, which keeps its optical
+/// thickness when scaled. Rays scatter by the material, which should be a phase function.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct Volume {
+    min: Point,
+    max: Point,
+    density: GridSource,
+    /// Multiplies the grid's values to give the chance of scattering per unit of distance
+    #[serde(default = "default_scale")]
+    density_scale: f32,
+    emission: Option<Emission>,
+    material: MaterialRef,
+}
+
+fn default_scale() -> f32 {
+    1.
+}
+
+/// Light given off by a volume where rays collide with it, so denser parts glow brighter
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub enum Emission {
+    /// Glow in the color of a black body at the grid's temperatures in kelvin, with a
+    /// strength of 1 giving a green channel of 1 at 1500 K
+    Temperature {
+        grid: GridSource,
+        #[serde(default = "default_scale")]
+        strength: f32,
+    },
+    /// Glow in one color, scaled by the grid's values
+    Color { grid: GridSource, color: Color },
+}
+
+impl Emission {
+    fn at(&self, point: Vec3) -> Color {
+        match self {
+            Emission::Temperature { grid, strength } => {
+                *strength * blackbody(grid.grid().sample(point))
+            }
+            Emission::Color { grid, color } => grid.grid().sample(point) * *color,
+        }
+    }
+}
+
+/// The temperature black bodies are normalized to
+const REFERENCE_TEMPERATURE: f32 = 1500.;
+
+/// The color of a black body from Planck's law at red, green and blue wavelengths, relative
+/// to the green of one at the reference temperature
+fn blackbody(temperature: f32) -> Color {
+    if temperature <= 0. {
+        return Color::new(0., 0., 0.);
+    }
+    // The second radiation constant hc/k in meter kelvin
+    const C2: f64 = 1.4388e-2;
+    let radiance = |wavelength: f64, temperature: f64| {
+        wavelength.powi(-5) / ((C2 / (wavelength * temperature)).exp() - 1.)
+    };
+    let reference = radiance(550e-9, REFERENCE_TEMPERATURE as f64);
+    let channel = |wavelength| (radiance(wavelength, temperature as f64) / reference) as f32;
+    Color::new(channel(610e-9), channel(550e-9), channel(465e-9))
+}
+
+impl Volume {
+    /// The largest density, which bounds the density everywhere for tracking
+    fn majorant(&self) -> f32 {
+        self.density.grid().max() * self.density_scale
+    }
+
+    /// The point relative to the box, in the unit cube inside it
+    fn to_grid(&self, point: Point) -> Vec3 {
+        let size = self.max - self.min;
+        let offset = point - self.min;
+        Vec3::new(
+            offset.x() / size.x(),
+            offset.y() / size.y(),
+            offset.z() / size.z(),
+        )
+    }
+
+    fn density_at(&self, point: Point) -> f32 {
+        self.density.grid().sample(self.to_grid(point)) * self.density_scale
+    }
+
+    /// Step through the volume at random distances for a medium as dense as the majorant,
+    /// calling `visit` with the density at each step until it returns false or the ray leaves
+    fn track(&self, ray: Ray, t_min: f32, t_max: f32, mut visit: impl FnMut(f32, f32) -> bool) {
+        let majorant = self.majorant();
+        if majorant <= 0. {
+            return;
+        }
+        let (mut t, exit) = match Aabb::new(self.min, self.max).clip(ray, t_min, t_max) {
+            Some(range) => range,
+            None => return,
+        };
+        let speed = ray.direction().length();
+        loop {
+            t += -(1. - random::gen::<f32>()).ln() / (majorant * speed);
+            if t >= exit || !visit(t, self.density_at(ray.at(t)) / majorant) {
+                return;
+            }
+        }
+    }
+}
+
+impl Hittable for Volume {
+    /// A volume has no surface for rays to hit
+    fn hit(&self, _ray: Ray, _t_min: f32, _t_max: f32) -> Option<HitRecord<'_>> {
+        None
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(Aabb::new(self.min, self.max))
+    }
+
+    /// A volume has no surface to combine with other solids
+    fn spans(&self, _ray: Ray) -> Vec<Span<'_>> {
+        Vec::new()
+    }
+
+    /// Sample where the ray collides with the volume by delta tracking: steps at the majorant
+    /// are real collisions with the chance of the actual density, and otherwise carry on
+    fn collide(&self, ray: Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+        let mut collision = None;
+        self.track(ray, t_min, t_max, |t, fraction| {
+            if random::gen::<f32>() < fraction {
+                collision = Some(t);
+            }
+            collision.is_none()
+        });
+
+        let t = collision?;
+        let point = ray.at(t);
+        // Phase functions ignore the normal, so any will do
+        let normal = Vec3::new(1., 0., 0.);
+        let record = HitRecord::new(point, normal, t, true, self.material.material());
+        Some(match &self.emission {
+            Some(emission) => record.with_emission(emission.at(self.to_grid(point))),
+            None => record,
+        })
+    }
+
+    /// How much light passes through the volume along the ray, estimated by ratio tracking
+    /// without ever stopping at a collision
+    fn transmittance(&self, ray: Ray, t_min: f32, t_max: f32) -> f32 {
+        let mut transmittance = 1.;
+        self.track(ray, t_min, t_max, |_, fraction| {
+            transmittance *= 1. - fraction;
+            true
+        });
+        transmittance
+    }
+}
+
+impl ResolveMaterials for Volume {
+    fn resolve_materials(&mut self, library: &MaterialLibrary) -> Result<()> {
+        self.material.resolve(library)
+    }
+}
+
+impl LoadFiles for Volume {
+    fn load_files(&mut self, directory: &Path) -> Result<()> {
+        self.material.load_files(directory)?;
+        self.density.load_files(directory)?;
+        match &mut self.emission {
+            Some(Emission::Temperature { grid, .. } | Emission::Color { grid, .. }) => {
+                grid.load_files(directory)
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+impl Validate for Volume {
+    fn validate(&self, path: &str, problems: &mut Problems) {
+        let size = self.max - self.min;
+        if size.x() <= 0. || size.y() <= 0. || size.z() <= 0. {
+            problems.error(
+                &join(path, "max"),
+                "Every coordinate of max must be larger than that of min",
+            );
+        }
+        if self.density_scale < 0. {
+            problems.error(
+                &join(path, "density_scale"),
+                "The density must not be negative",
+            );
+        }
+        self.density.validate(&join(path, "density"), problems);
+        match &self.emission {
+            Some(Emission::Temperature { grid, strength }) => {
+                let path = join(path, "emission.Temperature");
+                if *strength < 0. {
+                    problems.error(
+                        &join(&path, "strength"),
+                        "The strength must not be negative",
+                    );
+                }
+                grid.validate(&join(&path, "grid"), problems);
+            }
+            Some(Emission::Color { grid, .. }) => {
+                grid.validate(&join(path, "emission.Color.grid"), problems);
+            }
+            None => {}
+        }
+        self.material.validate(&join(path, "material"), problems);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::{Isotropic, Material};
+    use crate::object::grid::Grid;
+    use std::sync::Arc;
+
+    /// A unit cube with density 0 in its left half and 2 in its right half
+    fn half_filled() -> Volume {
+        let grid = Grid::new([2, 1, 1], vec![0., 2.]).unwrap();
+        Volume {
+            min: Vec3::new(0., 0., 0.),
+            max: Vec3::new(1., 1., 1.),
+            density: GridSource::File {
+                path: "half.json".into(),
+                resolution: None,
+                grid: Some(Arc::new(grid)),
+            },
+            density_scale: 1.,
+            emission: None,
+            material: Material::Isotropic(Isotropic {
+                albedo: Vec3::new(1., 1., 1.),
+            })
+            .into(),
+        }
+    }
+
+    #[test]
+    fn test_tracking_matches_density() {
+        random::seed(5);
+        let volume = half_filled();
+        let samples = 10000;
+
+        // Through the dense side at density 2 for 1 unit of distance
+        let ray = Ray::new(Vec3::new(0.9, 0.5, -1.), Vec3::new(0., 0., 1.));
+        let expected = (-2f32).exp();
+        let passed = (0..samples)
+            .filter(|_| volume.collide(ray, 0., f32::MAX).is_none())
+            .count();
+        assert!((passed as f32 / samples as f32 - expected).abs() < 0.02);
+        let transmittance = (0..samples)
+            .map(|_| volume.transmittance(ray, 0., f32::MAX))
+            .sum::<f32>();
+        assert!((transmittance / samples as f32 - expected).abs() < 0.02);
+
+        // Through the empty side, where nothing collides
+        let ray = Ray::new(Vec3::new(0.1, 0.5, -1.), Vec3::new(0., 0., 1.));
+        assert!((0..100).all(|_| volume.collide(ray, 0., f32::MAX).is_none()));
+    }
+
+    #[test]
+    fn test_blackbody() {
+        let warm = blackbody(REFERENCE_TEMPERATURE);
+        assert!((warm.y() - 1.).abs() < 1e-5);
+        assert!(warm.x() > warm.y() && warm.y() > warm.z());
+        // Hotter bodies are brighter and bluer
+        let hot = blackbody(6000.);
+        assert!(hot.y() > warm.y());
+        assert!(hot.z() / hot.x() > warm.z() / warm.x());
+    }
+}
@@ -35,10 +35,14 @@ impl Chunk {
         self.length
     }
 
-    fn _chunk_type(&self) -> &ChunkType {
+    pub fn _chunk_type(&self) -> &ChunkType {
         &self.chunk_type
     }
 
+    pub fn data(&self) -> &[u8] {
+        &self.message_bytes
+    }
+
     fn _data_as_string(&self) -> Result<String> {
         Ok(String::from_utf8(self.message_bytes.clone())?)
     }
@@ -0,0 +1,216 @@
+use std::convert::TryFrom;
+use std::io::Read;
+
+use anyhow::{anyhow, Result};
+use flate2::read::ZlibDecoder;
+
+use crate::png::Chunk;
+use crate::vec3::Color;
+
+const SIGNATURE: [u8; 8] = [137, 80, 78, 71, 13, 10, 26, 10];
+
+struct Header {
+    width: usize,
+    height: usize,
+    bit_depth: u8,
+    color_type: u8,
+}
+
+impl Header {
+    fn channels(&self) -> usize {
+        match self.color_type {
+            2 => 3, // RGB
+            4 => 2, // Grayscale and alpha
+            6 => 4, // RGBA
+            _ => 1, // Grayscale or palette index
+        }
+    }
+
+    /// The bytes of a whole pixel, or 1 for pixels smaller than a byte, as used by the filters
+    fn filter_width(&self) -> usize {
+        (self.channels() * self.bit_depth as usize / 8).max(1)
+    }
+
+    fn row_bytes(&self) -> usize {
+        (self.width * self.channels() * self.bit_depth as usize).div_ceil(8)
+    }
+}
+
+/// Decode a non-interlaced PNG into its width, height and colors, row by row from the top left.
+/// The colors keep their encoded (gamma corrected) values in [0, 1] and alpha is dropped.
+pub fn decode(bytes: &[u8]) -> Result<(usize, usize, Vec<Color>)> {
+    if !bytes.starts_with(&SIGNATURE) {
+        return Err(anyhow!("Not a PNG file"));
+    }
+
+    let mut header = None;
+    let mut palette = Vec::new();
+    let mut compressed = Vec::new();
+    let mut position = SIGNATURE.len();
+    while position < bytes.len() {
+        let chunk = Chunk::try_from(&bytes[position..])?;
+        position += 12 + chunk.data().len();
+        let data = chunk.data();
+        match chunk._chunk_type().bytes() {
+            b"IHDR" => {
+                if data.len() < 13 {
+                    return Err(anyhow!("Truncated PNG header"));
+                }
+                if data[12] != 0 {
+                    return Err(anyhow!("Interlaced PNGs are not supported"));
+                }
+                header = Some(Header {
+                    width: u32::from_be_bytes([data[0], data[1], data[2], data[3]]) as usize,
+                    height: u32::from_be_bytes([data[4], data[5], data[6], data[7]]) as usize,
+                    bit_depth: data[8],
+                    color_type: data[9],
+                });
+            }
+            b"PLTE" => palette = data.to_vec(),
+            b"IDAT" => compressed.extend_from_slice(data),
+            b"IEND" => break,
+            _ => {}
+        }
+    }
+    let header = header.ok_or_else(|| anyhow!("The PNG has no header"))?;
+    if !matches!(header.bit_depth, 1 | 2 | 4 | 8 | 16)
+        || !matches!(header.color_type, 0 | 2 | 3 | 4 | 6)
+    {
+        return Err(anyhow!(
+            "Unsupported PNG bit depth {} with color type {}",
+            header.bit_depth,
+            header.color_type
+        ));
+    }
+
+    let mut filtered = Vec::new();
+    ZlibDecoder::new(compressed.as_slice()).read_to_end(&mut filtered)?;
+    let rows = unfilter(&header, &filtered)?;
+
+    let mut colors = Vec::with_capacity(header.width * header.height);
+    for row in rows.chunks(header.row_bytes()) {
+        let samples = samples(&header, row);
+        for pixel in samples.chunks(header.channels()).take(header.width) {
+            let max = ((1_u32 << header.bit_depth) - 1) as f32;
+            let color = match header.color_type {
+                0 | 4 => {
+                    let gray = pixel[0] as f32 / max;
+                    Color::new(gray, gray, gray)
+                }
+                3 => {
+                    let index = pixel[0] as usize * 3;
+                    let entry = palette
+                        .get(index..index + 3)
+                        .ok_or_else(|| anyhow!("PNG palette index out of range"))?;
+                    Color::new(
+                        entry[0] as f32 / 255.,
+                        entry[1] as f32 / 255.,
+                        entry[2] as f32 / 255.,
+                    )
+                }
+                _ => Color::new(
+                    pixel[0] as f32 / max,
+                    pixel[1] as f32 / max,
+                    pixel[2] as f32 / max,
+                ),
+            };
+            colors.push(color);
+        }
+    }
+    Ok((header.width, header.height, colors))
+}
+
+/// Undo the filter of every scanline, returning the raw rows without their filter bytes
+fn unfilter(header: &Header, filtered: &[u8]) -> Result<Vec<u8>> {
+    let row_bytes = header.row_bytes();
+    if filtered.len() < (row_bytes + 1) * header.height {
+        return Err(anyhow!("Truncated PNG image data"));
+    }
+
+    let bpp = header.filter_width();
+    let mut rows = vec![0_u8; row_bytes * header.height];
+    for y in 0..header.height {
+        let line = &filtered[y * (row_bytes + 1)..(y + 1) * (row_bytes + 1)];
+        let (filter, line) = (line[0], &line[1..]);
+        let (previous, current) = rows.split_at_mut(y * row_bytes);
+        let above = if y == 0 {
+            None
+        } else {
+            Some(&previous[(y - 1) * row_bytes..])
+        };
+        let current = &mut current[..row_bytes];
+
+        for x in 0..row_bytes {
+            let a = if x >= bpp { current[x - bpp] } else { 0 };
+            let b = above.map_or(0, |above| above[x]);
+            let c = match above {
+                Some(above) if x >= bpp => above[x - bpp],
+                _ => 0,
+            };
+            let predictor = match filter {
+                0 => 0,
+                1 => a,
+                2 => b,
+                3 => ((a as u16 + b as u16) / 2) as u8,
+                4 => paeth(a, b, c),
+                _ => return Err(anyhow!("Unknown PNG filter type {}", filter)),
+            };
+            current[x] = line[x].wrapping_add(predictor);
+        }
+    }
+    Ok(rows)
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let pa = (p - a as i16).abs();
+    let pb = (p - b as i16).abs();
+    let pc = (p - c as i16).abs();
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+/// Split a raw row into its samples
+fn samples(header: &Header, row: &[u8]) -> Vec<u32> {
+    match header.bit_depth {
+        8 => row.iter().map(|&byte| byte as u32).collect(),
+        16 => row
+            .chunks(2)
+            .map(|pair| u16::from_be_bytes([pair[0], pair[1]]) as u32)
+            .collect(),
+        depth => {
+            let per_byte = 8 / depth;
+            let mask = (1_u8 << depth) - 1;
+            row.iter()
+                .flat_map(|&byte| {
+                    (0..per_byte).map(move |i| ((byte >> (8 - depth * (i + 1))) & mask) as u32)
+                })
+                .collect()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_reference_image() {
+        let (width, height, colors) = decode(include_bytes!("image.png")).unwrap();
+        // Written by another encoder, so it exercises all the scanline filters
+        assert_eq!((width, height), (400, 225));
+        assert_eq!(colors.len(), width * height);
+    }
+
+    #[test]
+    fn test_paeth() {
+        assert_eq!(paeth(10, 20, 10), 20);
+        assert_eq!(paeth(20, 10, 10), 20);
+        assert_eq!(paeth(10, 10, 20), 10);
+    }
+}
@@ -1,6 +1,8 @@
 mod chunk;
 mod chunk_type;
+mod decode;
 mod utils;
 
 pub use chunk::Chunk;
 pub use chunk_type::ChunkType;
+pub use decode::decode;
@@ -0,0 +1,142 @@
+use super::ray::Ray;
+use super::vec3::{Point, Vec3};
+
+/// An axis-aligned bounding box
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Aabb {
+    min: Point,
+    max: Point,
+}
+
+impl Aabb {
+    /// The box spanned by two opposite corners
+    pub fn new(a: Point, b: Point) -> Self {
+        Self {
+            min: a.min(b),
+            max: a.max(b),
+        }
+    }
+
+    /// The smallest box containing all points
+    pub fn from_points<I: IntoIterator<Item = Point>>(points: I) -> Option<Self> {
+        points
+            .into_iter()
+            .map(|point| Aabb::new(point, point))
+            .reduce(Aabb::surrounding)
+    }
+
+    pub fn min(self) -> Point {
+        self.min
+    }
+
+    pub fn max(self) -> Point {
+        self.max
+    }
+
+    pub fn center(self) -> Point {
+        (self.min + self.max) / 2.
+    }
+
+    /// The area of the box's six sides
+    pub fn surface_area(self) -> f32 {
+        let size = self.max - self.min;
+        2. * (size.x() * size.y() + size.y() * size.z() + size.z() * size.x())
+    }
+
+    /// The smallest box containing both boxes
+    #[must_use]
+    pub fn surrounding(self, other: Aabb) -> Aabb {
+        Aabb {
+            min: self.min.min(other.min),
+            max: self.max.max(other.max),
+        }
+    }
+
+    /// The box containing only what is inside both boxes, which is empty if they are apart
+    #[must_use]
+    pub fn intersection(self, other: Aabb) -> Aabb {
+        Aabb {
+            min: self.min.max(other.min),
+            max: self.max.min(other.max),
+        }
+    }
+
+    /// The box grown by a margin on every side
+    #[must_use]
+    pub fn padded(self, margin: f32) -> Aabb {
+        let margin = Vec3::new(margin, margin, margin);
+        Aabb {
+            min: self.min - margin,
+            max: self.max + margin,
+        }
+    }
+
+    /// The eight corners of the box
+    pub fn corners(self) -> [Point; 8] {
+        let (a, b) = (self.min, self.max);
+        [
+            Vec3::new(a.x(), a.y(), a.z()),
+            Vec3::new(b.x(), a.y(), a.z()),
+            Vec3::new(a.x(), b.y(), a.z()),
+            Vec3::new(b.x(), b.y(), a.z()),
+            Vec3::new(a.x(), a.y(), b.z()),
+            Vec3::new(b.x(), a.y(), b.z()),
+            Vec3::new(a.x(), b.y(), b.z()),
+            Vec3::new(b.x(), b.y(), b.z()),
+        ]
+    }
+
+    /// Whether the ray passes through the box between t_min and t_max, using the slab method
+    pub fn hit(self, ray: Ray, t_min: f32, t_max: f32) -> bool {
+        self.clip(ray, t_min, t_max).is_some()
+    }
+
+    /// The part of the range between t_min and t_max where the ray is inside the box
+    pub fn clip(self, ray: Ray, mut t_min: f32, mut t_max: f32) -> Option<(f32, f32)> {
+        let origin = ray.origin();
+        let direction = ray.direction();
+        let slabs = [
+            (origin.x(), direction.x(), self.min.x(), self.max.x()),
+            (origin.y(), direction.y(), self.min.y(), self.max.y()),
+            (origin.z(), direction.z(), self.min.z(), self.max.z()),
+        ];
+        for (origin, direction, min, max) in slabs {
+            let inverse = 1. / direction;
+            let mut t0 = (min - origin) * inverse;
+            let mut t1 = (max - origin) * inverse;
+            if inverse < 0. {
+                std::mem::swap(&mut t0, &mut t1);
+            }
+            // NaNs from rays in the plane of a slab leave the bounds untouched
+            if t0 > t_min {
+                t_min = t0;
+            }
+            if t1 < t_max {
+                t_max = t1;
+            }
+            if t_max < t_min {
+                return None;
+            }
+        }
+        Some((t_min, t_max))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hit() {
+        let aabb = Aabb::new(Vec3::new(1., 1., 1.), Vec3::new(-1., -1., -1.));
+        let towards = Ray::new(Vec3::new(0., 0., 5.), Vec3::new(0., 0., -1.));
+        assert!(aabb.hit(towards, 0., f32::MAX));
+        assert!(!aabb.hit(towards, 0., 3.));
+
+        let away = Ray::new(Vec3::new(0., 0., 5.), Vec3::new(0., 0., 1.));
+        assert!(!aabb.hit(away, 0., f32::MAX));
+
+        let along_face = Ray::new(Vec3::new(0., 2., 5.), Vec3::new(0., 0., -1.));
+        assert!(!aabb.hit(along_face, 0., f32::MAX));
+    }
+}
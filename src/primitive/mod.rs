@@ -1,2 +1,5 @@
+pub mod aabb;
+pub mod random;
 pub mod ray;
+pub mod roots;
 pub mod vec3;
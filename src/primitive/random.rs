@@ -0,0 +1,33 @@
+use std::cell::RefCell;
+
+use rand::distributions::uniform::{SampleRange, SampleUniform};
+use rand::distributions::{Distribution, Standard};
+use rand::rngs::SmallRng;
+use rand::{Rng, SeedableRng};
+
+thread_local! {
+    static RNG: RefCell<SmallRng> = RefCell::new(SmallRng::from_entropy());
+}
+
+/// Reseed the random number generator of the current thread
+pub fn seed(seed: u64) {
+    RNG.with(|rng| *rng.borrow_mut() = SmallRng::seed_from_u64(seed));
+}
+
+/// Reseed the random number generator of the current thread from the OS
+pub fn seed_from_entropy() {
+    RNG.with(|rng| *rng.borrow_mut() = SmallRng::from_entropy());
+}
+
+/// A random value from the standard distribution, e.g. [0, 1) for floats
+pub fn gen<T>() -> T
+where
+    Standard: Distribution<T>,
+{
+    RNG.with(|rng| rng.borrow_mut().gen())
+}
+
+/// A random value within the range
+pub fn gen_range<T: SampleUniform, R: SampleRange<T>>(range: R) -> T {
+    RNG.with(|rng| rng.borrow_mut().gen_range(range))
+}
@@ -4,11 +4,22 @@ use super::vec3::{Point, Vec3};
 pub struct Ray {
     origin: Point,
     direction: Vec3,
+    time: f32,
 }
 
 impl Ray {
     pub fn new(origin: Point, direction: Vec3) -> Self {
-        Self { origin, direction }
+        Self {
+            origin,
+            direction,
+            time: 0.,
+        }
+    }
+
+    /// The same ray, sent at another moment while the shutter is open
+    #[must_use]
+    pub fn with_time(self, time: f32) -> Self {
+        Self { time, ..self }
     }
 
     pub fn origin(self) -> Point {
@@ -19,6 +30,10 @@ impl Ray {
         self.direction
     }
 
+    pub fn time(self) -> f32 {
+        self.time
+    }
+
     /// Get the point along the vector at a certain param t
     pub fn at(self, t: f32) -> Point {
         self.origin + t * self.direction
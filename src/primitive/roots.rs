@@ -0,0 +1,191 @@
+//! Real roots of polynomials up to the fourth degree, for intersecting curved surfaces
+
+use std::f64::consts::PI;
+use std::ops::Deref;
+
+/// Coefficients closer to zero than this are treated as zero
+const EPSILON: f64 = 1e-12;
+
+/// Up to four real roots, in no particular order
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Roots {
+    values: [f64; 4],
+    len: usize,
+}
+
+impl Roots {
+    fn push(&mut self, root: f64) {
+        self.values[self.len] = root;
+        self.len += 1;
+    }
+
+    fn map(mut self, f: impl Fn(f64) -> f64) -> Self {
+        for root in &mut self.values[..self.len] {
+            *root = f(*root);
+        }
+        self
+    }
+}
+
+impl Deref for Roots {
+    type Target = [f64];
+
+    fn deref(&self) -> &[f64] {
+        &self.values[..self.len]
+    }
+}
+
+fn is_zero(x: f64) -> bool {
+    x.abs() < EPSILON
+}
+
+/// Roots of a x² + b x + c, falling back to the linear equation when a is zero
+pub fn quadratic(a: f64, b: f64, c: f64) -> Roots {
+    let mut roots = Roots::default();
+    if is_zero(a) {
+        if !is_zero(b) {
+            roots.push(-c / b);
+        }
+        return roots;
+    }
+
+    let discriminant = b * b - 4. * a * c;
+    if discriminant < 0. {
+        return roots;
+    }
+    // Avoid subtracting nearly equal numbers for the root closer to zero
+    let q = -0.5 * (b + b.signum() * discriminant.sqrt());
+    roots.push(q / a);
+    if !is_zero(q) {
+        roots.push(c / q);
+    } else {
+        roots.push(q / a);
+    }
+    roots
+}
+
+/// Roots of x³ + a x² + b x + c
+pub fn cubic(a: f64, b: f64, c: f64) -> Roots {
+    // Substitute x = y - a/3 for y³ + 3p y + 2q
+    let sub = a / 3.;
+    let p = (b - a * sub) / 3.;
+    let q = (2. * sub * sub * sub - sub * b + c) / 2.;
+    let discriminant = q * q + p * p * p;
+
+    let mut roots = Roots::default();
+    if is_zero(discriminant) {
+        if is_zero(q) {
+            roots.push(0.);
+        } else {
+            let u = (-q).cbrt();
+            roots.push(2. * u);
+            roots.push(-u);
+        }
+    } else if discriminant < 0. {
+        // Three real roots
+        let phi = (-q / (-p * p * p).sqrt()).clamp(-1., 1.).acos() / 3.;
+        let t = 2. * (-p).sqrt();
+        roots.push(t * phi.cos());
+        roots.push(-t * (phi + PI / 3.).cos());
+        roots.push(-t * (phi - PI / 3.).cos());
+    } else {
+        let sqrt_discriminant = discriminant.sqrt();
+        roots.push((sqrt_discriminant - q).cbrt() - (sqrt_discriminant + q).cbrt());
+    }
+    roots.map(|y| y - sub)
+}
+
+/// Roots of x⁴ + a x³ + b x² + c x + d, polished with Newton's method
+pub fn quartic(a: f64, b: f64, c: f64, d: f64) -> Roots {
+    // Substitute x = y - a/4 for y⁴ + p y² + q y + r
+    let sub = a / 4.;
+    let a2 = a * a;
+    let p = b - 3. / 8. * a2;
+    let q = a2 * a / 8. - a * b / 2. + c;
+    let r = -3. / 256. * a2 * a2 + a2 * b / 16. - a * c / 4. + d;
+
+    let mut roots = Roots::default();
+    if is_zero(r) {
+        // y (y³ + p y + q) = 0
+        roots.push(0.);
+        for &y in cubic(0., p, q).iter() {
+            roots.push(y);
+        }
+    } else {
+        // Split into two quadratics with the largest root of the resolvent cubic, which has
+        // both square roots real whenever the quartic has real roots
+        let z = cubic(-p / 2., -r, r * p / 2. - q * q / 8.)
+            .iter()
+            .fold(f64::MIN, |largest, &z| largest.max(z));
+        let u = z * z - r;
+        let v = 2. * z - p;
+        if u < -EPSILON || v < -EPSILON {
+            return roots;
+        }
+        let u = u.max(0.).sqrt();
+        let v = if q < 0. {
+            -v.max(0.).sqrt()
+        } else {
+            v.max(0.).sqrt()
+        };
+        for &y in quadratic(1., v, z - u)
+            .iter()
+            .chain(quadratic(1., -v, z + u).iter())
+        {
+            roots.push(y);
+        }
+    }
+
+    let polynomial = |x: f64| (((x + a) * x + b) * x + c) * x + d;
+    let derivative = |x: f64| ((4. * x + 3. * a) * x + 2. * b) * x + c;
+    roots.map(|y| {
+        let mut x = y - sub;
+        for _ in 0..2 {
+            let slope = derivative(x);
+            if !is_zero(slope) {
+                x -= polynomial(x) / slope;
+            }
+        }
+        x
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sorted(roots: Roots) -> Vec<f64> {
+        let mut roots = roots.to_vec();
+        roots.sort_by(f64::total_cmp);
+        roots.dedup_by(|a, b| (*a - *b).abs() < 1e-6);
+        roots
+    }
+
+    fn assert_roots(roots: Roots, expected: &[f64]) {
+        let roots = sorted(roots);
+        assert_eq!(roots.len(), expected.len(), "{:?}", roots);
+        for (root, expected) in roots.iter().zip(expected) {
+            assert!((root - expected).abs() < 1e-6, "{} != {}", root, expected);
+        }
+    }
+
+    #[test]
+    fn test_cubic() {
+        // (x - 1)(x - 2)(x + 3) = x³ - 7x + 6
+        assert_roots(cubic(0., -7., 6.), &[-3., 1., 2.]);
+        // (x - 2)(x² + 1) = x³ - 2x² + x - 2
+        assert_roots(cubic(-2., 1., -2.), &[2.]);
+    }
+
+    #[test]
+    fn test_quartic() {
+        // (x - 1)(x - 2)(x - 3)(x - 4)
+        assert_roots(quartic(-10., 35., -50., 24.), &[1., 2., 3., 4.]);
+        // (x² + 1)(x² - 4)
+        assert_roots(quartic(0., -3., 0., -4.), &[-2., 2.]);
+        // (x² + 1)(x² + 4) has no real roots
+        assert_roots(quartic(0., 5., 0., 4.), &[]);
+        // (x - 1)²(x - 2)(x + 4), whose resolvent cubic has a double root above its single one
+        assert_roots(quartic(0., -11., 18., -8.), &[-4., 1., 2.]);
+    }
+}
@@ -1,8 +1,7 @@
 use serde::{Deserialize, Serialize};
 use std::ops::{Add, AddAssign, Div, Mul, MulAssign, Neg, Sub};
 
-use rand::rngs::SmallRng;
-use rand::{Rng, SeedableRng};
+use super::random;
 
 pub type Point = Vec3;
 pub type Color = Vec3;
@@ -118,21 +117,19 @@ impl Vec3 {
 
     /// Create a new Vec3 with random coordinates
     pub fn new_random_range(_min: f32, _max: f32) -> Self {
-        let mut rng = SmallRng::from_entropy();
         Self {
-            x: rng.gen_range(_min.._max),
-            y: rng.gen_range(_min.._max),
-            z: rng.gen_range(_min.._max),
+            x: random::gen_range(_min.._max),
+            y: random::gen_range(_min.._max),
+            z: random::gen_range(_min.._max),
         }
     }
 
     /// Create a new Vec3 with random coordinates
     pub fn new_random() -> Self {
-        let mut rng = SmallRng::from_entropy();
         Self {
-            x: rng.gen::<f32>(),
-            y: rng.gen::<f32>(),
-            z: rng.gen::<f32>(),
+            x: random::gen::<f32>(),
+            y: random::gen::<f32>(),
+            z: random::gen::<f32>(),
         }
     }
 
@@ -150,9 +147,12 @@ impl Vec3 {
     /// Create a new Vec3 with random coordinates
     pub fn new_random_in_unit_disk() -> Self {
         let mut p;
-        let mut rng = SmallRng::from_entropy();
         loop {
-            p = Vec3::new(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0), 0.);
+            p = Vec3::new(
+                random::gen_range(-1.0..1.0),
+                random::gen_range(-1.0..1.0),
+                0.,
+            );
             if p.length_squared() < 1. {
                 return p;
             }
@@ -225,6 +225,41 @@ impl Vec3 {
         self.z
     }
 
+    /// The component along an axis, 0 for x, 1 for y and 2 for z
+    pub fn axis(self, axis: usize) -> f32 {
+        match axis {
+            0 => self.x,
+            1 => self.y,
+            _ => self.z,
+        }
+    }
+
+    /// The smallest of each component
+    #[must_use]
+    pub fn min(self, other: Vec3) -> Vec3 {
+        Vec3::new(
+            self.x.min(other.x),
+            self.y.min(other.y),
+            self.z.min(other.z),
+        )
+    }
+
+    /// The largest of each component
+    #[must_use]
+    pub fn max(self, other: Vec3) -> Vec3 {
+        Vec3::new(
+            self.x.max(other.x),
+            self.y.max(other.y),
+            self.z.max(other.z),
+        )
+    }
+
+    /// Linear interpolation towards the other vector, reaching it at t = 1
+    #[must_use]
+    pub fn lerp(self, other: Vec3, t: f32) -> Vec3 {
+        (1. - t) * self + t * other
+    }
+
     #[must_use]
     pub fn reflect(self, normal: Vec3) -> Vec3 {
         self - (normal * 2. * self.dot(normal))
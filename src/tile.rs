@@ -0,0 +1,210 @@
+use serde::{Deserialize, Serialize};
+
+/// The order in which tiles are handed out to the render threads.
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Clone, Copy)]
+pub enum TileOrder {
+    /// Left to right, top to bottom
+    #[default]
+    Scanline,
+    /// Outwards from the center of the region
+    Spiral,
+    /// Along a Hilbert curve, keeping consecutive tiles adjacent
+    Hilbert,
+}
+
+/// A rectangle of pixels, with (0, 0) at the top left of the image.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy)]
+pub struct Tile {
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+}
+
+impl Tile {
+    pub fn new(x: i32, y: i32, width: i32, height: i32) -> Self {
+        Self {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    /// Number of pixels in the tile
+    pub fn area(self) -> usize {
+        (self.width.max(0) * self.height.max(0)) as usize
+    }
+
+    /// The part of the tile that lies within the other tile
+    #[must_use]
+    pub fn intersect(self, other: Tile) -> Tile {
+        let x = self.x.max(other.x);
+        let y = self.y.max(other.y);
+        let right = (self.x + self.width).min(other.x + other.width);
+        let bottom = (self.y + self.height).min(other.y + other.height);
+        Tile::new(x, y, (right - x).max(0), (bottom - y).max(0))
+    }
+}
+
+/// The part of the frame to render.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy)]
+pub struct Region {
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+    /// Output only the region instead of embedding it in the full frame
+    #[serde(default)]
+    pub crop: bool,
+}
+
+impl Region {
+    pub fn tile(self) -> Tile {
+        Tile::new(self.x, self.y, self.width, self.height)
+    }
+}
+
+/// Split the area into tiles of at most `tile_size` x `tile_size` pixels, in the given order.
+pub fn tiles(area: Tile, tile_size: i32, order: TileOrder) -> Vec<Tile> {
+    let tile_size = tile_size.max(1);
+    let columns = (area.width + tile_size - 1) / tile_size;
+    let rows = (area.height + tile_size - 1) / tile_size;
+    if columns <= 0 || rows <= 0 {
+        return Vec::new();
+    }
+
+    let to_tile = |(column, row): (i32, i32)| {
+        Tile::new(
+            area.x + column * tile_size,
+            area.y + row * tile_size,
+            tile_size,
+            tile_size,
+        )
+        .intersect(area)
+    };
+
+    let cells = match order {
+        TileOrder::Scanline => scanline_cells(columns, rows),
+        TileOrder::Spiral => spiral_cells(columns, rows),
+        TileOrder::Hilbert => hilbert_cells(columns, rows),
+    };
+    cells.into_iter().map(to_tile).collect()
+}
+
+fn scanline_cells(columns: i32, rows: i32) -> Vec<(i32, i32)> {
+    (0..rows)
+        .flat_map(|row| (0..columns).map(move |column| (column, row)))
+        .collect()
+}
+
+fn spiral_cells(columns: i32, rows: i32) -> Vec<(i32, i32)> {
+    let total = (columns * rows) as usize;
+    let mut cells = Vec::with_capacity(total);
+    let (mut column, mut row) = ((columns - 1) / 2, (rows - 1) / 2);
+    let directions = [(1, 0), (0, 1), (-1, 0), (0, -1)];
+
+    // Walk right 1, down 1, left 2, up 2, right 3, ... keeping the cells inside the grid
+    let mut step = 1;
+    let mut direction = 0;
+    cells.push((column, row));
+    while cells.len() < total {
+        for _ in 0..2 {
+            let (dx, dy) = directions[direction % 4];
+            for _ in 0..step {
+                column += dx;
+                row += dy;
+                if (0..columns).contains(&column) && (0..rows).contains(&row) {
+                    cells.push((column, row));
+                }
+            }
+            direction += 1;
+        }
+        step += 1;
+    }
+    cells
+}
+
+fn hilbert_cells(columns: i32, rows: i32) -> Vec<(i32, i32)> {
+    let mut side = 1;
+    while side < columns.max(rows) {
+        side *= 2;
+    }
+
+    (0..side * side)
+        .map(|d| hilbert_d2xy(side, d))
+        .filter(|&(column, row)| column < columns && row < rows)
+        .collect()
+}
+
+// Convert a distance along the Hilbert curve to a cell in a `side` x `side` grid
+// Retrieved from: https://en.wikipedia.org/wiki/Hilbert_curve
+fn hilbert_d2xy(side: i32, d: i32) -> (i32, i32) {
+    let (mut x, mut y) = (0, 0);
+    let mut t = d;
+    let mut s = 1;
+    while s < side {
+        let rx = 1 & (t / 2);
+        let ry = 1 & (t ^ rx);
+        if ry == 0 {
+            if rx == 1 {
+                x = s - 1 - x;
+                y = s - 1 - y;
+            }
+            std::mem::swap(&mut x, &mut y);
+        }
+        x += s * rx;
+        y += s * ry;
+        t /= 4;
+        s *= 2;
+    }
+    (x, y)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_covers(area: Tile, tiles: &[Tile]) {
+        let mut covered = vec![0; area.area()];
+        for tile in tiles {
+            for y in tile.y..tile.y + tile.height {
+                for x in tile.x..tile.x + tile.width {
+                    covered[((y - area.y) * area.width + (x - area.x)) as usize] += 1;
+                }
+            }
+        }
+        assert!(covered.iter().all(|&count| count == 1));
+    }
+
+    #[test]
+    fn test_tiles_cover_area_once() {
+        let area = Tile::new(3, 5, 101, 37);
+        for order in [TileOrder::Scanline, TileOrder::Spiral, TileOrder::Hilbert] {
+            assert_covers(area, &tiles(area, 16, order));
+        }
+    }
+
+    #[test]
+    fn test_spiral_starts_at_center() {
+        let area = Tile::new(0, 0, 50, 50);
+        let tiles = tiles(area, 10, TileOrder::Spiral);
+        assert_eq!(tiles[0], Tile::new(20, 20, 10, 10));
+    }
+
+    #[test]
+    fn test_hilbert_tiles_are_adjacent() {
+        let area = Tile::new(0, 0, 64, 64);
+        let tiles = tiles(area, 8, TileOrder::Hilbert);
+        for pair in tiles.windows(2) {
+            let distance = (pair[0].x - pair[1].x).abs() + (pair[0].y - pair[1].y).abs();
+            assert_eq!(distance, 8);
+        }
+    }
+
+    #[test]
+    fn test_tile_intersect() {
+        let tile = Tile::new(0, 0, 10, 10).intersect(Tile::new(5, 8, 10, 10));
+        assert_eq!(tile, Tile::new(5, 8, 5, 2));
+    }
+}
@@ -1,302 +1,242 @@
-use std::fs::File;
-use std::io::{BufWriter, Write};
-use std::path::Path;
-use std::str::FromStr;
-
-use anyhow::{anyhow, Result};
-use flate2::write::ZlibEncoder;
-use flate2::Compression;
-use rand::rngs::SmallRng;
-use rand::{Rng, SeedableRng};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use anyhow::Result;
 use rayon::prelude::*;
 
-use crate::camera::Camera;
-use crate::config::RaytracerConfig;
+use crate::atmosphere::Interaction;
+use crate::camera::{Camera, Eye, StereoLayout};
+use crate::config::{PreparedConfig, RaytracerConfig};
+use crate::image::Image;
 use crate::material::Scatterable;
-use crate::object::{Hittable, Object};
-use crate::png::Chunk;
-use crate::png::ChunkType;
+use crate::object::{HitRecord, Hittable};
+use crate::primitive::random;
 use crate::ray::Ray;
-use crate::vec3::{Color, Vec3};
+use crate::tile::{self, Tile};
+use crate::vec3::Color;
 
 pub struct Tracer {
     camera: Camera,
     config: RaytracerConfig,
-    current_x: i32,
-    current_y_forward: i32,
-    current_y_backward: i32,
     max_u: f32,
     max_v: f32,
 }
 
 impl Tracer {
-    pub fn new(camera: Camera, config: RaytracerConfig) -> Self {
+    pub fn new(config: PreparedConfig) -> Self {
+        let config = config.into_inner();
+        let aspect_ratio = config.image_width as f32 / config.image_height as f32;
+        let camera = Camera::from_config(&config.camera, aspect_ratio);
         let max_u = (config.image_width - 1) as f32;
         let max_v = (config.image_height - 1) as f32;
         Self {
             camera,
-            current_x: 0,
-            current_y_forward: config.image_height - 1,
-            current_y_backward: 0,
             config,
             max_u,
             max_v,
         }
     }
 
-    pub fn save(self, filepath: &Path) -> Result<()> {
-        let file = File::create(filepath)?;
-
-        if let Some(ext) = filepath.extension().and_then(|s| s.to_str()) {
-            match ext {
-                "ppm" => self.save_as_ppm(file)?,
-                "bmp" => self.save_as_bmp(file)?,
-                "png" => self.save_as_png(file)?,
-                _ => return Err(anyhow!("Unsupported filetype!")),
-            }
-        } else {
-            println!("No filetype given, defaulting to ppm...");
-            self.save_as_ppm(file)?;
+    /// The part of the frame that will be rendered
+    pub fn region(&self) -> Tile {
+        let frame = Tile::new(0, 0, self.config.image_width, self.config.image_height);
+        match self.config.region {
+            Some(region) => region.tile().intersect(frame),
+            None => frame,
         }
+    }
 
-        Ok(())
+    /// The tiles covering the region, in the configured order
+    pub fn tiles(&self) -> Vec<Tile> {
+        tile::tiles(self.region(), self.config.tile_size, self.config.tile_order)
     }
 
-    fn save_as_ppm<W: Write>(self, writable: W) -> Result<()> {
-        let mut file = BufWriter::new(writable);
+    /// Render the scene tile by tile, returning the full frame or only the region if cropped
+    pub fn render(&self) -> Image {
+        self.render_with(&self.camera)
+    }
 
-        // Write header
-        writeln!(file, "P3")?;
-        writeln!(
-            file,
-            "{} {}",
-            self.config.image_width, self.config.image_height
-        )?;
-        writeln!(file, "{}", 255)?; // Maximum color
+    /// Render the scene as seen by both eyes of the stereo camera
+    pub fn render_stereo(&self) -> (Image, Image) {
+        let aspect_ratio = self.config.image_width as f32 / self.config.image_height as f32;
+        let left = Camera::for_eye(&self.config.camera, aspect_ratio, Eye::Left);
+        let right = Camera::for_eye(&self.config.camera, aspect_ratio, Eye::Right);
+        (self.render_with(&left), self.render_with(&right))
+    }
 
-        // Write pixels
-        let samples_per_pixel: i32 = self.config.samples_per_pixel;
-        for mut pixel in self {
-            pixel.correct_color(1. / samples_per_pixel as f32);
-            writeln!(file, "{} {} {}", pixel.r(), pixel.g(), pixel.b())?;
-        }
+    /// Render the scene through the given camera instead of the tracer's own
+    pub fn render_with(&self, camera: &Camera) -> Image {
+        let tiles = self.tiles();
+        let total_tiles = tiles.len();
+        let tiles_done = AtomicUsize::new(0);
+
+        // Bridge the tiles so they are picked up in the requested order
+        let rendered: Vec<(Tile, Vec<Color>)> = tiles
+            .into_iter()
+            .par_bridge()
+            .map(|tile| {
+                let pixels = self.render_tile_with(camera, tile);
+                let done = tiles_done.fetch_add(1, Ordering::Relaxed) + 1;
+                eprint!("\rTiles remaining: {}", total_tiles - done);
+                (tile, pixels)
+            })
+            .collect();
+        eprintln!();
 
-        Ok(())
+        self.assemble(rendered)
     }
 
-    fn save_as_bmp<W: Write>(self, writable: W) -> Result<()> {
-        let mut file = BufWriter::new(writable);
-
-        let mut bitmap_file_header: [u8; 14] = [
-            0x42, 0x4D, // BM marker
-            0xFF, 0xFF, 0xFF, 0xFF, // BMP file size in bytes
-            0x00, 0x00, 0x00, 0x00, // Reserved
-            0x36, 0x00, 0x00, 0x00, // Byte offset of the pixel array
-        ];
-        let mut dib_header: [u8; 40] = [
-            0x28, 0x00, 0x00, 0x00, // DIB header size in bytes
-            0xFF, 0xFF, 0xFF, 0xFF, // Bitmap pixel width
-            0xFF, 0xFF, 0xFF, 0xFF, // Bitmap pixel height
-            0x01, 0x00, // Number of color planes
-            0xFF, 0xFF, // Number of bits per pixel
-            0x00, 0x00, 0x00, 0x00, // BI_RGB, no pixel array compression
-            0xFF, 0xFF, 0xFF, 0xFF, // Size of raw bitmap data including padding
-            0x00, 0x00, 0x00, 0x00, // Print resolution (vertical)
-            0x00, 0x00, 0x00, 0x00, // Print resolution (horizontal)
-            0x00, 0x00, 0x00, 0x00, // Number of colors in the palette
-            0x00, 0x00, 0x00, 0x00, // Important colors (0 means all important)
-        ];
-
-        let bits_per_pixel: i32 = 24;
-        let row_size =
-            (((bits_per_pixel * self.config.image_width) as f32 / 32.).ceil() * 4.) as usize;
-        let data_size = row_size * self.config.image_height as usize;
-
-        dib_header[4..=7].copy_from_slice(&(self.config.image_width as u32).to_le_bytes());
-        dib_header[8..=11].copy_from_slice(&(self.config.image_height as u32).to_le_bytes());
-        dib_header[14..=15].copy_from_slice(&(bits_per_pixel as u16).to_le_bytes());
-        dib_header[20..=23].copy_from_slice(&(data_size as u32).to_le_bytes());
-
-        let total_size = data_size + dib_header.len() + bitmap_file_header.len();
-        bitmap_file_header[2..=5].copy_from_slice(&(total_size as u32).to_le_bytes());
-
-        file.write_all(&bitmap_file_header)?;
-        file.write_all(&dib_header)?;
-
-        let padding_required = (4 - self.config.image_width % 4) % 4;
-        let samples_per_pixel: i32 = self.config.samples_per_pixel;
-        let width = self.config.image_width as usize;
-        for (idx, mut pixel) in self.rev().enumerate() {
-            pixel.correct_color(1. / samples_per_pixel as f32);
-            file.write_all(&[pixel.b(), pixel.g(), pixel.r()])?;
-
-            // Write padding every row
-            if idx % width == 0 {
-                for _ in 0..padding_required {
-                    file.write_all(&[0_u8])?;
-                }
-            }
+    /// Place rendered tiles into the full frame, or into an image of the region if cropped
+    pub fn assemble<I: IntoIterator<Item = (Tile, Vec<Color>)>>(&self, rendered: I) -> Image {
+        let region = self.region();
+        let cropped = self.config.region.is_some_and(|region| region.crop);
+        let (mut image, dx, dy) = if cropped {
+            (Image::new(region.width, region.height), region.x, region.y)
+        } else {
+            let image = Image::new(self.config.image_width, self.config.image_height);
+            (image, 0, 0)
+        };
+        for (tile, pixels) in rendered {
+            image.write_tile(tile, &pixels, dx, dy);
         }
+        image
+    }
 
-        Ok(())
+    /// Render the pixels of a tile row by row from its top left, averaged over all samples.
+    /// With a seed set, a tile renders the same no matter which thread or worker picks it up.
+    pub fn render_tile(&self, tile: Tile) -> Vec<Color> {
+        self.render_tile_with(&self.camera, tile)
     }
 
-    // PNG Format taken from: http://www.libpng.org/pub/png/spec/1.2/PNG-Contents.html
-    // PNG Chunk/ChunkType scaffold + tests taken from https://picklenerd.github.io/pngme_book/
-    fn save_as_png<W: Write>(self, writable: W) -> Result<()> {
-        let mut file = BufWriter::new(writable);
-
-        // Write PNG Header
-        file.write_all(&[137, 80, 78, 71, 13, 10, 26, 10])?;
-
-        // Write IHDR chunk
-        let mut ihdr_data: [u8; 13] = [
-            0xFF, 0xFF, 0xFF, 0xFF, // Pixel width
-            0xFF, 0xFF, 0xFF, 0xFF, // Pixel height
-            0x8,  // Bit depth (number of bits per sample, NOT per pixel)
-            0x2,  // Color type
-            0x0,  // Compression method
-            0x0,  // Filter method
-            0x0,  // Interlace method
-        ];
-        ihdr_data[0..=3].copy_from_slice(&(self.config.image_width as u32).to_be_bytes());
-        ihdr_data[4..=7].copy_from_slice(&(self.config.image_height as u32).to_be_bytes());
-
-        file.write_all(
-            &Chunk::new(ChunkType::from_str("IHDR").unwrap(), ihdr_data.to_vec()).as_bytes(),
-        )?;
-
-        let mut e = ZlibEncoder::new(Vec::new(), Compression::default());
+    fn render_tile_with(&self, camera: &Camera, tile: Tile) -> Vec<Color> {
+        match self.config.seed {
+            Some(seed) => random::seed(seed ^ ((tile.x as u64) << 32 | tile.y as u64)),
+            None => random::seed_from_entropy(),
+        }
 
         let samples_per_pixel: i32 = self.config.samples_per_pixel;
-        let data: Vec<u8> = (0..self.config.image_height)
-            .into_par_iter()
-            .rev()
-            .flat_map(|y| {
-                (0..self.config.image_width)
-                    .into_par_iter()
-                    .map(move |x| (x, y))
-            })
-            .map_init(SmallRng::from_entropy, |rng, (x, y)| {
+        let mut pixels = Vec::with_capacity(tile.area());
+
+        for y in tile.y..tile.y + tile.height {
+            // Image rows go top to bottom, the viewport goes bottom to top
+            let _j = (self.config.image_height - 1 - y) as f32;
+            for x in tile.x..tile.x + tile.width {
                 let _i = x as f32;
-                let _j = y as f32;
                 let mut pixel = Color::new(0., 0., 0.);
 
                 for _ in 0..samples_per_pixel {
-                    let u = (_i + rng.gen::<f32>()) / self.max_u;
-                    let v = (_j + rng.gen::<f32>()) / self.max_v;
-                    let ray = (&self.camera).get_ray(u, v);
-                    pixel += ray_color(ray, &self.config.world, self.config.max_depth);
+                    let u = (_i + random::gen::<f32>()) / self.max_u;
+                    let v = (_j + random::gen::<f32>()) / self.max_v;
+                    let ray = camera.get_ray(u, v);
+                    pixel += ray_color(ray, &self.config);
                 }
 
-                // Write filter-type byte every row
-                pixel.correct_color(1. / samples_per_pixel as f32);
-                [pixel.r(), pixel.g(), pixel.b()]
-            })
-            .flatten()
-            .collect();
-
-        let width = self.config.image_width as usize;
-        for (index, pixel) in data.chunks(3).enumerate() {
-            if index % width == 0 {
-                e.write_all(&[0])?;
+                pixels.push(pixel / samples_per_pixel as f32);
             }
-            e.write_all(pixel)?;
         }
+        pixels
+    }
 
-        let compressed = e.finish()?;
-        file.write_all(&Chunk::new(ChunkType::from_str("IDAT").unwrap(), compressed).as_bytes())?;
-
-        // Write IEND chunk
-        file.write_all(&Chunk::new(ChunkType::from_str("IEND").unwrap(), [].to_vec()).as_bytes())?;
-
+    /// Move the camera to where its keyframes put it at the frame, focusing it again
+    pub fn set_frame(&mut self, frame: f32) -> Result<()> {
+        self.config.camera = self.config.camera.at_frame(frame);
+        self.config.autofocus()?;
+        let aspect_ratio = self.config.image_width as f32 / self.config.image_height as f32;
+        self.camera = Camera::from_config(&self.config.camera, aspect_ratio);
         Ok(())
     }
-}
 
-impl Iterator for Tracer {
-    type Item = Vec3;
+    pub fn config(&self) -> &RaytracerConfig {
+        &self.config
+    }
 
-    fn next(&mut self) -> Option<Self::Item> {
-        // No more lines
-        if self.current_y_forward == 0 && self.current_x == self.config.image_width {
-            return None;
-        }
-        // Move to next line
-        if self.current_x == self.config.image_width {
-            eprint!("\rScanlines remaining: {}", self.current_y_forward);
-            self.current_y_forward -= 1;
-            self.current_x = 0;
+    /// The files `save` writes for the path, one per eye for stereo cameras that save the
+    /// eyes separately
+    pub fn saved_paths(&self, filepath: &Path) -> Vec<PathBuf> {
+        match self.config.camera.stereo {
+            Some(stereo) if stereo.layout == StereoLayout::Separate => {
+                vec![eye_path(filepath, "left"), eye_path(filepath, "right")]
+            }
+            _ => vec![filepath.to_path_buf()],
         }
+    }
 
-        let _j = self.current_y_forward as f32;
-        let _i = self.current_x as f32;
-        let mut pixel = Color::new(0., 0., 0.);
-
-        let mut rng = SmallRng::from_entropy();
-        for _ in 0..self.config.samples_per_pixel {
-            let u = (_i + rng.gen::<f32>()) / self.max_u;
-            let v = (_j + rng.gen::<f32>()) / self.max_v;
-            let ray = (&self.camera).get_ray(u, v);
-            pixel += ray_color(ray, &self.config.world, self.config.max_depth);
+    /// Render and save the scene, with stereo cameras saving both eyes in their layout
+    pub fn save(&self, filepath: &Path) -> Result<()> {
+        let layout = match self.config.camera.stereo {
+            Some(stereo) => stereo.layout,
+            None => return self.render().save(filepath),
+        };
+
+        let (left, right) = self.render_stereo();
+        match layout {
+            StereoLayout::Separate => {
+                left.save(&eye_path(filepath, "left"))?;
+                right.save(&eye_path(filepath, "right"))
+            }
+            StereoLayout::TopBottom => left.above(&right).save(filepath),
+            StereoLayout::SideBySide => left.beside(&right).save(filepath),
         }
-
-        self.current_x += 1;
-
-        Some(pixel)
     }
 }
 
-impl DoubleEndedIterator for Tracer {
-    fn next_back(&mut self) -> Option<Self::Item> {
-        // No more lines
-        if self.current_y_backward == self.config.image_height + 1
-            && self.current_x == self.config.image_width
-        {
-            return None;
-        }
-
-        // Move to next line
-        if self.current_x == self.config.image_width {
-            eprint!(
-                "\rScanlines remaining: {}",
-                self.config.image_height - self.current_y_backward
-            );
-            self.current_y_backward += 1;
-            self.current_x = 0;
-        }
-
-        let mut rng = SmallRng::from_entropy();
-        let _j = self.current_y_backward as f32;
-        let _i = self.current_x as f32;
-        let mut pixel = Color::new(0., 0., 0.);
-
-        for _ in 0..self.config.samples_per_pixel {
-            let u = (_i + rng.gen::<f32>()) / self.max_u;
-            let v = (_j + rng.gen::<f32>()) / self.max_v;
-            let ray = (&self.camera).get_ray(u, v);
-            pixel += ray_color(ray, &self.config.world, self.config.max_depth);
-        }
-
-        self.current_x += 1;
-
-        Some(pixel)
-    }
+/// The path with a suffix added to the file name, e.g. `render_left.png`
+fn eye_path(filepath: &Path, eye: &str) -> PathBuf {
+    let stem = filepath
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let name = match filepath.extension() {
+        Some(ext) => format!("{}_{}.{}", stem, eye, ext.to_string_lossy()),
+        None => format!("{}_{}", stem, eye),
+    };
+    filepath.with_file_name(name)
 }
 
-fn ray_color(ray: Ray, world: &[Object], depth: i32) -> Color {
+fn ray_color(ray: Ray, config: &RaytracerConfig) -> Color {
+    let world = &config.world;
+    let atmosphere = config.atmosphere.as_ref();
     let mut result = Color::new(0., 0., 0.);
     let mut global_attenuation = Color::new(1., 1., 1.);
 
+    // Rays without a direction, e.g. outside a fisheye's image circle, see nothing
+    if ray.direction().is_near_zero() {
+        return result;
+    }
     let mut current_ray = ray;
 
     let min_y = Color::new(1., 1., 1.); // White
     let max_y = Color::new(0.5, 0.7, 1.); // Blue
 
-    for _ in 0..depth {
-        if let Some(record) = world.hit(current_ray, 0.001, f32::MAX) {
-            if let Some(res) = record.material().scatter(&current_ray, &record) {
-                global_attenuation *= res.attenuation;
+    for _ in 0..config.max_depth {
+        // Media in the world may scatter the ray before it reaches the surface it hits
+        let hit = world.hit(current_ray, 0.001, f32::MAX);
+        let t_surface = hit.map_or(f32::MAX, |record| record.t());
+        let hit = world.collide(current_ray, 0.001, t_surface).or(hit);
+
+        // The atmosphere may scatter the ray before it reaches the hit or the background
+        if let Some(atmosphere) = atmosphere {
+            let t_end = hit.map_or(f32::INFINITY, |record| record.t());
+            match atmosphere.interact(current_ray, t_end) {
+                Interaction::Scatter { ray, weight } => {
+                    global_attenuation *= weight;
+                    current_ray = ray;
+                    continue;
+                }
+                Interaction::Pass { weight } => global_attenuation *= weight,
+            }
+        }
+
+        if let Some(record) = hit {
+            let record = record.material().shade(record);
+            result += record.emitted() * global_attenuation;
+            result += direct_light(&current_ray, &record, config) * global_attenuation;
+            let scattered = record
+                .material()
+                .scatter(&current_ray, &record)
+                .filter(|res| record.agrees_on_side(res.ray.direction()));
+            if let Some(res) = scattered {
+                global_attenuation *= res.attenuation * record.tint();
                 current_ray = res.ray;
             } else {
                 break;
@@ -312,3 +252,95 @@ fn ray_color(ray: Ray, world: &[Object], depth: i32) -> Color {
     }
     result
 }
+
+/// The light reaching the hit straight from the scene's lights and scattered along the ray
+fn direct_light(ray: &Ray, record: &HitRecord, config: &RaytracerConfig) -> Color {
+    let mut total = Color::new(0., 0., 0.);
+    for light in &config.lights {
+        let illumination = match light.illuminate(record.point()) {
+            Some(illumination) => illumination,
+            None => continue,
+        };
+        let reflectance = match record
+            .material()
+            .reflectance(ray, record, illumination.direction)
+        {
+            Some(reflectance) => reflectance,
+            None => return total,
+        };
+        if !record.agrees_on_side(illumination.direction) {
+            continue;
+        }
+        let shadow = Ray::new(record.point(), illumination.direction).with_time(ray.time());
+        if config
+            .world
+            .hit(shadow, 0.001, illumination.distance * (1. - 1e-4))
+            .is_some()
+        {
+            continue;
+        }
+        let media = config
+            .world
+            .transmittance(shadow, 0.001, illumination.distance);
+        let transmittance = config
+            .atmosphere
+            .as_ref()
+            .map_or(Color::new(1., 1., 1.), |a| {
+                a.transmittance(shadow, illumination.distance)
+            })
+            * media;
+        total += illumination.color * reflectance * transmittance * record.tint();
+    }
+    total
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vec3::Vec3;
+
+    #[test]
+    fn test_misses_reach_background_through_atmosphere() {
+        random::seed(7);
+        // Haze that only scatters, without falloff, around an empty world
+        let config: RaytracerConfig = serde_json::from_value(serde_json::json!({
+            "look_from": {"x": 0, "y": 0, "z": 0},
+            "look_to": {"x": 0, "y": 0, "z": -1},
+            "atmosphere": {"scattering": {"x": 0.01, "y": 0.01, "z": 0.01}, "max_distance": 100},
+            "world": [],
+        }))
+        .unwrap();
+        let ray = Ray::new(Vec3::new(0., 0., 0.), Vec3::new(0., 1., 0.));
+        let samples = 1000;
+        let average = (0..samples)
+            .map(|_| ray_color(ray, &config))
+            .fold(Color::new(0., 0., 0.), |sum, color| sum + color)
+            / samples as f32;
+        // Light is only moved around, so the sky stays about as bright as the background
+        for channel in [average.x(), average.y(), average.z()] {
+            assert!(channel > 0.6 && channel <= 1., "{:?}", average);
+        }
+    }
+
+    #[test]
+    fn test_fisheye_corners_are_black() {
+        let config: RaytracerConfig = serde_json::from_value(serde_json::json!({
+            "image_width": 8,
+            "image_height": 8,
+            "samples_per_pixel": 4,
+            "look_from": {"x": 0, "y": 0, "z": 0},
+            "look_to": {"x": 0, "y": 0, "z": -1},
+            "projection": {"Fisheye": {"fov": 180}},
+            "world": [],
+        }))
+        .unwrap();
+        let tracer = Tracer::new(config.prepare(Path::new("")).unwrap());
+        // The corner lies outside the image circle, while the center sees the background
+        assert_eq!(
+            tracer.render_tile(Tile::new(0, 0, 1, 1)),
+            [Color::new(0., 0., 0.)]
+        );
+        let center = tracer.render_tile(Tile::new(4, 4, 1, 1))[0];
+        assert!(center.x() > 0.);
+    }
+}
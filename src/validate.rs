@@ -0,0 +1,407 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+
+use crate::camera::FocusTarget;
+use crate::config::RaytracerConfig;
+use crate::material::Material;
+use crate::tile::Tile;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Severity {
+    /// The scene cannot be rendered
+    Error,
+    /// The scene renders, but probably not as intended
+    Warning,
+}
+
+/// A problem found in a scene, located by the dot-separated path of the offending field
+#[derive(Debug, PartialEq, Clone)]
+pub struct Problem {
+    pub severity: Severity,
+    pub path: String,
+    pub message: String,
+    pub line: Option<usize>,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        write!(f, "{}: {}", severity, self.path)?;
+        if let Some(line) = self.line {
+            write!(f, " (line {})", line)?;
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+/// Collects problems while walking the scene
+#[derive(Debug, Default)]
+pub struct Problems {
+    problems: Vec<Problem>,
+    materials: BTreeMap<String, Material>,
+}
+
+impl Problems {
+    /// Collect problems of a scene with the given library of named materials
+    pub fn new<I: IntoIterator<Item = (String, Material)>>(materials: I) -> Self {
+        Self {
+            problems: Vec::new(),
+            materials: materials.into_iter().collect(),
+        }
+    }
+
+    /// Whether the scene has a material with this name
+    pub fn knows_material(&self, name: &str) -> bool {
+        self.materials.contains_key(name)
+    }
+
+    /// The material of the scene with this name
+    pub fn material(&self, name: &str) -> Option<&Material> {
+        self.materials.get(name)
+    }
+
+    pub fn error<S: Into<String>>(&mut self, path: &str, message: S) {
+        self.push(Severity::Error, path, message.into());
+    }
+
+    pub fn warning<S: Into<String>>(&mut self, path: &str, message: S) {
+        self.push(Severity::Warning, path, message.into());
+    }
+
+    fn push(&mut self, severity: Severity, path: &str, message: String) {
+        self.problems.push(Problem {
+            severity,
+            path: path.to_string(),
+            message,
+            line: None,
+        });
+    }
+
+    pub fn into_vec(self) -> Vec<Problem> {
+        self.problems
+    }
+}
+
+/// Join a field onto a dot-separated path
+pub fn join(path: &str, field: &str) -> String {
+    if path.is_empty() {
+        field.to_string()
+    } else {
+        format!("{}.{}", path, field)
+    }
+}
+
+pub trait Validate {
+    /// Report the problems of this part of the scene, found at `path`
+    fn validate(&self, path: &str, problems: &mut Problems);
+}
+
+impl Validate for RaytracerConfig {
+    fn validate(&self, path: &str, problems: &mut Problems) {
+        let field = |name: &str| join(path, name);
+
+        if self.image_width <= 0 {
+            problems.error(&field("image_width"), "The image width must be positive");
+        }
+        if self.image_height <= 0 {
+            problems.error(&field("image_height"), "The image height must be positive");
+        }
+        if self.samples_per_pixel <= 0 {
+            problems.error(
+                &field("samples_per_pixel"),
+                "At least one sample per pixel is required",
+            );
+        }
+        if self.max_depth <= 0 {
+            problems.error(&field("max_depth"), "The maximum depth must be positive");
+        }
+        self.camera.validate(path, problems);
+
+        if self.tile_size <= 0 {
+            problems.error(&field("tile_size"), "The tile size must be positive");
+        }
+        if let Some(region) = self.region {
+            let frame = Tile::new(0, 0, self.image_width, self.image_height);
+            if region.width <= 0 || region.height <= 0 {
+                problems.error(&field("region"), "The region must have a positive size");
+            } else if region.tile().intersect(frame).area() == 0 {
+                problems.error(&field("region"), "The region lies outside of the image");
+            } else if region.tile().intersect(frame) != region.tile() {
+                problems.warning(
+                    &field("region"),
+                    "The region extends past the image and will be clipped",
+                );
+            }
+        }
+        if let Some(frames) = self.frames {
+            if frames.end < frames.start {
+                problems.error(&field("frames"), "The last frame comes before the first");
+            }
+        }
+        if self.threads == Some(0) {
+            problems.error(&field("threads"), "At least one thread is required");
+        }
+
+        for (name, material) in &self.materials {
+            material.validate(&join(&field("materials"), name), problems);
+        }
+        if let Some(atmosphere) = &self.atmosphere {
+            atmosphere.validate(&field("atmosphere"), problems);
+        }
+        for (index, light) in self.lights.iter().enumerate() {
+            light.validate(&join(&field("lights"), &index.to_string()), problems);
+        }
+        for (index, import) in self.gltf.iter().enumerate() {
+            import.validate(&join(&field("gltf"), &index.to_string()), problems);
+        }
+        if self.world.is_empty() && self.gltf.is_empty() {
+            problems.warning(&field("world"), "The world is empty");
+        }
+        let mut names = BTreeSet::new();
+        for (index, object) in self.world.iter().enumerate() {
+            let path = join(&field("world"), &index.to_string());
+            object.validate(&path, problems);
+            for name in object.names() {
+                if !names.insert(name) {
+                    problems.error(&path, format!("The name '{}' is used more than once", name));
+                }
+            }
+        }
+
+        match &self.camera.focus_on {
+            Some(FocusTarget::Pixel { x, y })
+                if !(0..self.image_width).contains(x) || !(0..self.image_height).contains(y) =>
+            {
+                problems.error(
+                    &field("focus_on"),
+                    "The focus pixel lies outside of the image",
+                );
+            }
+            Some(FocusTarget::Object(name)) if !names.contains(name.as_str()) => {
+                problems.error(
+                    &field("focus_on"),
+                    format!("There is no object named '{}' to focus on", name),
+                );
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Validate the scene, locating each problem in the source it was loaded from
+pub fn validate(config: &RaytracerConfig, source: &str) -> Vec<Problem> {
+    let materials = config.materials.iter();
+    let mut problems = Problems::new(materials.map(|(name, m)| (name.clone(), m.clone())));
+    config.validate("", &mut problems);
+
+    let lines = value_lines(source);
+    let mut problems = problems.into_vec();
+    for problem in &mut problems {
+        // Fields left to their defaults are not in the source, so fall back to their parent
+        let mut path = problem.path.as_str();
+        problem.line = loop {
+            if let Some(&line) = lines.get(path) {
+                break Some(line);
+            }
+            match path.rfind('.') {
+                Some(index) => path = &path[..index],
+                None => break None,
+            }
+        };
+    }
+    problems
+}
+
+/// Map the dot-separated path of every value in the JSON source to the line it starts on
+pub fn value_lines(source: &str) -> HashMap<String, usize> {
+    let mut locator = Locator {
+        bytes: source.as_bytes(),
+        position: 0,
+        line: 1,
+        lines: HashMap::new(),
+    };
+    locator.value(String::new());
+    locator.lines
+}
+
+struct Locator<'a> {
+    bytes: &'a [u8],
+    position: usize,
+    line: usize,
+    lines: HashMap<String, usize>,
+}
+
+impl<'a> Locator<'a> {
+    fn peek(&self) -> Option<u8> {
+        self.bytes.get(self.position).copied()
+    }
+
+    fn bump(&mut self) -> Option<u8> {
+        let byte = self.peek()?;
+        if byte == b'\n' {
+            self.line += 1;
+        }
+        self.position += 1;
+        Some(byte)
+    }
+
+    fn skip_whitespace(&mut self) {
+        while matches!(self.peek(), Some(b' ' | b'\t' | b'\n' | b'\r')) {
+            self.bump();
+        }
+    }
+
+    fn value(&mut self, path: String) {
+        self.skip_whitespace();
+        if !path.is_empty() {
+            self.lines.entry(path.clone()).or_insert(self.line);
+        }
+
+        match self.peek() {
+            Some(b'{') => {
+                self.bump();
+                loop {
+                    self.skip_whitespace();
+                    match self.peek() {
+                        Some(b'"') => {
+                            let key = self.string();
+                            self.skip_whitespace();
+                            if self.peek() == Some(b':') {
+                                self.bump();
+                            }
+                            self.value(join(&path, &key));
+                        }
+                        Some(b',') => {
+                            self.bump();
+                        }
+                        Some(b'}') | None => {
+                            self.bump();
+                            break;
+                        }
+                        Some(_) => {
+                            // Not valid JSON, so nothing more to locate
+                            self.position = self.bytes.len();
+                        }
+                    }
+                }
+            }
+            Some(b'[') => {
+                self.bump();
+                let mut index = 0;
+                loop {
+                    self.skip_whitespace();
+                    match self.peek() {
+                        Some(b',') => {
+                            self.bump();
+                        }
+                        Some(b']') | None => {
+                            self.bump();
+                            break;
+                        }
+                        Some(_) => {
+                            self.value(join(&path, &index.to_string()));
+                            index += 1;
+                        }
+                    }
+                }
+            }
+            Some(b'"') => {
+                self.string();
+            }
+            Some(_) => {
+                // Numbers and literals
+                while !matches!(
+                    self.peek(),
+                    None | Some(b',' | b'}' | b']' | b' ' | b'\t' | b'\n' | b'\r')
+                ) {
+                    self.bump();
+                }
+            }
+            None => {}
+        }
+    }
+
+    fn string(&mut self) -> String {
+        let mut bytes = Vec::new();
+        self.bump(); // Opening quote
+        while let Some(byte) = self.bump() {
+            match byte {
+                b'"' => break,
+                b'\\' => {
+                    if let Some(escaped) = self.bump() {
+                        bytes.push(escaped);
+                    }
+                }
+                _ => bytes.push(byte),
+            }
+        }
+        String::from_utf8_lossy(&bytes).into_owned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCENE: &str = r#"{
+  "image_width": 0,
+  "look_from": {"x": 0, "y": 0, "z": 0},
+  "look_to": {"x": 0, "y": 0, "z": 0},
+  "world": [
+    {
+      "Sphere": {
+        "center": {"x": 0, "y": 0, "z": -1},
+        "radius": -0.5,
+        "material": {"Dielectric": {"refractive_index": 1.5}}
+      }
+    },
+    {
+      "Sphere": {
+        "center": {"x": 0, "y": 0, "z": -3},
+        "radius": -1,
+        "material": {"Lambertian": {"albedo": {"x": 0.5, "y": 0.5, "z": 0.5}}}
+      }
+    },
+    {
+      "Sphere": {
+        "center": {"x": 0, "y": 0, "z": -5},
+        "radius": -0.5,
+        "material": "glass"
+      }
+    }
+  ],
+  "materials": {"glass": {"Dielectric": {"refractive_index": 1.5}}}
+}"#;
+
+    #[test]
+    fn test_value_lines() {
+        let lines = value_lines(SCENE);
+        assert_eq!(lines["image_width"], 2);
+        assert_eq!(lines["look_to.z"], 4);
+        assert_eq!(lines["world.0"], 6);
+        assert_eq!(lines["world.0.Sphere.radius"], 9);
+    }
+
+    #[test]
+    fn test_validate_reports_all_problems() {
+        let config: RaytracerConfig = serde_json::from_str(SCENE).unwrap();
+        let problems = validate(&config, SCENE);
+        let located: Vec<_> = problems
+            .iter()
+            .map(|problem| (problem.severity, problem.path.as_str(), problem.line))
+            .collect();
+
+        assert_eq!(
+            located,
+            [
+                (Severity::Error, "image_width", Some(2)),
+                (Severity::Error, "look_to", Some(4)),
+                // Only the inside out sphere that is not a dielectric shell
+                (Severity::Warning, "world.1.Sphere.radius", Some(16)),
+            ]
+        );
+    }
+}